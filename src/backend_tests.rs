@@ -0,0 +1,507 @@
+use crate::program::errors::RuntimeError;
+use crate::statements::statement::Statement;
+use crate::statements::stmt_visitor::StatementInterpreter;
+use crate::vm::vm::Vm;
+
+// Programs both backends run, each written once with what it has to leave behind. A program lists its
+// globals as print shows them, a failure the report of the error it stops with.
+
+struct Program {
+    name: &'static str,
+    source: &'static str,
+    globals: &'static [(&'static str, &'static str)],
+}
+
+struct Failure {
+    name: &'static str,
+    source: &'static str,
+    report: &'static str,
+}
+
+const PROGRAMS: &[Program] = &[
+    Program {
+        name: "else if chain",
+        source: "var x = 2;
+        var r = 0;
+        if (x == 1) r = 1;
+        else if (x == 2) r = 2;
+        else r = 3;",
+        globals: &[("r", "2")],
+    },
+    Program {
+        name: "for loop scope",
+        source: "var i = 100;
+        var sum = 0;
+        for (var i = 0; i < 3; i = i + 1) {
+            fun current() {
+                return i;
+            }
+            sum = sum + current();
+        }",
+        globals: &[("i", "100"), ("sum", "3")],
+    },
+    Program {
+        name: "for without condition",
+        source: "fun find() {
+            for (var i = 0;; i = i + 1) {
+                if (i == 5) return i;
+            }
+        }
+        var r = find();",
+        globals: &[("r", "5")],
+    },
+    Program {
+        name: "assign nil and functions",
+        source: "var x = 1;
+        var y = x;
+        x = nil;
+        y = 3;
+        var z;
+        z = x;
+        fun one() { return 1; }
+        var f;
+        f = one;
+        var r = f();",
+        globals: &[("x", "nil"), ("y", "3"), ("z", "nil"), ("r", "1"), ("one", "<fn one>")],
+    },
+    Program {
+        name: "compound assignment",
+        source: "var x = 5;
+        x += 3;
+        x *= 2;
+        x %= 5;
+        var a = 1;
+        var post = a++;
+        var pre = ++a;
+        class Counter {}
+        var counter = Counter();
+        counter.count = 10;
+        var calls = 0;
+        fun obj() {
+            calls += 1;
+            return counter;
+        }
+        obj().count += 5;
+        var old = obj().count--;
+        var count = counter.count;
+        var xs = [1, 2];
+        xs[0] += 5;
+        var element = xs[1]++;",
+        globals: &[("x", "1"), ("post", "1"), ("pre", "3"), ("a", "3"), ("calls", "2"), ("old", "15"),
+            ("count", "14"), ("xs", "[6, 3]"), ("element", "2")],
+    },
+    Program {
+        name: "conditional short circuit",
+        source: "var calls = 0;
+        fun touch() {
+            calls += 1;
+            return \"touched\";
+        }
+        var x = 3;
+        var size = x > 2 ? \"big\" : touch();
+        var sign = x < 0 ? \"negative\" : x == 0 ? \"zero\" : \"positive\";",
+        globals: &[("size", "big"), ("sign", "positive"), ("calls", "0")],
+    },
+    Program {
+        name: "closures and upvalues",
+        source: "fun makeCounter() {
+            var count = 0;
+            return fun () {
+                count += 1;
+                return count;
+            };
+        }
+        fun apply(f, x) {
+            return f(x);
+        }
+        var counter = makeCounter();
+        counter();
+        var counted = counter();
+        var applied = apply(fun (n) { return n * 10; }, 4);
+        var fact = fun (n) { return n < 2 ? 1 : n * fact(n - 1); };
+        var factorial = fact(5);
+        fun pair() {
+            var shared = 0;
+            fun inc() { shared += 1; }
+            fun get() { return shared; }
+            inc();
+            inc();
+            return get;
+        }
+        var sharedCount = pair()();",
+        globals: &[("counted", "2"), ("applied", "40"), ("factorial", "120"), ("sharedCount", "2")],
+    },
+    Program {
+        name: "local slots",
+        source: "var x = 1;
+        var shadowed = 0;
+        var fact = 0;
+        var parity = 0;
+        {
+            var x = x + 1;
+            shadowed = x;
+            var go = fun (n) { return n < 2 ? 1 : n * go(n - 1); };
+            fact = go(5);
+            fun isEven(n) { return n == 0 ? true : isOdd(n - 1); }
+            fun isOdd(n) { return n == 0 ? false : isEven(n - 1); }
+            parity = isEven(10);
+        }
+        fun count() {
+            var running = true;
+            var steps = 0;
+            while (running) {
+                steps++;
+                if (steps == 3) running = false;
+            }
+            return steps;
+        }
+        var steps = count();",
+        globals: &[("x", "1"), ("shadowed", "2"), ("fact", "120"), ("parity", "true"), ("steps", "3")],
+    },
+    Program {
+        name: "lists",
+        source: "var xs = [1, 2, 3];
+        xs[1] = 20;
+        xs[0] += 5;
+        xs.push(4);
+        var popped = xs.pop();
+        xs.insert(0, 0);
+        var removed = xs.remove(3);
+        var alias = xs;
+        alias.push(7);
+        var length = xs.len();
+        var part = xs.slice(1, 3);
+        var found = xs.contains(20);",
+        globals: &[("xs", "[0, 6, 20, 7]"), ("popped", "4"), ("removed", "3"), ("length", "4"), ("part", "[6, 20]"),
+            ("found", "true")],
+    },
+    Program {
+        name: "maps",
+        source: "var m = {\"b\": 1, \"a\": 2, 3: \"three\"};
+        m[\"c\"] = 5;
+        m[\"a\"] += 10;
+        m[true] = nil;
+        var removed = m.remove(\"b\");
+        var has = m.has(\"b\");
+        var missing = m[\"zzz\"];
+        var keys = m.keys();
+        var size = m.len();",
+        globals: &[("m", "{a: 12, 3: three, c: 5, true: nil}"), ("removed", "1"), ("has", "false"), ("missing", "nil"),
+            ("keys", "[a, 3, c, true]"), ("size", "4")],
+    },
+    Program {
+        name: "for in loops",
+        source: "var items = [];
+        for (x in [1, 2]) items.push(x);
+        for (var key in {\"a\": 1, \"b\": 2}) items.push(key);
+        for (char in \"hi\") items.push(char);
+        var total = 0;
+        for (i in range(1, 4)) total += i;
+        var closures = [];
+        for (i in range(3)) closures.push(fun () { return i; });
+        var captured = closures[0]() + closures[2]() * 10;",
+        globals: &[("items", "[1, 2, a, b, h, i]"), ("total", "6"), ("captured", "20")],
+    },
+    Program {
+        name: "for in iterator protocol",
+        source: "class Countdown {
+            iterator() {
+                var it = CountdownIterator();
+                it.n = this.from;
+                return it;
+            }
+        }
+        class CountdownIterator {
+            hasNext() { return this.n > 0; }
+            next() {
+                this.n -= 1;
+                return this.n + 1;
+            }
+        }
+        var countdown = Countdown();
+        countdown.from = 3;
+        var seen = [];
+        for (n in countdown) seen.push(n);",
+        globals: &[("seen", "[3, 2, 1]")],
+    },
+    Program {
+        name: "strings",
+        source: r#"var name = "World";
+        var xs = [1, 2];
+        var greeting = "Hello ${name}! ${xs[0] + xs[1]} ${"nested ${name}"} ${ {"k": true} } \${name}";
+        var s = "  Hello, Wörld  ".trim();
+        var results = [s.len(), s.substring(7, 12), s.indexOf("ö"), s.indexOf("z"), s.split(", "),
+            "-".join(["a", 1, true]), s.upper(), s.lower(), s.replace("l", "L"), s.startsWith("He"),
+            "ab".chars(), " 42".toNumber(), "x".toNumber(), str([1, "a"])];
+        var compared = ["a" < "b", "b" >= "c", "n = " + 3, 3 + " apples"];"#,
+        globals: &[("greeting", "Hello World! 3 nested World {k: true} ${name}"),
+            ("results", "[12, Wörld, 8, -1, [Hello, Wörld], a-1-true, HELLO, WÖRLD, hello, wörld, HeLLo, WörLd, true, \
+[a, b], 42, nil, [1, a]]"),
+            ("compared", "[true, false, n = 3, 3 apples]")],
+    },
+    Program {
+        name: "print formatting and toString",
+        source: r#"class Plain {}
+        fun f() {}
+        var results = str([Plain, Plain(), f, str]);
+        class Point {
+            toString() { return "(" + this.x + ", " + this.y + ")"; }
+        }
+        var p = Point();
+        p.x = 1;
+        p.y = 2;
+        var hooked = [str(p), "at ${p}", str({"p": [p]})];"#,
+        globals: &[("results", "[Plain, Plain instance, <fn f>, <native fn str>]"),
+            ("hooked", "[(1, 2), at (1, 2), {p: [(1, 2)]}]")],
+    },
+    Program {
+        name: "toString has to return a string",
+        source: r#"class A { toString() { return this; } }
+        class B { toString() { return 1; } }
+        var errors = [];
+        try { print A(); } catch (e) { errors.push(e.message); }
+        try { var text = "${B()}"; } catch (e) { errors.push(e.message); }"#,
+        globals: &[("errors", "[toString() has to return a string, found A instance, toString() has to return a string, found 1]")],
+    },
+    Program {
+        name: "containers that contain themselves",
+        source: r#"var l = [1];
+        l.push(l);
+        var m = {"a": 1};
+        m["self"] = m;
+        m["list"] = l;
+        var shared = [2];
+        var texts = [str(l), str(m), str([shared, shared]), "${l}"];"#,
+        globals: &[("texts", "[[1, [...]], {a: 1, self: {...}, list: [1, [...]]}, [[2], [2]], [1, [...]]]"),
+            ("l", "[1, [...]]")],
+    },
+    Program {
+        name: "operator overloading",
+        source: "class Vec {
+            __add(o) { var r = Vec(); r.x = this.x + o.x; return r; }
+            __eq(o) { return this.x == o.x; }
+            __lt(o) { return this.x < o.x; }
+            __neg() { var r = Vec(); r.x = -this.x; return r; }
+        }
+        var a = Vec();
+        a.x = 1;
+        var b = Vec();
+        b.x = 2;
+        var sum = (a + b).x;
+        var negated = (-a).x;
+        var results = [a == b, a != b, a < b, a == a];
+        class Plain {}
+        var p = Plain();
+        var identity = [p == p, p != Plain(), 1 != 2];",
+        globals: &[("sum", "3"), ("negated", "-1"), ("results", "[false, true, true, true]"),
+            ("identity", "[true, true, true]")],
+    },
+    Program {
+        name: "try catch",
+        source: r#"var log = [];
+        try {
+            throw "oops";
+        } catch (e) {
+            log.push(e);
+        }
+        try {
+            var x = undefinedVar;
+        } catch (e) {
+            log.push(e.message);
+            log.push(e.line);
+        }
+        try {
+            try { throw Error("inner"); } finally { log.push("finally"); }
+        } catch (e) {
+            log.push(e.message + " at " + e.line);
+        }
+        fun cleanup() {
+            try { return 1; } finally { log.push("cleanup"); }
+        }
+        log.push(cleanup());
+        for (var i = 0; i < 3; i++) {
+            try { if (i == 1) throw i; log.push(i); } catch (e) { log.push("c" + e); }
+        }"#,
+        globals: &[("log", "[oops, Undefined variable 'undefinedVar', 8, finally, inner at 14, cleanup, 1, 0, c1, 2]")],
+    },
+    Program {
+        name: "arithmetic errors are catchable",
+        source: r#"var log = [];
+        var max = 9223372036854775807;
+        var operations = [
+            fun () { return max + 1; },
+            fun () { return -max - 2; },
+            fun () { return max * 2; },
+            fun () { return 1 / 0; },
+            fun () { return 1 % 0; },
+            fun () { return -(-max - 1); },
+            fun () { var n = max; n++; return n; },
+        ];
+        for (var operation in operations) {
+            try { log.push(operation()); } catch (e) { log.push(e.message); }
+        }"#,
+        globals: &[("log", "[Integer overflow in 9223372036854775807 + 1, Integer overflow in -9223372036854775807 - 2, \
+Integer overflow in 9223372036854775807 * 2, Division by zero, Division by zero, Integer overflow in -(-9223372036854775808), \
+Integer overflow in 9223372036854775807++]")],
+    },
+    Program {
+        name: "wrong argument count is catchable",
+        source: r#"var log = [];
+        fun f(a) { return a; }
+        class Point { norm() { return 0; } }
+        var calls = [
+            fun () { return f(1, 2); },
+            fun () { return f(); },
+            fun () { return Point(1); },
+            fun () { return Point().norm(3); },
+            fun () { return [].push(); },
+        ];
+        for (var call in calls) {
+            try { log.push(call()); } catch (e) { log.push(e.message); }
+        }"#,
+        globals: &[("log", "[f expects 1 arguments but got 2, f expects 1 arguments but got 0, \
+Point expects 0 arguments but got 1, norm expects 0 arguments but got 1, push expects 1 arguments but got 0]")],
+    },
+    Program {
+        name: "logical operators run left to right and short circuit",
+        source: r#"var order = [];
+        fun a() { order.push("a"); return false; }
+        fun b() { order.push("b"); return true; }
+        var either = a() or b();
+        var both = a() and b();
+        var skipped = b() or a();
+        var chained = false or false or true ? "x" : "z";
+        var errors = [];
+        try { var bad = false or 1; } catch (e) { errors.push(e.message); }
+        try { var bad = 1 and b(); } catch (e) { errors.push(e.message); }"#,
+        globals: &[("order", "[a, b, a, b]"), ("either", "true"), ("both", "false"), ("skipped", "true"), ("chained", "x"),
+            ("errors", "[Operands of 'or' must be booleans, found 1, Operands of 'and' must be booleans, found 1]")],
+    },
+    Program {
+        name: "conditions have to be booleans",
+        source: r#"var log = [];
+        var checks = [
+            fun () { if (1) return "y"; else return "n"; },
+            fun () { return nil ? "a" : "b"; },
+            fun () { while ("x") return 1; },
+            fun () { for (; 0;) return 1; },
+        ];
+        for (var check in checks) {
+            try { log.push(check()); } catch (e) { log.push(e.message); }
+        }"#,
+        globals: &[("log", "[Condition has to be a boolean, found 1, Condition has to be a boolean, found nil, \
+Condition has to be a boolean, found x, Condition has to be a boolean, found 0]")],
+    },
+];
+
+const FAILURES: &[Failure] = &[
+    Failure {
+        name: "if condition on its line",
+        source: "var x = 1;\nvar y = x;\n\n\nif (5) x = 2;",
+        report: "Error: Condition has to be a boolean, found 5\n[line 5] in script",
+    },
+    Failure {
+        name: "while condition on its line",
+        source: "var i = 0;\n\nwhile (i) i = i + 1;",
+        report: "Error: Condition has to be a boolean, found 0\n[line 3] in script",
+    },
+    Failure {
+        name: "for condition on its line",
+        source: "fun check() {\n  for (var i = 0; nil; i++) {}\n}\ncheck();",
+        report: "Error: Condition has to be a boolean, found nil\n[line 2] in check()\n[line 4] in script",
+    },
+    Failure {
+        name: "conditional expression on its line",
+        source: "var a = 1;\nvar b = nil ? 1 : 2;",
+        report: "Error: Condition has to be a boolean, found nil\n[line 2] in script",
+    },
+    Failure {
+        name: "logical operand on its line",
+        source: "var a = 1;\nvar b = a or 2;",
+        report: "Error: Operands of 'or' must be booleans, found 1\n[line 2] in script",
+    },
+    Failure {
+        name: "assign undeclared",
+        source: "missing = 1;",
+        report: "Error: Undefined variable 'missing'\n[line 1] in script",
+    },
+    Failure {
+        name: "list index out of bounds",
+        source: "var xs = [1, 2, 3]; xs[3] = 1;",
+        report: "Error: Index 3 out of bounds for list of length 3\n[line 1] in script",
+    },
+    Failure {
+        name: "substring out of bounds",
+        source: r#"var s = "abc".substring(0, 4);"#,
+        report: "Error: Index 4 out of bounds for string of length 3\n[line 1] in script",
+    },
+    Failure {
+        name: "missing operator method",
+        source: "class Plain {}
+        var x = Plain() * 2;",
+        report: "Error: Operator '*' is not defined for Plain, add a __mul method to the class\n[line 2] in script",
+    },
+    Failure {
+        name: "uncaught error reaches host with trace",
+        source: "fun inner() {
+            var xs = [1];
+            return xs[5];
+        }
+        fun outer() {
+            return inner();
+        }
+        outer();",
+        report: "Error: Index 5 out of bounds for list of length 1
+[line 3] in inner()
+[line 6] in outer()
+[line 8] in script",
+    },
+];
+
+type Backend = fn(&str, &[&str]) -> Result<Vec<String>, RuntimeError>;
+
+const BACKENDS: [(&str, Backend); 2] = [("tree-walker", tree_walker), ("vm", vm)];
+
+fn parse(program: &str) -> Vec<Box<Statement>> {
+    let tokens = crate::token::Scanner::new().tokenize_string(program.to_string());
+    crate::parser::Parser::new(tokens).program()
+}
+
+// each backend runs the source on a fresh interpreter and prints the globals asked for
+fn tree_walker(source: &str, names: &[&str]) -> Result<Vec<String>, RuntimeError> {
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.run(parse(source))?;
+    Ok(names.iter().map(|name| interpreter.lookup_variable(name.to_string()).borrow().print()).collect())
+}
+
+fn vm(source: &str, names: &[&str]) -> Result<Vec<String>, RuntimeError> {
+    let mut vm = Vm::new();
+    vm.run(parse(source))?;
+    Ok(names.iter().map(|name| vm.global(name).unwrap_or_else(|| panic!("no global {}", name)).print()).collect())
+}
+
+#[test]
+fn test_programs() {
+    for (backend, run) in BACKENDS {
+        for program in PROGRAMS {
+            let names: Vec<&str> = program.globals.iter().map(|(name, _)| *name).collect();
+            let values = run(program.source, &names)
+                .unwrap_or_else(|error| panic!("{} on the {}: {}", program.name, backend, error.report()));
+            for ((name, expected), value) in program.globals.iter().zip(values) {
+                assert_eq!(value, *expected, "{} on the {}: global {}", program.name, backend, name);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_failures() {
+    for (backend, run) in BACKENDS {
+        for failure in FAILURES {
+            match run(failure.source, &[]) {
+                Ok(_) => panic!("{} on the {}: expected an uncaught error", failure.name, backend),
+                Err(error) => assert_eq!(error.report(), failure.report, "{} on the {}", failure.name, backend),
+            }
+        }
+    }
+}
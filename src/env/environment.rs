@@ -1,49 +1,79 @@
 use std::cell::{Ref, RefCell};
 use std::collections::HashMap;
+use std::mem::size_of;
 use std::ops::Deref;
 use std::rc::Rc;
 
 use crate::expressions::expression::ExpressionRes;
+use crate::interner::Symbol;
+use crate::program::heap::{self, Heap};
 
+/// Where the resolver found a variable, the interpreter reads and writes it there without looking up its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Slot {
+    // names the resolver did not find in any scope live in the globals table
+    #[default]
+    Global,
+    // `depth` scopes out from the innermost one, at `index` in it
+    Local { depth: usize, index: usize },
+}
+
+/// Named variables, used for the globals table and the fields of instances.
 #[derive(Debug)]
 pub struct Environment {
-    variables: HashMap<String, Rc<RefCell<ExpressionRes>>>,
-    methods: HashMap<String, Rc<RefCell<ExpressionRes>>>,
+    variables: HashMap<Symbol, Rc<RefCell<ExpressionRes>>>,
+    methods: HashMap<Symbol, Rc<RefCell<ExpressionRes>>>,
     classes: HashMap<String, Rc<Class>>,
-    pub enclosing: Option<Rc<RefCell<Environment>>>
+    // bytes this environment added to the heap usage
+    accounted: usize,
+    heap: Rc<Heap>,
 }
 
 impl Environment {
     pub fn new() -> Environment {
+        let heap = heap::current();
+        heap.allocate(size_of::<Environment>());
         Environment {
             variables: Default::default(),
             methods: Default::default(),
             classes: Default::default(),
-            enclosing: Default::default(),
-        }
-    }
-    pub fn new_with_enclosing( enclosing: Rc<RefCell<Environment>>) -> Environment {
-        Environment {
-            variables: Default::default(),
-            methods: Default::default(),
-            classes: Default::default(),
-            enclosing: Some(enclosing),
+            accounted: size_of::<Environment>(),
+            heap,
         }
     }
 
-    pub fn define_variable(&mut self, name: String, expr: ExpressionRes) {
-        self.variables.insert(name, Rc::new(RefCell::new(expr)));
+    pub fn define_variable(&mut self, name: Symbol, expr: ExpressionRes) {
+        self.define_ref(name, Rc::new(RefCell::new(expr)));
     }
-    pub fn redefine_variable(&mut self, name: String, expr: ExpressionRes) {
-        let option = self.variables.get(&name).unwrap();
-        let mut ref_mut = option.replace(expr);
+    pub fn redefine_variable(&mut self, name: Symbol, expr: ExpressionRes) {
+        let option = self.variables.get(&name).unwrap().clone();
+        self.account(&option.borrow(), &expr);
+        option.replace(expr);
     }
 
-    pub fn define_ref(&mut self, name: String, expr: Rc<RefCell<ExpressionRes>>) {
+    // the names themselves live in the interner, an entry only adds its symbol
+    pub fn define_ref(&mut self, name: Symbol, expr: Rc<RefCell<ExpressionRes>>) {
+        let previous = self.variables.get(&name).cloned();
+        match previous {
+            None => {
+                let size = size_of::<Symbol>() + heap::value_size(&expr.borrow());
+                self.heap.allocate(size);
+                self.accounted += size;
+            }
+            Some(previous) => self.account(&previous.borrow(), &expr.borrow()),
+        }
         self.variables.insert(name, expr);
     }
 
-    pub fn get_variable(&mut self, name: String) -> Option<Rc<RefCell<ExpressionRes>>> {
+    fn account(&mut self, previous: &ExpressionRes, next: &ExpressionRes) {
+        let released = (size_of::<Symbol>() + heap::value_size(previous)).min(self.accounted);
+        self.heap.release(released);
+        let size = size_of::<Symbol>() + heap::value_size(next);
+        self.heap.allocate(size);
+        self.accounted = self.accounted - released + size;
+    }
+
+    pub fn get_variable(&self, name: Symbol) -> Option<Rc<RefCell<ExpressionRes>>> {
         let option = self.variables.get(&name);
         match option {
             None => { None }
@@ -54,9 +84,98 @@ impl Environment {
         }
     }
 
-    pub fn remove_var(&mut self, name: String) {
-        self.variables.remove(&*name);
-        self.variables.insert(name, Rc::new(RefCell::new(ExpressionRes::from_none())));
+    pub fn cells(&self) -> impl Iterator<Item = &Rc<RefCell<ExpressionRes>>> {
+        self.variables.values()
+    }
+
+    /// Drops every variable, used to break reference cycles.
+    pub fn clear(&mut self) {
+        self.variables.clear();
+        self.heap.release(self.accounted - size_of::<Environment>());
+        self.accounted = size_of::<Environment>();
+    }
+
+    pub fn remove_var(&mut self, name: Symbol) {
+        self.define_variable(name, ExpressionRes::from_none());
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        self.heap.release(self.accounted);
+    }
+}
+
+/// Local variables of a block or call, each in the slot the resolver gave it.
+#[derive(Debug)]
+pub struct Scope {
+    slots: Vec<ExpressionRes>,
+    pub enclosing: Option<Rc<RefCell<Scope>>>,
+    // bytes this scope added to the heap usage
+    accounted: usize,
+    heap: Rc<Heap>,
+}
+
+impl Scope {
+    pub fn new(enclosing: Option<Rc<RefCell<Scope>>>) -> Scope {
+        let heap = heap::current();
+        heap.allocate(size_of::<Scope>());
+        Scope {
+            slots: vec![],
+            enclosing,
+            accounted: size_of::<Scope>(),
+            heap,
+        }
+    }
+
+    /// The scope `depth` levels out from this one.
+    pub fn ancestor(scope: &Rc<RefCell<Scope>>, depth: usize) -> Rc<RefCell<Scope>> {
+        let mut current = scope.clone();
+        for _ in 0..depth {
+            let enclosing = current.borrow().enclosing.clone().expect("resolved past the outermost scope");
+            current = enclosing;
+        }
+        current
+    }
+
+    // a declaration that did not run, like one in an if without braces, leaves its slot nil
+    pub fn get(&self, index: usize) -> ExpressionRes {
+        match self.slots.get(index) {
+            None => ExpressionRes::from_none(),
+            Some(value) => value.clone(),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: ExpressionRes) {
+        while self.slots.len() <= index {
+            self.slots.push(ExpressionRes::from_none());
+            self.heap.allocate(size_of::<ExpressionRes>());
+            self.accounted += size_of::<ExpressionRes>();
+        }
+        let size = heap::value_size(&value);
+        let released = heap::value_size(&self.slots[index]).min(self.accounted);
+        self.heap.release(released);
+        self.heap.allocate(size);
+        self.accounted = self.accounted - released + size;
+        self.slots[index] = value;
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &ExpressionRes> {
+        self.slots.iter()
+    }
+
+    /// Drops every variable and the enclosing scope, used to break reference cycles.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.enclosing = None;
+        self.heap.release(self.accounted - size_of::<Scope>());
+        self.accounted = size_of::<Scope>();
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.heap.release(self.accounted);
     }
 }
 
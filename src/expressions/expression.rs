@@ -3,8 +3,12 @@ use std::fmt::{Debug, Formatter};
 use std::ops::Add;
 use std::rc::Rc;
 
+use crate::env::environment::Slot;
+use crate::interner::Symbol;
 use crate::expressions::visitor::{ExpressionInterpreter, Visitor};
-use crate::program::runtime::{Class, Instance, Method};
+use crate::program::gc;
+use crate::program::runtime::{Class, Closure, Instance, List, Map, Method, Native};
+use crate::statements::statement::Statement;
 use crate::token::{Token, TokenType};
 
 #[derive(Debug, Clone)]
@@ -36,11 +40,18 @@ pub enum Expression {
     },
     LiteralExpr {
         token_type: TokenType,
-        value: String,
+        value: Symbol,
+    },
+    // number literal, parsed once by the parser
+    NumberExpr {
+        value: isize,
     },
     VariableExpr {
         token_type: TokenType,
-        value: String,
+        value: Symbol,
+        line: usize,
+        // filled in by the resolver before the program runs
+        slot: Slot,
     },
     Assignment {
         identifier: Box<Expression>,
@@ -54,35 +65,80 @@ pub enum Expression {
     Call {
        identifier: Box<Expression>,
        args: Vec<Box<Expression>>,
+       line: usize,
     },
     Get {
         expr: Box<Expression>,
-        name: String,
-    }
-
+        name: Symbol,
+    },
+    CompoundAssignment {
+        identifier: Box<Expression>,
+        token: Token,
+        value: Box<Expression>,
+    },
+    Increment {
+        identifier: Box<Expression>,
+        token: Token,
+        prefix: bool,
+    },
+    Conditional {
+        condition: Box<Expression>,
+        then_branch: Box<Expression>,
+        else_branch: Box<Expression>,
+        // line of the ?
+        line: usize,
+    },
+    Lambda {
+        args: Vec<Expression>,
+        block: Rc<Statement>,
+    },
+    ListExpr {
+        items: Vec<Box<Expression>>,
+    },
+    MapExpr {
+        entries: Vec<(Box<Expression>, Box<Expression>)>,
+    },
+    Index {
+        expr: Box<Expression>,
+        index: Box<Expression>,
+    },
+    Interpolation {
+        parts: Vec<Box<Expression>>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct ExpressionRes {
     pub type_: ExprResType,
-    pub str: String,
+    // text of strings and names, shared between copies and never changed in place
+    pub str: Rc<str>,
     pub number: isize,
     pub boolean: bool,
     pub method: Option<Rc<Method>>,
     pub class: Option<Rc<Class>>,
     pub instance: Option<Rc<RefCell<Instance>>>,
+    pub list: Option<Rc<RefCell<List>>>,
+    pub map: Option<Rc<RefCell<Map>>>,
+    pub native: Option<Rc<Native>>,
+    // functions compiled for the virtual machine
+    pub closure: Option<Rc<Closure>>,
 }
 
 impl ExpressionRes {
+    // functions, classes and instances are shared by reference between copies
     pub fn copy(p: &ExpressionRes) -> ExpressionRes {
         ExpressionRes {
             type_: p.type_.clone(),
             str: p.str.clone(),
             number: p.number.clone(),
             boolean: p.boolean.clone(),
-            method: None,
-            class: None,
-            instance: None,
+            method: p.method.clone(),
+            class: p.class.clone(),
+            instance: p.instance.clone(),
+            list: p.list.clone(),
+            map: p.map.clone(),
+            native: p.native.clone(),
+            closure: p.closure.clone(),
         }
     }
 }
@@ -96,103 +152,194 @@ pub enum ExprResType {
     Function,
     Class,
     Instance,
+    List,
+    Map,
+    Native,
     Nil,
 }
 
 impl ExpressionRes {
-    pub fn from_str(str: String) -> ExpressionRes {
+    pub fn from_str(str: impl Into<Rc<str>>) -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::String,
-            str,
+            str: str.into(),
             number: 0,
             boolean: false,
-            method: None, class: None, instance: None,
+            method: None, class: None, instance: None, list: None, map: None, native: None, closure: None,
         }
     }
 
     pub fn from_number(number: isize) -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::Number,
-            str: String::new(),
+            str: Symbol::EMPTY.as_str(),
             number,
             boolean: false,
-            method: None, class: None,instance: None,
+            method: None, class: None, instance: None, list: None, map: None, native: None, closure: None,
         }
     }
 
     pub fn from_bool(boolean: bool) -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::Boolean,
-            str: String::new(),
+            str: Symbol::EMPTY.as_str(),
             number: 0,
             boolean,
-            method: None, class: None,instance: None,
+            method: None, class: None, instance: None, list: None, map: None, native: None, closure: None,
         }
     }
 
-    pub fn from_variable(str: String) -> ExpressionRes {
+    pub fn from_variable(str: impl Into<Rc<str>>) -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::Identifier,
-            str,
+            str: str.into(),
             number: 0,
             boolean: false,
-            method: None, class: None,instance: None,
+            method: None, class: None, instance: None, list: None, map: None, native: None, closure: None,
         }
     }
 
     pub fn from_method(method: Method) -> ExpressionRes {
+        let method = Rc::new(method);
+        gc::track_method(&method);
         ExpressionRes {
             type_: ExprResType::Function,
-            str: method.name.clone(),
+            str: method.name.as_str().into(),
             number: 0,
             boolean: false,
-            method: Some(Rc::new(method)),
+            method: Some(method),
             class: None,
             instance: None,
+            list: None,
+            map: None,
+            native: None,
+            closure: None,
         }
     }
 
     pub fn from_class(class: Class) -> ExpressionRes {
+        let class = Rc::new(class);
+        gc::track_class(&class);
         ExpressionRes {
             type_: ExprResType::Class,
-            str: "class ".to_string().add(&class.name.clone()),
+            str: format!("class {}", class.name).into(),
             number: 0,
             boolean: false,
             method:  None,
-            class: Some(Rc::new(class)),instance: None,
+            class: Some(class),instance: None,
+            list: None,
+            map: None,
+            native: None,
+            closure: None,
         }
     }
 
     pub fn from_instance(instance: Instance) -> ExpressionRes {
+        let instance = Rc::new(RefCell::new(instance));
+        gc::track_instance(&instance);
+        ExpressionRes::from_instance_ref(instance)
+    }
+
+    pub fn from_instance_ref(instance: Rc<RefCell<Instance>>) -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::Instance,
-            str: "instance of object".to_string(),
+            str: "instance of object".into(),
             number: 0,
             boolean: false,
             method:  None,
             class: None,
-            instance: Some(Rc::new(RefCell::new(instance))),
+            instance: Some(instance),
+            list: None,
+            map: None,
+            native: None,
+            closure: None,
         }
     }
 
     pub fn from_none() -> ExpressionRes {
         ExpressionRes {
             type_: ExprResType::Nil,
-            str: "nil".to_string(),
+            str: Symbol::NIL.as_str(),
             number: 0,
             boolean: false,
             method: None,
             class: None,
             instance: None,
+            list: None,
+            map: None,
+            native: None,
+            closure: None,
         }
     }
 
-    pub fn get_params_method(&self) -> Vec<String> {
-        let mut args = vec![];
-        for arg in &self.method.as_ref().unwrap().args {
-            args.push(arg.str.clone())
+    pub fn from_list(items: Vec<ExpressionRes>) -> ExpressionRes {
+        let list = Rc::new(RefCell::new(List::new(items)));
+        gc::track_list(&list);
+        ExpressionRes {
+            type_: ExprResType::List,
+            str: Symbol::EMPTY.as_str(),
+            number: 0,
+            boolean: false,
+            method: None,
+            class: None,
+            instance: None,
+            list: Some(list),
+            map: None,
+            native: None,
+            closure: None,
+        }
+    }
+
+    pub fn from_map(map: Map) -> ExpressionRes {
+        let map = Rc::new(RefCell::new(map));
+        gc::track_map(&map);
+        ExpressionRes {
+            type_: ExprResType::Map,
+            str: Symbol::EMPTY.as_str(),
+            number: 0,
+            boolean: false,
+            method: None,
+            class: None,
+            instance: None,
+            list: None,
+            map: Some(map),
+            native: None,
+            closure: None,
+        }
+    }
+
+    pub fn from_closure(closure: Closure) -> ExpressionRes {
+        let closure = Rc::new(closure);
+        gc::track_closure(&closure);
+        ExpressionRes {
+            type_: ExprResType::Function,
+            str: closure.function.name.as_str().into(),
+            number: 0,
+            boolean: false,
+            method: None,
+            class: None,
+            instance: None,
+            list: None,
+            map: None,
+            native: None,
+            closure: Some(closure),
+        }
+    }
+
+    pub fn from_native(native: Native) -> ExpressionRes {
+        ExpressionRes {
+            type_: ExprResType::Native,
+            str: native.name.as_str().into(),
+            number: 0,
+            boolean: false,
+            method: None,
+            class: None,
+            instance: None,
+            list: None,
+            map: None,
+            native: Some(Rc::new(native)),
+            closure: None,
         }
-        args
     }
 
     pub fn get_method(&self) -> &Rc<Method> {
@@ -203,16 +350,75 @@ impl ExpressionRes {
         self.type_ == other.type_
     }
 
+    /// Value equality for primitives, identity for everything shared by reference.
+    pub fn equals(&self, other: &ExpressionRes) -> bool {
+        if !self.eq_type(other) {
+            return false;
+        }
+        match self.type_ {
+            ExprResType::String | ExprResType::Identifier => Rc::ptr_eq(&self.str, &other.str) || self.str == other.str,
+            ExprResType::Number => self.number == other.number,
+            ExprResType::Boolean => self.boolean == other.boolean,
+            ExprResType::Nil => true,
+            ExprResType::Function => ptr_eq(&self.method, &other.method) || ptr_eq(&self.closure, &other.closure),
+            ExprResType::Class => ptr_eq(&self.class, &other.class),
+            ExprResType::Instance => ptr_eq(&self.instance, &other.instance),
+            ExprResType::List => ptr_eq(&self.list, &other.list),
+            ExprResType::Map => ptr_eq(&self.map, &other.map),
+            ExprResType::Native => ptr_eq(&self.native, &other.native),
+        }
+    }
+
+    /// Text of the value without calling into user code, a list or map inside itself shows as [...] or {...}.
     pub fn print(&self) -> String {
+        self.print_nested(&mut vec![])
+    }
+
+    // `open` holds the addresses of the lists and maps being printed
+    fn print_nested(&self, open: &mut Vec<usize>) -> String {
         match self.type_ {
-            ExprResType::String => self.str.clone(),
+            ExprResType::String => self.str.to_string(),
             ExprResType::Number => self.number.to_string(),
             ExprResType::Boolean => if self.boolean { String::from("true") } else { String::from("false") },
             ExprResType::Nil => String::from("nil"),
-            ExprResType::Identifier => self.str.clone(),
-            ExprResType::Function => { "function :".to_string().add(&*self.str) }
-            ExprResType::Class => {"class :" .to_string().add(&*self.str)}
-            ExprResType::Instance => {format!("instance : {:#?}",& self.instance)}
+            ExprResType::Identifier => self.str.to_string(),
+            ExprResType::Function => format!("<fn {}>", self.str),
+            ExprResType::Class => self.class.as_ref().unwrap().name.clone(),
+            ExprResType::Instance => format!("{} instance", self.instance.as_ref().unwrap().borrow().class.name),
+            ExprResType::List => {
+                let list = self.list.as_ref().unwrap();
+                let address = Rc::as_ptr(list) as usize;
+                if open.contains(&address) {
+                    return "[...]".to_string();
+                }
+                open.push(address);
+                let items: Vec<String> = list.borrow().iter()
+                    .map(|item| item.print_nested(open))
+                    .collect();
+                open.pop();
+                format!("[{}]", items.join(", "))
+            }
+            ExprResType::Map => {
+                let map = self.map.as_ref().unwrap();
+                let address = Rc::as_ptr(map) as usize;
+                if open.contains(&address) {
+                    return "{...}".to_string();
+                }
+                open.push(address);
+                let entries: Vec<String> = map.borrow().entries().iter()
+                    .map(|(key, value)| format!("{}: {}", key.to_res().print(), value.print_nested(open)))
+                    .collect();
+                open.pop();
+                format!("{{{}}}", entries.join(", "))
+            }
+            ExprResType::Native => format!("<native fn {}>", self.str),
         }
     }
+}
+
+fn ptr_eq<T: ?Sized>(lhs: &Option<Rc<T>>, rhs: &Option<Rc<T>>) -> bool {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Rc::ptr_eq(lhs, rhs),
+        _ => false,
+    }
 }
\ No newline at end of file
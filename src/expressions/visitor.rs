@@ -4,24 +4,30 @@ use std::ops::Deref;
 use std::rc::Rc;
 
 use log::trace;
-use regex::internal::Inst;
 
-use crate::env::environment::Environment;
+use crate::env::environment::{Scope, Slot};
+use crate::interner::Symbol;
 use crate::expressions::expression::{Expression, ExpressionRes, ExprResType};
 use crate::expressions::expression::ExprResType::{Function, Identifier, Nil};
 use crate::program::program::ProgramEnvs;
-use crate::program::runtime::{Instance, Method};
+use crate::program::{errors, natives, operations};
+use crate::program::errors::RuntimeError;
+use crate::program::natives::Host;
+use crate::program::execution::{ExecutionState, Frame};
+use crate::program::runtime::{Class, Instance, Map, MapKey, Method};
+use crate::statements::statement::Statement;
 use crate::statements::stmt_visitor::{StatementInterpreter, StatementRes};
 use crate::statements::stmt_visitor::StatementRes::{Expr, Void};
-use crate::token::TokenType;
+use crate::token::{Token, TokenType};
 
 pub trait Visitor<T> {
-  fn eval(&self, expression: Expression) -> T;
+  fn eval(&self, expression: &Expression) -> T;
 }
 
 #[derive(Clone)]
 pub struct ExpressionInterpreter {
   pub envs: Rc<RefCell<ProgramEnvs>>,
+  pub state: Rc<RefCell<ExecutionState>>,
 }
 
 impl Debug for ExpressionInterpreter {
@@ -34,283 +40,328 @@ impl Debug for ExpressionInterpreter {
 impl ExpressionInterpreter {
   pub fn new() -> ExpressionInterpreter {
     ExpressionInterpreter {
-      envs: Rc::new(RefCell::new(ProgramEnvs::new()))
+      envs: Rc::new(RefCell::new(ProgramEnvs::new())),
+      state: Rc::new(RefCell::new(ExecutionState::new())),
     }
   }
   pub fn new_with_envs(envs: Rc<RefCell<ProgramEnvs>>) -> ExpressionInterpreter {
     ExpressionInterpreter {
-      envs
+      envs,
+      state: Rc::new(RefCell::new(ExecutionState::new())),
+    }
+  }
+  pub fn new_with_state(envs: Rc<RefCell<ProgramEnvs>>, state: Rc<RefCell<ExecutionState>>) -> ExpressionInterpreter {
+    ExpressionInterpreter {
+      envs,
+      state,
     }
   }
 }
 
-impl Visitor<ExpressionRes> for ExpressionInterpreter {
-  fn eval(&self, expression: Expression) -> ExpressionRes {
-    match expression {
-      Expression::Expr { value, equality } => {
-        match equality {
-          None => { ExpressionRes::from_none() }
-          Some(value) => {
-            self.eval(*value)
+impl Host for ExpressionInterpreter {
+  fn call(&mut self, callee: ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    ExpressionInterpreter::call(self, callee, arguments)
+  }
+
+  fn memory_limit(&self) -> Option<usize> {
+    self.state.borrow().memory_limit
+  }
+}
+
+enum Place {
+  Variable(Symbol, Slot),
+  Property(ExpressionRes, Symbol),
+  Element(ExpressionRes, ExpressionRes),
+}
+
+impl ExpressionInterpreter {
+  /// Resolves a variable result to the value currently stored under its name.
+  pub fn value_of(&self, res: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    if res.type_ == Identifier {
+      return Ok(ExpressionRes::copy(self.envs.borrow().lookup_var(Symbol::intern(&res.str))?.borrow().deref()));
+    }
+    Ok(res)
+  }
+
+  /// Evaluates the expression and resolves a variable result to the value it holds.
+  pub fn evaluate(&self, expression: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let res = self.eval(expression)?;
+    self.value_of(res)
+  }
+
+  /// Calls a function, native or class with already evaluated arguments.
+  pub fn call(&self, callable: ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    trace!("Call with {:#?}", callable.type_.clone());
+    match callable.type_ {
+      Function => {
+        let method = callable.get_method();
+        errors::expect_arity(&method.name, method.args.len(), arguments.len())?;
+        let mut arguments_scope = Scope::new(method.captured.clone());
+        // the resolver gave the parameters the first slots, in order
+        for (index, value) in arguments.into_iter().enumerate() {
+          arguments_scope.set(index, value);
+        }
+
+        let globals = self.envs.borrow().globals();
+        let resolved_scope = Rc::new(RefCell::new(arguments_scope));
+        self.state.borrow_mut().push_frame(Frame {
+          name: method.name.clone(),
+          line: errors::current_line(),
+          callee: ExpressionRes::copy(&callable),
+        })?;
+        let result = method.call(Rc::new(RefCell::new(
+          ProgramEnvs::new_with_scope(globals, resolved_scope))), self.state.clone());
+        if let Err(mut error) = result {
+          if error.trace.is_empty() {
+            error.trace = self.state.borrow().traceback(error.line);
           }
+          self.state.borrow_mut().pop_frame();
+          return Err(error);
+        }
+        self.state.borrow_mut().pop_frame();
+
+        match result {
+          Ok(Expr { res }) => Ok(res),
+          _ => Ok(ExpressionRes::from_none()),
         }
       }
-      Expression::Equality { token, value } => {
-        println!("Hello-world Equality {:?}", &value);
-        ExpressionRes::from_str(String::from(""))
+      ExprResType::Native => {
+        callable.native.as_ref().unwrap().call(&mut self.clone(), arguments)
       }
-      Expression::Comparison { token_type, value } => {
-        println!("Hello-world Equality {:?}", &value);
-        ExpressionRes::from_str(String::from(""))
+      ExprResType::Class => {
+        Ok(ExpressionRes::from_instance(Class::construct(callable.class.clone().unwrap(), arguments)?))
       }
-      Expression::GroupingExpr { value } => {
-        self.eval(*value)
+      _ => Err(errors::error("please call () is only usable on functions or classes".to_string())),
+    }
+  }
+
+  /// Evaluates the object and index of an assignment target exactly once.
+  fn place(&self, target: &Expression) -> Result<Place, RuntimeError> {
+    match target {
+      Expression::VariableExpr { value: name, slot, line, .. } => {
+        errors::set_line(*line);
+        Ok(Place::Variable(*name, *slot))
+      }
+      Expression::Get { expr, name } => Ok(Place::Property(self.evaluate(expr)?, *name)),
+      Expression::Index { expr, index } => {
+        let object = self.evaluate(expr)?;
+        Ok(Place::Element(object, self.evaluate(index)?))
       }
-      Expression::BinaryExpr { token, rhs, lhs } => {
-        let mut rhs_res = self.eval(*rhs);
-        let mut lhs_res = self.eval(*lhs);
+      other => { panic!("Invalid assignment target {:?}", other) }
+    }
+  }
 
-        if rhs_res.type_ == Identifier {
-          let rc = self.envs.borrow().lookup_var(rhs_res.str.to_string());
-          rhs_res = ExpressionRes::copy(rc.borrow().deref());
-        }
+  fn read_place(&self, place: &Place) -> Result<ExpressionRes, RuntimeError> {
+    match place {
+      Place::Variable(name, slot) => self.envs.borrow().get(*slot, *name),
+      Place::Property(object, name) => operations::get_property(object, *name),
+      Place::Element(object, index) => natives::get_index(object, index),
+    }
+  }
 
-        if lhs_res.type_ == Identifier {
-          let rc = self.envs.borrow().lookup_var(lhs_res.str.to_string());
-          lhs_res = ExpressionRes::copy(rc.borrow().deref());
-        }
+  fn write_place(&self, place: Place, value: ExpressionRes) -> Result<(), RuntimeError> {
+    match place {
+      Place::Variable(name, slot) => self.envs.borrow().assign(slot, name, value),
+      Place::Property(object, name) => operations::set_property(&object, name, value),
+      Place::Element(object, index) => natives::set_index(&object, &index, value),
+    }
+  }
 
-        if lhs_res.type_ == ExprResType::Number && lhs_res.eq_type(&rhs_res) {
-          match token.token_type {
-            TokenType::Greater => ExpressionRes::from_bool(
-              lhs_res.number > rhs_res.number),
-            TokenType::GreaterEqual => ExpressionRes::from_bool(
-              lhs_res.number >= rhs_res.number),
-            TokenType::Less => ExpressionRes::from_bool(
-              lhs_res.number < rhs_res.number),
-            TokenType::LessEqual => ExpressionRes::from_bool(
-              lhs_res.number <= rhs_res.number),
-            TokenType::EqualEqual => ExpressionRes::from_bool(
-              lhs_res.number == rhs_res.number),
-            TokenType::Minus => ExpressionRes::from_number(
-              lhs_res.number - rhs_res.number),
-            TokenType::Slash => ExpressionRes::from_number(
-              lhs_res.number / rhs_res.number),
-            TokenType::Star => ExpressionRes::from_number(
-              lhs_res.number * rhs_res.number),
-            TokenType::Plus => ExpressionRes::from_number(
-              lhs_res.number + rhs_res.number),
-            TokenType::Percent => ExpressionRes::from_number(
-              (lhs_res.number).rem_euclid(rhs_res.number)
-            ),
-            _ => ExpressionRes::from_none()
-          }
-        } else if lhs_res.type_ == ExprResType::String && lhs_res.eq_type(&rhs_res) {
-          match token.token_type {
-            TokenType::Plus => ExpressionRes::from_str(
-              lhs_res.str.to_string() + &*rhs_res.str),
-            TokenType::EqualEqual => ExpressionRes::from_bool(
-              lhs_res.str.to_string() == rhs_res.str.to_string()),
-            _ => ExpressionRes::from_none(),
+  fn binary(&self, token: &Token, lhs_res: ExpressionRes, rhs_res: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    errors::set_line(token.line);
+    operations::binary(&mut self.clone(), token.token_type, &token.value.as_str(), lhs_res, rhs_res)
+  }
+}
+
+/// The parameter names of a function declaration, its calls bind the arguments in this order.
+pub fn parameters(args: &[Expression]) -> Vec<ExpressionRes> {
+  args.iter().map(|arg| match arg {
+    Expression::VariableExpr { value, .. } => ExpressionRes::from_variable(value.as_str()),
+    other => { panic!("Expected a parameter name, found {:?}", other) }
+  }).collect()
+}
+
+// every arm calls out to its own method, in debug builds the locals of all arms would otherwise add up
+// in the frame of eval, which nests for every Lox call
+impl Visitor<Result<ExpressionRes, RuntimeError>> for ExpressionInterpreter {
+  fn eval(&self, expression: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    match expression {
+      Expression::Expr { value, equality } => {
+        match equality {
+          None => { Ok(ExpressionRes::from_none()) }
+          Some(value) => {
+            self.eval(value)
           }
-        } else {
-          println!("There has been an error in a binary operation");
-          ExpressionRes::from_none()
         }
       }
-      Expression::UnaryExpr { token, rhs } => {
-        let rhs_res = self.eval(*rhs);
-        match (rhs_res.type_, token.token_type) {
-          (ExprResType::Number, TokenType::Minus) => ExpressionRes::from_number(-(rhs_res.number)),
-          (ExprResType::Boolean, TokenType::Bang) => ExpressionRes::from_bool(!(rhs_res.boolean)),
-          _ => ExpressionRes::from_none()
-        }
-      }
-      Expression::LiteralExpr { token_type, value } => {
-        match token_type {
-          TokenType::String => ExpressionRes::from_str(value.clone()),
-          TokenType::Number => ExpressionRes::from_number(str::parse::<isize>(&value).unwrap()),
-          TokenType::False => ExpressionRes::from_bool(false),
-          TokenType::True => ExpressionRes::from_bool(true),
-          _ => ExpressionRes::from_none()
-        }
+      Expression::Equality { value, .. } | Expression::Comparison { value, .. } => self.unused(value),
+      Expression::GroupingExpr { value } => {
+        self.eval(value)
       }
-      Expression::VariableExpr { token_type, value } => {
+      Expression::BinaryExpr { token, rhs, lhs } => self.binary_expression(token, lhs, rhs),
+      Expression::UnaryExpr { token, rhs } => self.unary(token, rhs),
+      Expression::LiteralExpr { token_type, value } => Ok(literal(*token_type, *value)),
+      Expression::NumberExpr { value } => Ok(ExpressionRes::from_number(*value)),
+      Expression::VariableExpr { token_type, value, line, slot } => {
+        errors::set_line(*line);
         match token_type {
-          TokenType::Nil => ExpressionRes::from_none(),
-          _ => ExpressionRes::from_variable(value.clone())
+          TokenType::Nil => Ok(ExpressionRes::from_none()),
+          _ => self.envs.borrow().get(*slot, *value),
         }
       }
-      Expression::Assignment { identifier, value } => {
-        let assignee = self.eval(*identifier);
-        let value = self.eval(*value);
-        match value.type_ {
-          Identifier => {
-            let rc = self.envs.borrow().lookup_var(value.str.clone());
-            rc.replace(ExpressionRes::copy(&value));
-            return ExpressionRes::copy(&value);
-          }
-          Function => {
-            value
-          }
-          Nil => {
-            self.envs.borrow().remove_var(assignee.str);
-            ExpressionRes::from_none()
-          }
-          _ => {
-            let res = ExpressionRes::copy(&value);
-            self.envs.borrow().assign_to_existing(assignee.str.to_string(), value);
-            res
-          }
-        }
-      }
-      Expression::Logical { token, rhs, lhs } => {
-        let mut rhs_res = self.eval(*rhs);
-        let mut lhs_res = self.eval(*lhs);
+      Expression::Assignment { identifier, value } => self.assignment(identifier, value),
+      Expression::Logical { token, rhs, lhs } => self.logical(token, lhs, rhs),
+      Expression::Call { identifier, args, line } => self.call_expression(identifier, args, *line),
+      Expression::Get { expr, name } => self.get_expression(expr, *name),
+      Expression::CompoundAssignment { identifier, token, value } => self.compound_assignment(identifier, token, value),
+      Expression::ListExpr { items } => self.list(items),
+      Expression::MapExpr { entries } => self.map(entries),
+      Expression::Index { expr, index } => self.index(expr, index),
+      Expression::Interpolation { parts } => self.interpolation(parts),
+      Expression::Lambda { args, block } => Ok(self.lambda(args, block)),
+      Expression::Conditional { condition, then_branch, else_branch, line } => self.conditional(condition, then_branch, else_branch, *line),
+      Expression::Increment { identifier, token, prefix } => self.increment(identifier, token, *prefix),
+    }
+  }
+}
 
-        if rhs_res.type_ == Identifier {
-          let rc = self.envs.borrow().lookup_var(rhs_res.str.to_string());
-          rhs_res = ExpressionRes::copy(rc.borrow().deref());
-        }
+impl ExpressionInterpreter {
+  fn unused(&self, value: &str) -> Result<ExpressionRes, RuntimeError> {
+    println!("Hello-world Equality {:?}", value);
+    Ok(ExpressionRes::from_str(String::from("")))
+  }
 
-        if lhs_res.type_ == Identifier {
-          let rc1 = self.envs.borrow().lookup_var(lhs_res.str.to_string());
-          lhs_res = ExpressionRes::copy(rc1.borrow().deref());
-        }
+  fn binary_expression(&self, token: &Token, lhs: &Expression, rhs: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let lhs_res = self.evaluate(lhs)?;
+    let rhs_res = self.evaluate(rhs)?;
+    self.binary(token, lhs_res, rhs_res)
+  }
 
-        if lhs_res.type_ == ExprResType::Boolean && lhs_res.eq_type(&rhs_res) {
-          match token.token_type {
-            TokenType::And => {
-              ExpressionRes::from_bool(lhs_res.boolean && rhs_res.boolean)
-            }
-            TokenType::Or => {
-              ExpressionRes::from_bool(lhs_res.boolean || rhs_res.boolean)
-            }
-            _ => {
-              panic!("cannot evaluate logical expression for {:#?} {:#?}", &lhs_res, &rhs_res)
-            }
-          }
-        } else {
-          panic!("cannot evaluate logical expression for {:#?} {:#?}", &lhs_res, &rhs_res)
-        }
-      }
-      Expression::Call { identifier, args } => {
-        let mut callable;
-        let res3 = self.eval(*identifier);
-        match &res3.type_ {
-          &Identifier => {
-            callable = self.envs.borrow().lookup_var(res3.str.clone());
-          }
-          &Function => {
-            callable = Rc::new(RefCell::new(res3))
-          }
-          _ => { callable = Rc::new(RefCell::new(res3)) }
-        }
+  fn unary(&self, token: &Token, rhs: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let rhs_res = self.evaluate(rhs)?;
+    errors::set_line(token.line);
+    operations::unary(&mut self.clone(), token.token_type, &token.value.as_str(), rhs_res)
+  }
 
+  fn assignment(&self, identifier: &Expression, value: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let place = self.place(identifier)?;
+    let value = self.evaluate(value)?;
+    self.write_place(place, value.clone())?;
+    Ok(value)
+  }
 
-        trace!("Call with {:#?}", callable.borrow().type_.clone());
-        let result = match callable.borrow().type_.clone() {
-          Function => {
-            let mut enclosed_environment = None;
-            if let Some(value) = callable.borrow().method.clone() {
-              enclosed_environment = Some(value.captured_env.clone());
-            }
-
-            let mut arguments_env = Environment::new_with_enclosing(enclosed_environment.unwrap());
-
-            let argument_names = callable.as_ref().borrow().get_params_method();
-            if argument_names.len() != args.len() {
-              println!("{{ wow, so weird, this look like passed args are not same as declared definition }}")
-            }
-            let mut i: usize = 0;
-            for arg in args {
-              let mut res = self.eval(*arg);
-              if res.type_ == ExprResType::Identifier {
-                let rc2 = self.envs.borrow_mut().lookup_var(res.str.clone());
-                res = ExpressionRes::copy(rc2.borrow_mut().deref());
-              }
-
-              arguments_env.define_variable(argument_names[i].clone(), res);
-              i = i + 1;
-            }
-
-            let resolved_env = Rc::new(RefCell::new(arguments_env));
-            let result = callable.as_ref()
-              .borrow()
-              .get_method()
-              .call(Rc::new(RefCell::new(
-                ProgramEnvs::new_with_env(resolved_env.clone()))));
-
-            match result {
-              Ok(Void {}) => { return ExpressionRes::from_none(); }
-              Ok(Expr { mut res }) => {
-                return res;
-              }
-              Err(val) => { panic!("{}", val) }
-            }
-          }
-          ExprResType::Class => {
-            let class_object = callable.borrow();
-            let mut declared_parameters = vec![];
-
-            for dec_arg in &class_object.class.as_ref().unwrap().args {
-              declared_parameters.push(dec_arg.str.clone());
-            }
-            let mut constructor_environment = Environment::new();
-
-            if declared_parameters.len() != args.len() {
-              println!("{{ wow, so weird, this look like passed args are not same as declared definition }}");
-            }
-
-            let mut i: usize = 0;
-            for arg in args {
-              let mut resolved_argument = self.eval(*arg);
-              if resolved_argument.type_ == ExprResType::Identifier {
-                let actual_ref = self.envs.borrow_mut().lookup_var(resolved_argument.str.clone());
-                resolved_argument = ExpressionRes::copy(actual_ref.borrow_mut().deref());
-              }
-
-              constructor_environment.define_variable(declared_parameters[i].clone(), resolved_argument);
-              i = i + 1;
-            }
-            let environment = Rc::new(RefCell::new(constructor_environment));
-            let mut environment1 = Environment::new_with_enclosing(environment.clone());
-            for method_class in &class_object.class.as_ref().unwrap().methods {
-              let mut binding_method = (*method_class.deref()).clone();
-              binding_method.captured_env = environment.clone();
-              environment1.define_variable(binding_method.name.clone(), ExpressionRes::from_method(binding_method))
-            }
-
-
-            let class_reference = (*class_object.class.as_ref().unwrap()).clone();
-            let instance = class_reference.call(Rc::new(RefCell::new(environment1)), class_reference.clone()).unwrap();
-            ExpressionRes::from_instance(instance)
-          }
-          _ => { panic!("please call () is only usable on functions or classes") }
-        };
-        result
-      }
-      Expression::Get { expr, name } => {
-        let res1 = self.eval(*expr);
-        trace!("Entering get {:#?}", res1);
-        if &res1.type_ == &ExprResType::Identifier {
-          let rc = self.envs.borrow().lookup_var(res1.str.to_string());
+  fn logical(&self, token: &Token, lhs: &Expression, rhs: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let lexeme = token.value.as_str();
+    let lhs_res = self.evaluate(lhs)?;
+    errors::set_line(token.line);
+    if operations::short_circuits(token.token_type, &lexeme, &lhs_res)? {
+      return Ok(lhs_res);
+    }
+    let rhs_res = self.evaluate(rhs)?;
+    errors::set_line(token.line);
+    operations::short_circuits(token.token_type, &lexeme, &rhs_res)?;
+    Ok(rhs_res)
+  }
 
-          if &rc.as_ref().borrow().type_ == &ExprResType::Instance {
-            let option = rc.as_ref().borrow_mut().instance.as_ref().unwrap().borrow_mut()
-              .env.borrow_mut().get_variable(name).unwrap();
+  fn call_expression(&self, identifier: &Expression, args: &[Box<Expression>], line: usize) -> Result<ExpressionRes, RuntimeError> {
+    let callable = self.evaluate(identifier)?;
+    let mut arguments = vec![];
+    for arg in args {
+      arguments.push(self.evaluate(arg)?);
+    }
+    errors::set_line(line);
+    let result = self.call(callable, arguments)?;
+    errors::set_line(line);
+    Ok(result)
+  }
 
+  fn get_expression(&self, expr: &Expression, name: Symbol) -> Result<ExpressionRes, RuntimeError> {
+    let object = self.evaluate(expr)?;
+    trace!("Entering get {:#?}", object.type_);
+    operations::get_property(&object, name)
+  }
 
-            let res2 = ExpressionRes::from_method(option.as_ref().borrow_mut()
-              .method.as_ref().unwrap()
-              .prepare_for_call(Environment::new()));
+  fn compound_assignment(&self, identifier: &Expression, token: &Token, value: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let token_type = match token.token_type {
+      TokenType::PlusEqual => TokenType::Plus,
+      TokenType::MinusEqual => TokenType::Minus,
+      TokenType::StarEqual => TokenType::Star,
+      TokenType::SlashEqual => TokenType::Slash,
+      _ => TokenType::Percent,
+    };
+    let operator = Token { token_type, value: token.value, line: token.line };
+    let place = self.place(identifier)?;
+    let current = self.read_place(&place)?;
+    let rhs = self.evaluate(value)?;
+    let result = self.binary(&operator, current, rhs)?;
+    self.write_place(place, result.clone())?;
+    Ok(result)
+  }
 
-            trace!("Trace returning from Get {:#?}", &res2);
-            return res2;
-          }
-        }
-        panic!("nonono");
-      }
+  fn list(&self, items: &[Box<Expression>]) -> Result<ExpressionRes, RuntimeError> {
+    let mut values = vec![];
+    for item in items {
+      values.push(self.evaluate(item)?);
+    }
+    Ok(ExpressionRes::from_list(values))
+  }
+
+  fn map(&self, entries: &[(Box<Expression>, Box<Expression>)]) -> Result<ExpressionRes, RuntimeError> {
+    let mut map = Map::new();
+    for (key, value) in entries {
+      let key = self.evaluate(key)?;
+      let value = self.evaluate(value)?;
+      map.insert(MapKey::from_res(&key)?, value);
+    }
+    Ok(ExpressionRes::from_map(map))
+  }
+
+  fn index(&self, expr: &Expression, index: &Expression) -> Result<ExpressionRes, RuntimeError> {
+    let object = self.evaluate(expr)?;
+    let index = self.evaluate(index)?;
+    natives::get_index(&object, &index)
+  }
+
+  fn interpolation(&self, parts: &[Box<Expression>]) -> Result<ExpressionRes, RuntimeError> {
+    let mut value = String::new();
+    for part in parts {
+      let part = self.evaluate(part)?;
+      value.push_str(&operations::stringify(&mut self.clone(), &part)?);
+    }
+    Ok(ExpressionRes::from_str(value))
+  }
+
+  fn lambda(&self, args: &[Expression], block: &Rc<Statement>) -> ExpressionRes {
+    let method = Method::new("anonymous".to_string(), parameters(args), block.clone(), self.envs.borrow().get_top());
+    ExpressionRes::from_method(method)
+  }
+
+  fn conditional(&self, condition: &Expression, then_branch: &Expression, else_branch: &Expression, line: usize) -> Result<ExpressionRes, RuntimeError> {
+    let condition = self.evaluate(condition)?;
+    errors::set_line(line);
+    if operations::condition(&condition)? {
+      self.eval(then_branch)
+    } else {
+      self.eval(else_branch)
     }
   }
-}
\ No newline at end of file
+
+  fn increment(&self, identifier: &Expression, token: &Token, prefix: bool) -> Result<ExpressionRes, RuntimeError> {
+    errors::set_line(token.line);
+    let offset = if token.token_type == TokenType::PlusPlus { 1 } else { -1 };
+    let place = self.place(identifier)?;
+    let current = self.read_place(&place)?;
+    let result = operations::step(&current, &token.value.as_str(), offset)?;
+    self.write_place(place, result.clone())?;
+    if prefix { Ok(result) } else { Ok(current) }
+  }
+}
+
+fn literal(token_type: TokenType, value: Symbol) -> ExpressionRes {
+  match token_type {
+    TokenType::String => ExpressionRes::from_str(value.as_str()),
+    TokenType::False => ExpressionRes::from_bool(false),
+    TokenType::True => ExpressionRes::from_bool(true),
+    _ => ExpressionRes::from_none()
+  }
+}
@@ -5,8 +5,9 @@ use std::ptr::eq;
 use std::rc::Rc;
 
 use crate::expressions::expression::{Expression, ExpressionRes};
-use crate::expressions::expression::Expression::{BinaryExpr, Expr, LiteralExpr, Logical, UnaryExpr};
+use crate::expressions::expression::Expression::{BinaryExpr, Expr, LiteralExpr, Logical, NumberExpr, UnaryExpr};
 use crate::expressions::visitor::{ExpressionInterpreter, Visitor};
+use crate::interner::Symbol;
 use crate::parser::Parser;
 use crate::statements::statement::Statement;
 use crate::token::{Scanner, Token, TokenType};
@@ -17,13 +18,13 @@ fn unary_bang() {
     let expr = UnaryExpr {
         token: Token {
             token_type: TokenType::Bang,
-            value: "".to_string(),
+            value: Symbol::intern(""),
             line: 0,
         },
-        rhs: Box::new(LiteralExpr { token_type: TokenType::False, value: "".to_string() }),
+        rhs: Box::new(LiteralExpr { token_type: TokenType::False, value: Symbol::intern("") }),
     };
     let mut visitor = ExpressionInterpreter::new();
-    assert!(visitor.eval(expr).boolean);
+    assert!(visitor.eval(&expr).unwrap().boolean);
 }
 
 #[test]
@@ -31,14 +32,24 @@ fn string_binary_plus() {
     let expr = BinaryExpr {
         token: Token {
             token_type: TokenType::Plus,
-            value: "".to_string(),
+            value: Symbol::intern(""),
             line: 0,
         },
-        lhs: Box::new(LiteralExpr { token_type: TokenType::String, value: "hello ".to_string() }),
-        rhs: Box::new(LiteralExpr { token_type: TokenType::String, value: "world".to_string() }),
+        lhs: Box::new(LiteralExpr { token_type: TokenType::String, value: Symbol::intern("hello ") }),
+        rhs: Box::new(LiteralExpr { token_type: TokenType::String, value: Symbol::intern("world") }),
     };
     let mut visitor = ExpressionInterpreter::new();
-    assert_eq!(visitor.eval(expr).str, "hello world");
+    assert_eq!(&*visitor.eval(&expr).unwrap().str, "hello world");
+}
+
+#[test]
+fn string_literal_shares_text() {
+    let expr = LiteralExpr { token_type: TokenType::String, value: Symbol::intern("shared") };
+    let visitor = ExpressionInterpreter::new();
+    let first = visitor.eval(&expr).unwrap();
+    let copy = ExpressionRes::copy(&first);
+    assert!(Rc::ptr_eq(&first.str, &visitor.eval(&expr).unwrap().str));
+    assert!(Rc::ptr_eq(&first.str, &copy.str));
 }
 
 
@@ -46,26 +57,20 @@ fn string_binary_plus() {
 fn visitor_test() {
     let token = Token {
         token_type: TokenType::Minus,
-        value: "".to_string(),
+        value: Symbol::intern(""),
         line: 0,
     };
     let equality =  Box::new(BinaryExpr {
         token,
-        rhs: Box::new(LiteralExpr {
-            token_type: TokenType::Number,
-            value: "10".to_string(),
-        }),
-        lhs:  Box::new(LiteralExpr {
-            token_type: TokenType::Number,
-            value: "1".to_string(),
-        }),
+        rhs: Box::new(NumberExpr { value: 10 }),
+        lhs:  Box::new(NumberExpr { value: 1 }),
     });
     let expr = Expr {
         value: String::from("here"),
         equality: Some(equality.clone()),
     };
 
-    println!("{:?}", get_visitor().eval(*equality))
+    println!("{:?}", get_visitor().eval(&equality))
 }
 
 #[test]
@@ -76,7 +81,7 @@ fn logical_test() {
         lhs: get_true_literal(),
     });
 
-    assert!(get_visitor().eval(*logical_false.clone()).boolean);
+    assert!(!get_visitor().eval(&logical_false).unwrap().boolean);
 
     let logical_true = Box::new(Logical {
         token: Token::new(TokenType::And, "".to_string(), 0),
@@ -85,15 +90,15 @@ fn logical_test() {
     });
 
 
-    println!("{:#?}", get_visitor().eval(*logical_true.clone()));
-    assert!(get_visitor().eval(*logical_true.clone()).boolean);
+    println!("{:#?}", get_visitor().eval(&logical_true));
+    assert!(get_visitor().eval(&logical_true).unwrap().boolean);
 
     let logical = Logical {
         token: Token::new(TokenType::Or, "".to_string(), 0),
         rhs: logical_false,
         lhs: logical_true,
     };
-    assert!(get_visitor().eval(logical).boolean);
+    assert!(get_visitor().eval(&logical).unwrap().boolean);
 }
 
 #[test]
@@ -115,7 +120,7 @@ fn get_statements(statement: String) -> Vec<Box<Statement>> {
 
 
 fn get_false_literal() ->  Box<Expression>{
-    Box::new(LiteralExpr { token_type: TokenType::False, value: "".to_string() })
+    Box::new(LiteralExpr { token_type: TokenType::False, value: Symbol::intern("") })
 }
 
 fn get_visitor() -> ExpressionInterpreter {
@@ -123,5 +128,5 @@ fn get_visitor() -> ExpressionInterpreter {
 }
 
 fn get_true_literal() ->  Box<Expression> {
-    Box::new(LiteralExpr { token_type: TokenType::True, value: "".to_string() })
+    Box::new(LiteralExpr { token_type: TokenType::True, value: Symbol::intern("") })
 }
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+/// Text of an identifier or literal, stored once and shared by the scanner, parser and runtime.
+/// Symbols compare and hash as numbers. Like the heap counters they belong to the thread that interned them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+// interned first, in this order, so they have the fixed symbols below
+const PREDEFINED: [&str; 17] = [
+    "", "nil", "toString", "iterator", "hasNext", "next",
+    "__add", "__sub", "__mul", "__div", "__mod", "__eq", "__lt", "__le", "__gt", "__ge", "__neg",
+];
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner { symbols: HashMap::new(), names: vec![] };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+}
+
+impl Symbol {
+    pub const EMPTY: Symbol = Symbol(0);
+    pub const NIL: Symbol = Symbol(1);
+    // methods the runtime looks up on instances
+    pub const TO_STRING: Symbol = Symbol(2);
+    pub const ITERATOR: Symbol = Symbol(3);
+    pub const HAS_NEXT: Symbol = Symbol(4);
+    pub const NEXT: Symbol = Symbol(5);
+    pub const ADD: Symbol = Symbol(6);
+    pub const SUB: Symbol = Symbol(7);
+    pub const MUL: Symbol = Symbol(8);
+    pub const DIV: Symbol = Symbol(9);
+    pub const MOD: Symbol = Symbol(10);
+    pub const EQ: Symbol = Symbol(11);
+    pub const LT: Symbol = Symbol(12);
+    pub const LE: Symbol = Symbol(13);
+    pub const GT: Symbol = Symbol(14);
+    pub const GE: Symbol = Symbol(15);
+    pub const NEG: Symbol = Symbol(16);
+
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    /// The interned text, shared instead of copied.
+    pub fn as_str(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+// shown as the text, so token and AST dumps read the same as before interning
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &*self.as_str())
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.as_str() == *other
+    }
+}
+
+#[test]
+fn test_intern() {
+    let name = Symbol::intern("counter");
+    assert_eq!(name, Symbol::intern(&String::from("counter")));
+    assert_ne!(name, Symbol::intern("count"));
+    assert!(Rc::ptr_eq(&name.as_str(), &Symbol::intern("counter").as_str()));
+    assert_eq!(Symbol::intern("nil"), Symbol::NIL);
+    assert_eq!(Symbol::intern("hasNext"), Symbol::HAS_NEXT);
+    assert_eq!(Symbol::intern("__neg"), Symbol::NEG);
+    assert_eq!(format!("{} {:?}", name, name), "counter \"counter\"");
+}
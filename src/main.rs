@@ -1,16 +1,29 @@
 #![allow(warnings, unused)]
 extern crate core;
 
-use log::{LevelFilter, SetLoggerError, trace};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use log::{LevelFilter, SetLoggerError};
 use simple_logger::SimpleLogger;
 use crate::parser::Parser;
+use crate::program::errors;
+use crate::program::execution::Limits;
+use crate::statements::printer::print_program;
 use crate::statements::statement::Statement;
 use crate::statements::stmt_visitor::StatementInterpreter;
 use crate::token::{Scanner, Token};
+use crate::vm::chunk::Function;
+use crate::vm::compiler::Compiler;
+use crate::vm::disassembler::disassemble;
+use crate::vm::loxc;
+use crate::vm::vm::Vm;
 
 mod expressions;
 mod parser;
 mod token;
+mod interner;
 #[cfg(test)]
 mod token_test;
 mod statements;
@@ -18,7 +31,10 @@ mod env;
 mod program;
 #[cfg(test)]
 mod parser_tests;
+#[cfg(test)]
+mod backend_tests;
 mod resolver_visitor;
+mod vm;
 
 fn get_class() -> String  {
     "class Hello {
@@ -34,12 +50,160 @@ fn get_class() -> String  {
     ".to_string()
 }
 
+// every Lox call nests several visitor calls, so the interpreter gets a stack deep enough for a deeper call limit
+// than the default, even in debug builds where a call can take tens of kilobytes of it
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+const MAX_DEPTH: usize = 5000;
+
+const USAGE: &str = "Usage: interpreter [--vm] [--dump-tokens] [--dump-ast] [--dump-bytecode] [script.lox | script.loxc]
+       interpreter compile script.lox [output.loxc]
+The dump options print what the scanner, parser or compiler made of the script instead of running it.
+With --vm, or when given a .loxc file, the script runs from its precompiled form while that matches the source.";
+
+#[derive(Default)]
+struct Options {
+    use_vm: bool,
+    dump_tokens: bool,
+    dump_ast: bool,
+    dump_bytecode: bool,
+    script: Option<String>,
+}
+
 fn main() {
     init();
-    let program = get_statement(get_class());
-    debug(&program);
-    let mut interpreter = StatementInterpreter::new_default();
-    interpreter.interpret(program);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("compile") {
+        compile(&args[1..]);
+        return;
+    }
+    let mut options = Options::default();
+    for arg in args {
+        match arg.as_str() {
+            "--vm" => options.use_vm = true,
+            "--dump-tokens" => options.dump_tokens = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--dump-bytecode" => options.dump_bytecode = true,
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown option {}\n{}", flag, USAGE);
+                std::process::exit(64);
+            }
+            _ if options.script.is_none() => options.script = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(64);
+            }
+        }
+    }
+    // a compiled script is run from the source next to it when that changed
+    let path = options.script.as_ref().map(PathBuf::from);
+    let precompiled = path.as_ref().map_or(false, |path| path.extension() == Some(OsStr::new("loxc")));
+    options.use_vm |= precompiled;
+    let source = match &path {
+        None => Some(get_class()),
+        Some(path) if precompiled => std::fs::read_to_string(path.with_extension("lox")).ok(),
+        Some(path) => Some(read_source(path)),
+    };
+    let interpreter = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let cached = match &path {
+                Some(path) if options.use_vm && !options.dump_tokens && !options.dump_ast => loxc::load(&loxc::cache_path(path), source.as_deref()),
+                _ => Err("no compiled script".to_string()),
+            };
+            let source = match (source, cached) {
+                (_, Ok(script)) => return run_compiled(&options, script),
+                (Some(source), Err(_)) => source,
+                (None, Err(error)) => {
+                    eprintln!("Could not load {}: {}", options.script.as_ref().unwrap(), error);
+                    std::process::exit(66);
+                }
+            };
+            let tokens = scan(source);
+            if options.dump_tokens {
+                println!("{}", dump_tokens(&tokens));
+            }
+            let program = parse(tokens);
+            if options.dump_ast {
+                println!("{}", print_program(&program));
+            }
+            if options.dump_bytecode || options.use_vm {
+                return run_compiled(&options, compile_program(&program));
+            }
+            if options.dump_tokens || options.dump_ast {
+                return;
+            }
+            let mut interpreter = StatementInterpreter::new_default();
+            interpreter.set_max_depth(MAX_DEPTH);
+            if let Err(error) = interpreter.run(program) {
+                eprintln!("{}", error.report());
+                std::process::exit(70);
+            }
+        })
+        .unwrap();
+    interpreter.join().unwrap();
+}
+
+fn run_compiled(options: &Options, script: Rc<Function>) {
+    if options.dump_bytecode {
+        print!("{}", disassemble(&script));
+    }
+    if options.dump_tokens || options.dump_ast || options.dump_bytecode {
+        return;
+    }
+    let mut vm = Vm::new();
+    vm.set_max_depth(MAX_DEPTH);
+    if let Err(error) = vm.run_function(script) {
+        eprintln!("{}", error.report());
+        std::process::exit(70);
+    }
+}
+
+// writes the compiled script to a .loxc file, next to the source unless an output is given
+fn compile(args: &[String]) {
+    let (script, output) = match args {
+        [script] => (PathBuf::from(script), loxc::cache_path(Path::new(script))),
+        [script, output] => (PathBuf::from(script), PathBuf::from(output)),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(64);
+        }
+    };
+    let source = read_source(&script);
+    let hash = loxc::source_hash(&source);
+    let program = get_statement(source);
+    let bytes = loxc::serialize(&compile_program(&program), hash);
+    if let Err(error) = std::fs::write(&output, bytes) {
+        eprintln!("Could not write {}: {}", output.display(), error);
+        std::process::exit(74);
+    }
+}
+
+fn compile_program(program: &[Box<Statement>]) -> Rc<Function> {
+    Compiler::new().compile(program).unwrap_or_else(|error| {
+        eprintln!("{}", error.report());
+        std::process::exit(65);
+    })
+}
+
+fn scan(source: String) -> Vec<Token> {
+    Scanner::new().scan(source).unwrap_or_else(|error| {
+        eprintln!("{}", error.report());
+        std::process::exit(65);
+    })
+}
+
+fn parse(tokens: Vec<Token>) -> Vec<Box<Statement>> {
+    Parser::new(tokens).parse().unwrap_or_else(|error| {
+        eprintln!("{}", error.report());
+        std::process::exit(65);
+    })
+}
+
+fn read_source(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {}", path.display(), error);
+        std::process::exit(66);
+    })
 }
 
 pub fn init() -> Result<(), SetLoggerError> {
@@ -47,23 +211,16 @@ pub fn init() -> Result<(), SetLoggerError> {
         .map(|()| log::set_max_level(LevelFilter::Debug))
 }
 
-fn debug(p: &Vec<Box<Statement>>) {
-    for statement in p {
-        trace!("{:#?}", statement)
-    }
-}
-fn debug_token(p: &Vec<Token>) {
-    for statement in p {
-        println!("{:#?}", statement)
-    }
+// one token per line with its source line, counted from 1
+fn dump_tokens(tokens: &[Token]) -> String {
+    let lines: Vec<String> = tokens.iter()
+        .map(|token| format!("{:>4} {:<14} {}", token.line + 1, format!("{:?}", token.token_type), token.value))
+        .collect();
+    lines.join("\n")
 }
 
 fn get_statement(program: String) -> Vec<Box<Statement>> {
-    let vec = Scanner::new().tokenize_string(String::from(program));
-    // debug_token(&vec);
-    let mut parser = Parser::new(vec);
-    let program = parser.program();
-    program
+    parse(scan(program))
 }
 
 fn return_fn() -> String {
@@ -1,33 +1,62 @@
 use std::collections::LinkedList;
-use log::trace;
+use std::rc::Rc;
 
+use crate::env::environment::Slot;
+use crate::program::errors::SyntaxError;
 use crate::expressions::expression::{Expression, ExpressionRes};
-use crate::expressions::expression::Expression::{Assignment, BinaryExpr, Call, Get, GroupingExpr, LiteralExpr, Logical, UnaryExpr, VariableExpr};
+use crate::expressions::expression::Expression::{Assignment, BinaryExpr, Call, CompoundAssignment, Conditional, Get, GroupingExpr, Increment, Index, Interpolation, Lambda, ListExpr, LiteralExpr, MapExpr, NumberExpr, Logical, UnaryExpr, VariableExpr};
 use crate::statements::statement::Statement;
-use crate::statements::statement::Statement::{BlockStatement, ClassDeclaration, ForStatement, FunStatement, IfStatement, ReturnStatement, Stmt, WhileStatement};
+use crate::statements::statement::Statement::{BlockStatement, ClassDeclaration, ForInStatement, ForStatement, FunStatement, IfStatement, ReturnStatement, Stmt, ThrowStatement, TryStatement, WhileStatement};
 use crate::token::{Scanner, Token, TokenType};
-use crate::token::TokenType::{And, Comma, Dot, Else, Equal, Identifier, LeftBrace, LeftParen, Or, RightBrace, RightParen, Semicolon};
+use crate::token::TokenType::{And, Catch, Colon, Comma, Dot, Else, Equal, Finally, Identifier, In, LeftBrace, LeftBracket, LeftParen, MinusMinus, Or, PlusPlus, Question, RightBrace, RightBracket, RightParen, Semicolon};
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     size: usize,
-
+    // stands in for the current token once the tokens run out
+    eof: Token,
+    // the first mistake found, parsing stops as soon as it is set
+    error: Option<SyntaxError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
         let i = tokens.len();
+        let line = tokens.last().map_or(0, |token| token.line);
         Parser {
             tokens,
             current: 0,
             size: i,
+            eof: Token::new(TokenType::EOF, "".to_string(), line),
+            error: None,
         }
     }
 
+    /// Statements of the program, or the first mistake in it.
+    pub fn parse(&mut self) -> Result<Vec<Box<Statement>>, SyntaxError> {
+        let declarations = self.program_declarations();
+        match self.error.take() {
+            None => Ok(declarations),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Like parse, but panics with the report of a mistake.
     pub fn program(&mut self) -> Vec<Box<Statement>> {
+        match self.parse() {
+            Ok(declarations) => declarations,
+            Err(error) => panic!("{}", error.report()),
+        }
+    }
+
+    fn program_declarations(&mut self) -> Vec<Box<Statement>> {
         let mut declarations = Vec::new();
-        while self.current < self.size && self.get_current().token_type != TokenType::EOF {
+        while self.error.is_none() && self.current < self.size && self.get_current().token_type != TokenType::EOF {
+            if self.peek_next(Semicolon) {
+                self.advance();
+                continue;
+            }
             match self.declaration() {
                 Some(value) => declarations.push(value),
                 None => continue,
@@ -52,19 +81,22 @@ impl Parser {
 
     fn variable_declaration(&mut self) -> Option<Box<Statement>> {
         self.advance();
+        if !self.peek_next(Identifier) {
+            return self.fail("Expected a variable name after var".to_string());
+        }
         let option = self.primary();
         if self.get_current().token_type == TokenType::Equal {
             self.advance();
             let expression = self.expression();
-            self.consume(Semicolon, "Variable initialization without semicolon".to_string());
+            self.consume(Semicolon, "Expected ; after the variable value".to_string());
             Some(Box::new(Statement::VarDeclaration {
                 expr: expression,
                 identifier: option.unwrap(),
             }))
         } else {
-            self.consume(Semicolon, "Declaration without semicolon".to_string());
+            self.consume(Semicolon, "Expected ; after the variable name".to_string());
             Some(Box::new(Statement::VarDeclaration {
-                expr: Some(Box::new(VariableExpr { token_type: TokenType::Nil, value: "".to_string() })),
+                expr: None,
                 identifier: option.unwrap(),
             }))
         }
@@ -74,16 +106,16 @@ impl Parser {
         self.advance();
         let identifier = match self.get_current().token_type {
             TokenType::Identifier => { self.get_current().clone() }
-            _ => { panic!("no identifier after fn declaration found: {:#?}", self.get_current()) }
+            _ => { return self.fail("Expected a class name after class".to_string()) }
         };
         self.advance();
         if self.peek_next(LeftBrace) {
             self.advance()
         } else {
-            panic!("Did not find lef brace after identifier")
+            return self.fail("Expected { after the class name".to_string());
         }
         let mut functions = vec![];
-        while (!self.peek_next(RightBrace)) {
+        while self.error.is_none() && !self.peek_next(RightBrace) && !self.peek_next(TokenType::EOF) {
             match self.function() {
                 None => { break; }
                 Some(value) => {
@@ -95,16 +127,16 @@ impl Parser {
         if self.peek_next(RightBrace) {
             self.advance()
         } else {
-            panic!("Did not find lef brace after identifier")
+            return self.fail("Expected } after the class body".to_string());
         }
-        Some(Box::new(ClassDeclaration { identifier, functions }))
+        Some(Box::new(ClassDeclaration { identifier, functions, slot: Slot::Global }))
     }
 
     pub fn statement_get(&mut self) -> Option<Box<Statement>> {
         match self.get_current().token_type {
             TokenType::Print => self.print_statement(),
             TokenType::If => self.if_statement(),
-            TokenType::Fun => {
+            TokenType::Fun if self.peek_after(Identifier) => {
                 self.advance();
                 self.function()
             }
@@ -112,80 +144,153 @@ impl Parser {
             TokenType::For => self.for_loop(),
             TokenType::LeftBrace => self.block(),
             TokenType::Return => self.return_stmt(),
+            TokenType::Throw => self.throw_statement(),
+            TokenType::Try => self.try_statement(),
+            TokenType::Semicolon => {
+                self.advance();
+                Some(Box::new(BlockStatement { statements: Default::default() }))
+            }
             _ => self.expression_statement(),
         }
     }
 
     pub fn if_statement(&mut self) -> Option<Box<Statement>> {
+        let line = self.get_current().line;
         self.advance();
-        self.consume(LeftParen, "Expected a brace before condition".to_string());
-        let expr = *self.expression().unwrap();
-        self.consume(RightParen, "Expected a brace after condition".to_string());
-        let body = self.block().unwrap();
+        self.consume(LeftParen, "Expected ( before the condition".to_string());
+        let expr = *self.require(Parser::expression, "Expected a condition after if")?;
+        self.consume(RightParen, "Expected ) after the condition".to_string());
+        let body = self.require(Parser::statement_get, "Expected a body after the if condition")?;
+        let mut else_body = None;
         if self.peek_next(Else) {
             self.advance();
-            if self.peek_next(LeftBrace) {
-                match self.block() {
-                    None => { panic!(r" blcok after {{ in if statement"); }
-                    Some(value) => {
-                        return Some(Box::new(IfStatement { expr, body, else_body: Some(value) }));
-                    }
-                }
-            } else {
-                panic!(r"missing {{ after else ");
+            else_body = Some(self.require(Parser::statement_get, "Expected a body after else")?);
+        }
+        Some(Box::new(IfStatement { expr, body, else_body, line }))
+    }
+
+    fn throw_statement(&mut self) -> Option<Box<Statement>> {
+        let line = self.get_current().line;
+        self.advance();
+        let expr = self.require(Parser::expression, "Expected a value after throw")?;
+        self.consume(Semicolon, "Expected ; after thrown value".to_string());
+        Some(Box::new(ThrowStatement { expr, line }))
+    }
+
+    // try { } catch (e) { } finally { }, where either catch or finally may be left out
+    fn try_statement(&mut self) -> Option<Box<Statement>> {
+        self.advance();
+        let body = self.try_block("try")?;
+        let mut catch_name = None;
+        let mut catch_body = None;
+        if self.peek_next(Catch) {
+            self.advance();
+            self.consume(LeftParen, "Expected ( after catch".to_string());
+            if !self.peek_next(Identifier) {
+                return self.fail("Expected a name for the caught error".to_string());
             }
+            catch_name = Some(self.get_current().clone());
+            self.advance();
+            self.consume(RightParen, "Expected ) after caught error name".to_string());
+            catch_body = Some(self.try_block("catch")?);
+        }
+        let mut finally_body = None;
+        if self.peek_next(Finally) {
+            self.advance();
+            finally_body = Some(self.try_block("finally")?);
+        }
+        if catch_body.is_none() && finally_body.is_none() {
+            return self.fail("Expected catch or finally after the try block".to_string());
         }
-        Some(Box::new((IfStatement { expr, body, else_body: None })))
+        Some(Box::new(TryStatement { body, catch_name, catch_body, finally_body }))
+    }
+
+    fn try_block(&mut self, clause: &str) -> Option<Box<Statement>> {
+        if !self.peek_next(LeftBrace) {
+            return self.fail(format!("Expected a block after {}", clause));
+        }
+        self.block()
     }
 
     fn function(&mut self) -> Option<Box<Statement>> {
         let identifier = match self.get_current().token_type {
             TokenType::Identifier => { self.get_current().clone() }
-            _ => { panic!("no identifier after fn declaration found: {:#?}", self.get_current()) }
+            _ => { return self.fail("Expected a function name".to_string()) }
         };
         self.advance();
 
-        self.consume(LeftParen, "please define function (".to_string());
+        let args = self.parameters()?;
+        if !self.peek_next(LeftBrace) {
+            return self.fail("Expected a block after the function parameters".to_string());
+        }
+        let block = self.block()?;
+        Some(Box::new(FunStatement {
+            identifier,
+            args,
+            block: Some(Rc::from(block)),
+            slot: Slot::Global,
+        }))
+    }
+
+    fn lambda(&mut self) -> Option<Box<Expression>> {
+        self.advance();
+        let args = self.parameters()?;
+        if !self.peek_next(LeftBrace) {
+            return self.fail("Expected a block after the function parameters".to_string());
+        }
+        let block = self.block()?;
+        Some(Box::new(Lambda { args, block: Rc::from(block) }))
+    }
+
+    fn parameters(&mut self) -> Option<Vec<Expression>> {
+        self.consume(LeftParen, "Expected ( before the parameters".to_string());
         let mut args = Vec::<Expression>::new();
         while !self.peek_next(RightParen) {
-            args.push(*self.expression().unwrap());
+            if !self.peek_next(Identifier) {
+                return self.fail("Expected a parameter name".to_string());
+            }
+            args.push(*self.primary()?);
             if self.peek_next(TokenType::Comma) {
                 self.advance();
                 if self.peek_next(RightParen) {
-                    panic!("found rightparen after comman in fun declaration");
+                    return self.fail("Expected a parameter name after ,".to_string());
                 }
+            } else if !self.peek_next(RightParen) {
+                return self.fail("Expected , or ) after a parameter".to_string());
             }
         }
-        self.consume(RightParen, "please define function with )".to_string());
-        match self.block() {
-            None => { panic!("there should be block after function )") }
-            Some(value) => {
-                Some(Box::new(FunStatement {
-                    identifier,
-                    args,
-                    block: Some(value),
-                }))
-            }
-        }
+        self.consume(RightParen, "Expected ) after the parameters".to_string());
+        Some(args)
     }
 
     fn while_block(&mut self) -> Option<Box<Statement>> {
+        let line = self.get_current().line;
         self.advance();
-        self.consume(LeftParen, "Expected a brace before condition".to_string());
-        let expr = self.expression().unwrap();
-        self.consume(RightParen, "Expected a brace after condition".to_string());
-        let statements = self.declaration().unwrap();
-        Some(Box::new(WhileStatement { expr, body: statements }))
+        self.consume(LeftParen, "Expected ( before the condition".to_string());
+        let expr = self.require(Parser::expression, "Expected a condition after while")?;
+        self.consume(RightParen, "Expected ) after the condition".to_string());
+        let statements = self.require(Parser::declaration, "Expected a body after the while condition")?;
+        Some(Box::new(WhileStatement { expr, body: statements, line }))
     }
 
     fn for_loop(&mut self) -> Option<Box<Statement>> {
+        let line = self.get_current().line;
         self.advance();
-        self.consume(LeftParen, "Expected a brace before condition".to_string());
+        self.consume(LeftParen, "Expected ( before the condition".to_string());
+        if self.peek_next(Identifier) && self.peek_after(In) {
+            return self.for_in_loop();
+        }
+        if self.peek_next(TokenType::Var) && self.peek_after(Identifier)
+            && self.current + 2 < self.size && self.tokens[self.current + 2].token_type == In {
+            self.advance();
+            return self.for_in_loop();
+        }
         let initiation = match self.get_current().token_type {
             TokenType::Var => {
                 self.declaration()
             }
             TokenType::Semicolon => {
+                self.advance();
                 None
             }
             _ => {
@@ -197,45 +302,56 @@ impl Parser {
         if !self.peek_next(Semicolon) {
             condition = self.expression_statement();
         } else {
-            self.consume(Semicolon, "ok".to_string());
+            self.advance();
         }
 
         let mut increment = None;
-        if !self.peek_next(Semicolon) {
-            increment = self.expression_statement();
-        } else {
-            self.consume(Semicolon, "ok".to_string());
+        if !self.peek_next(RightParen) {
+            increment = Some(Box::new(Stmt {
+                expr: self.require(Parser::expression, "Expected an increment after the condition")?,
+            }));
         }
 
-        self.consume(RightParen, "missing parenthesis after for loop".to_string());
+        self.consume(RightParen, "Expected ) after the for clauses".to_string());
 
-        let body = self.declaration().unwrap();
-        let mut else_body = None;
-        if self.peek_next(Else) {
-            self.advance();
-            else_body = self.declaration();
-        }
+        let body = self.require(Parser::statement_get, "Expected a body after the for clauses")?;
         Some(Box::new(ForStatement {
             initiation,
             condition,
             increment,
             body,
+            line,
         }))
     }
 
+    fn for_in_loop(&mut self) -> Option<Box<Statement>> {
+        let identifier = self.get_current().clone();
+        self.advance();
+        self.consume(In, "Expected in after loop variable".to_string());
+        let iterable = self.require(Parser::expression, "Expected a value to loop over after in")?;
+        self.consume(RightParen, "Expected ) after the for clauses".to_string());
+        let body = self.require(Parser::statement_get, "Expected a body after the for clauses")?;
+        Some(Box::new(ForInStatement { identifier, iterable, body }))
+    }
+
     pub fn print_statement(&mut self) -> Option<Box<Statement>> {
         self.advance();
-        let expression = self.expression();
-        self.consume(Semicolon, "Print statement".to_string());
+        let expression = self.require(Parser::expression, "Expected a value after print")?;
+        self.consume(Semicolon, "Expected ; after the printed value".to_string());
 
-        Some(Box::new(Statement::PrintStatement { expr: expression.unwrap() }))
+        Some(Box::new(Statement::PrintStatement { expr: expression }))
     }
 
     pub fn block(&mut self) -> Option<Box<Statement>> {
         self.advance();
         let mut list: LinkedList<Box<Statement>> = Default::default();
 
-        while self.current < self.size && self.get_current().token_type != TokenType::RightBrace {
+        while self.error.is_none() && self.current < self.size
+            && self.get_current().token_type != TokenType::RightBrace && self.get_current().token_type != TokenType::EOF {
+            if self.peek_next(Semicolon) {
+                self.advance();
+                continue;
+            }
             match self.declaration() {
                 Some(value) => list.push_back(value),
                 None => continue,
@@ -245,7 +361,7 @@ impl Parser {
         if self.get_current().token_type == TokenType::RightBrace {
             self.advance()
         } else {
-            println!("found in block statement")
+            return self.fail("Expected } after the block".to_string());
         }
 
         Some(Box::new(BlockStatement { statements: list }))
@@ -258,11 +374,11 @@ impl Parser {
             self.advance();
             return Some(Box::new(ReturnStatement { expr: None }));
         };
-        let option = self.expression().unwrap();
+        let option = self.require(Parser::expression, "Expected a value or ; after return")?;
         if self.peek_next(Semicolon) {
             self.advance()
         } else {
-            panic!("there should be a semicolon at {:#?}", self.get_current())
+            return self.fail("Expected ; after the returned value".to_string());
         }
         Some(Box::new(ReturnStatement { expr: Some(option) }))
     }
@@ -271,7 +387,7 @@ impl Parser {
         match self.expression() {
             None => { None }
             Some(value) => {
-                self.consume(Semicolon, "Ending of expression".to_string());
+                self.consume(Semicolon, "Expected ; after the expression".to_string());
                 Some(Box::new(
                     Stmt {
                         expr: value
@@ -290,7 +406,7 @@ impl Parser {
     }
 
     pub fn assignment(&mut self) -> Option<Box<Expression>> {
-        let lhs = match self.logic_or() {
+        let lhs = match self.conditional() {
             None => { return None; }
             Some(value) => {
                 value
@@ -300,18 +416,20 @@ impl Parser {
             return match self.get_current().token_type {
                 TokenType::Equal => {
                     self.advance();
-                    let value = self.assignment().unwrap();
+                    self.check_assignment_target(&lhs);
+                    let value = self.require(Parser::assignment, "Expected a value after =")?;
                     Some(Box::new(Assignment { identifier: lhs, value }))
                 }
-                TokenType::Or => {
+                TokenType::PlusEqual |
+                TokenType::MinusEqual |
+                TokenType::StarEqual |
+                TokenType::SlashEqual |
+                TokenType::PercentEqual => {
                     let token = self.get_current().clone();
                     self.advance();
-                    let rhs = self.logic_or();
-                    Some(Box::new(Logical {
-                        token,
-                        lhs,
-                        rhs: rhs.unwrap_or_else(|| panic!("there should be second part of bool expr after or")),
-                    }))
+                    self.check_assignment_target(&lhs);
+                    let value = self.require(Parser::assignment, "Expected a value after the operator")?;
+                    Some(Box::new(CompoundAssignment { identifier: lhs, token, value }))
                 }
                 _ => { Some(lhs) }
             };
@@ -319,40 +437,49 @@ impl Parser {
         None
     }
 
+    pub fn conditional(&mut self) -> Option<Box<Expression>> {
+        let condition = self.logic_or()?;
+        if !self.peek_next(Question) {
+            return Some(condition);
+        }
+        let line = self.get_current().line;
+        self.advance();
+        let then_branch = self.require(Parser::expression, "Expected an expression after ?")?;
+        if self.peek_next(Colon) {
+            self.advance();
+        } else {
+            return self.fail("Expected : in a conditional expression".to_string());
+        }
+        let else_branch = self.require(Parser::conditional, "Expected an expression after :")?;
+        Some(Box::new(Conditional { condition, then_branch, else_branch, line }))
+    }
+
     pub fn logic_or(&mut self) -> Option<Box<Expression>> {
         let mut lhs = self.logic_and();
-        if self.peek_next(Or) && lhs.is_some() {
+        while self.peek_next(Or) && lhs.is_some() {
             let token = self.get_current().clone();
             self.advance();
-            match self.logic_and() {
-                None => { panic!("invalid logic pattern, missing second expression") }
-                Some(rhs) => {
-                    lhs = Some(Box::new(Logical {
-                        token,
-                        lhs: lhs.unwrap(),
-                        rhs,
-                    }))
-                }
-            }
+            let rhs = self.require(Parser::logic_and, "Expected an expression after or")?;
+            lhs = Some(Box::new(Logical {
+                token,
+                lhs: lhs.unwrap(),
+                rhs,
+            }))
         }
         lhs
     }
 
     pub fn logic_and(&mut self) -> Option<Box<Expression>> {
         let mut lhs = self.equality();
-        if self.peek_next(And) && lhs.is_some() {
+        while self.peek_next(And) && lhs.is_some() {
             let token = self.get_current().clone();
             self.advance();
-            match self.equality() {
-                None => { panic!("invalid logic pattern, missing second expression") }
-                Some(rhs) => {
-                    lhs = Some(Box::new(Logical {
-                        token,
-                        lhs: lhs.unwrap(),
-                        rhs,
-                    }))
-                }
-            }
+            let rhs = self.require(Parser::equality, "Expected an expression after and")?;
+            lhs = Some(Box::new(Logical {
+                token,
+                lhs: lhs.unwrap(),
+                rhs,
+            }))
         }
         lhs
     }
@@ -371,7 +498,7 @@ impl Parser {
         } {
             let token = self.get_current().clone();
             self.advance();
-            let rhs = self.comparison().unwrap();
+            let rhs = self.require(Parser::comparison, "Expected an expression after the operator")?;
             lhs = Box::new(BinaryExpr { token, rhs, lhs });
         }
         Some(lhs)
@@ -390,9 +517,9 @@ impl Parser {
             TokenType::LessEqual => true,
             _ => false,
         } {
-            let token = self.tokens[self.current].clone();
+            let token = self.get_current().clone();
             self.advance();
-            let rhs = self.term().unwrap();
+            let rhs = self.require(Parser::term, "Expected an expression after the operator")?;
             lhs = Box::new(BinaryExpr { token, rhs, lhs });
         };
         Some(lhs)
@@ -411,7 +538,7 @@ impl Parser {
         } {
             let token = self.get_current().clone();
             self.advance();
-            let rhs = self.factor().unwrap();
+            let rhs = self.require(Parser::factor, "Expected an expression after the operator")?;
             lhs = Box::new(BinaryExpr { token, rhs, lhs });
         };
         Some(lhs)
@@ -423,37 +550,63 @@ impl Parser {
             Some(value) => { value }
         };
 
-        while self.current < self.size && match self.tokens[self.current].token_type {
+        while self.current < self.size && match self.get_current().token_type {
             TokenType::Slash |
             TokenType::Star |
             TokenType::Percent => true,
             _ => false,
         } {
-            let token = self.tokens[self.current].clone();
+            let token = self.get_current().clone();
             self.advance();
-            let rhs = self.unary().unwrap();
+            let rhs = self.require(Parser::unary, "Expected an expression after the operator")?;
             lhs = Box::new(BinaryExpr { token, rhs, lhs });
         };
         Some(lhs)
     }
 
     fn unary(&mut self) -> Option<Box<Expression>> {
-        while self.current < self.size && match self.tokens[self.current].token_type {
+        if self.current < self.size && match self.get_current().token_type {
             TokenType::Bang |
             TokenType::Minus => true,
             _ => false,
         } {
-            let token = self.tokens[self.current].clone();
+            let token = self.get_current().clone();
             self.advance();
-            let rhs = self.unary().unwrap();
+            let rhs = self.require(Parser::unary, "Expected an expression after the operator")?;
             return Some(Box::new(UnaryExpr { token, rhs }));
         };
-        return self.call();
+        if self.peek_next(PlusPlus) || self.peek_next(MinusMinus) {
+            let token = self.get_current().clone();
+            self.advance();
+            let identifier = self.require(Parser::unary, "Expected a variable after the operator")?;
+            self.check_assignment_target(&identifier);
+            return Some(Box::new(Increment { identifier, token, prefix: true }));
+        }
+        return self.postfix();
+    }
+
+    fn postfix(&mut self) -> Option<Box<Expression>> {
+        let expr = self.call();
+        if expr.is_some() && (self.peek_next(PlusPlus) || self.peek_next(MinusMinus)) {
+            let token = self.get_current().clone();
+            self.advance();
+            let identifier = expr.unwrap();
+            self.check_assignment_target(&identifier);
+            return Some(Box::new(Increment { identifier, token, prefix: false }));
+        }
+        expr
+    }
+
+    fn check_assignment_target(&mut self, target: &Expression) {
+        match target {
+            VariableExpr { .. } | Get { .. } | Index { .. } => {}
+            _ => { self.fail::<()>("Invalid assignment target".to_string()); }
+        }
     }
 
 
     fn call(&mut self) -> Option<Box<Expression>> {
-        let mut res = self.primary();
+        let mut res = Some(self.primary()?);
         loop {
             if self.peek_next(LeftParen) {
                 let mut args: Vec<Box<Expression>> = vec![];
@@ -466,23 +619,23 @@ impl Parser {
                             if !self.peek_next(Comma) {
                                 break;
                             }
-                            match self.expression() {
-                                None => {
-                                    panic!("found comma but not the argument");
-                                }
-                                Some(value) => {
-                                    args.push(value);
-                                }
-                            }
+                            self.advance();
+                            args.push(self.require(Parser::expression, "Expected an argument after ,")?);
                         }
                     }
                 }
                 if self.peek_next(RightParen) {
                     self.advance();
                 } else {
-                    panic!("did not find the brace after arguments, found {:?}", self.get_current());
+                    return self.fail("Expected ) after arguments".to_string());
                 }
-                res = Some(Box::new(Call { identifier: res.unwrap(), args }));
+                let line = self.tokens[self.current - 1].line;
+                res = Some(Box::new(Call { identifier: res.unwrap(), args, line }));
+            } else if self.peek_next(LeftBracket) {
+                self.advance();
+                let index = self.require(Parser::expression, "Expected an index after [")?;
+                self.consume(RightBracket, "Expected ] after index".to_string());
+                res = Some(Box::new(Index { expr: res.unwrap(), index }));
             } else if self.peek_next(Dot){
                 self.advance();
                 if self.peek_next(Identifier) {
@@ -490,7 +643,7 @@ impl Parser {
                     self.advance();
                     res = Some(Box::new(Get { expr: res.unwrap(), name: x }))
                 } else {
-                    trace!("there should be indentifier after .")
+                    return self.fail("Expected a property name after .".to_string());
                 }
 
             }
@@ -498,37 +651,78 @@ impl Parser {
                 return res
             }
         }
-        panic!("")
     }
 
     fn primary(&mut self) -> Option<Box<Expression>> {
-        let primary: Expression = match self.tokens[self.current].token_type {
+        let primary: Expression = match self.get_current().token_type {
             TokenType::False |
             TokenType::True |
             TokenType::Nil => {
-                let token = self.tokens[self.current].clone();
+                let token = self.get_current().clone();
                 self.advance();
                 LiteralExpr { token_type: token.token_type, value: token.value }
             }
-            TokenType::String |
-            TokenType::Number => {
-                let token = self.tokens[self.current].clone();
+            TokenType::String => {
+                let token = self.get_current().clone();
                 self.advance();
                 LiteralExpr { token_type: token.token_type, value: token.value }
             }
+            TokenType::Number => {
+                let token = self.get_current().clone();
+                let value = match str::parse::<isize>(&token.value.as_str()) {
+                    Ok(value) => value,
+                    Err(_) => return self.fail(format!("Number literal {} is too large", token.value)),
+                };
+                self.advance();
+                NumberExpr { value }
+            }
             TokenType::LeftParen => {
                 self.advance();
-                let expression = self.expression().unwrap();
-                if self.tokens[self.current].token_type != TokenType::RightParen {
-                    println!("Error, missing right brace {:?}", self.tokens[self.current])
+                let expression = self.require(Parser::expression, "Expected an expression after (")?;
+                self.consume(RightParen, "Expected ) after the expression".to_string());
+                GroupingExpr { value: expression }
+            }
+            TokenType::Interpolation => {
+                return self.interpolation();
+            }
+            TokenType::Fun => {
+                return self.lambda();
+            }
+            TokenType::LeftBracket => {
+                self.advance();
+                let mut items = vec![];
+                while !self.peek_next(RightBracket) {
+                    items.push(self.require(Parser::expression, "Expected a list item")?);
+                    if !self.peek_next(Comma) {
+                        break;
+                    }
+                    self.advance();
                 }
+                self.consume(RightBracket, "Expected ] after list items".to_string());
+                ListExpr { items }
+            }
+            // blocks are parsed as statements, so a brace in expression position is a map
+            TokenType::LeftBrace => {
                 self.advance();
-                GroupingExpr { value: expression }
+                let mut entries = vec![];
+                while !self.peek_next(RightBrace) {
+                    let key = self.require(Parser::expression, "Expected a map key")?;
+                    self.consume(Colon, "Expected : after map key".to_string());
+                    let value = self.require(Parser::expression, "Expected a map value after :")?;
+                    entries.push((key, value));
+                    if !self.peek_next(Comma) {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.consume(RightBrace, "Expected } after map entries".to_string());
+                MapExpr { entries }
             }
-            TokenType::Identifier => {
+            TokenType::Identifier |
+            TokenType::This => {
                 let token = self.get_current().clone();
                 self.advance();
-                VariableExpr { token_type: token.token_type, value: token.value }
+                VariableExpr { token_type: token.token_type, value: token.value, line: token.line, slot: Slot::Global }
             }
             _ => {
                 return None;
@@ -537,23 +731,71 @@ impl Parser {
         Some(Box::new(primary))
     }
 
+    // "a ${x} b" arrives as Interpolation("a "), the tokens of x, then String(" b"); mistakes
+    // are reported on the line of the string, since a bad ${} can swallow the String that ends it
+    fn interpolation(&mut self) -> Option<Box<Expression>> {
+        let line = self.get_current().line;
+        let mut parts = vec![];
+        while self.peek_next(TokenType::Interpolation) {
+            let token = self.get_current().clone();
+            self.advance();
+            parts.push(Box::new(LiteralExpr { token_type: TokenType::String, value: token.value }));
+            match self.expression() {
+                None => { return self.fail_at(line, "Invalid expression in string interpolation".to_string()) }
+                Some(value) => { parts.push(value) }
+            }
+        }
+        let token = self.get_current().clone();
+        if token.token_type != TokenType::String {
+            return self.fail_at(line, "Invalid expression in string interpolation".to_string());
+        }
+        self.advance();
+        parts.push(Box::new(LiteralExpr { token_type: TokenType::String, value: token.value }));
+        Some(Box::new(Interpolation { parts }))
+    }
+
     fn get_current(&self) -> &Token {
-        &self.tokens[self.current]
+        self.tokens.get(self.current).unwrap_or(&self.eof)
+    }
+
+    // the rule's result, or a mistake when there is nothing it can parse here
+    fn require<T>(&mut self, rule: fn(&mut Parser) -> Option<T>, message: &str) -> Option<T> {
+        let parsed = rule(self);
+        if parsed.is_none() {
+            return self.fail(message.to_string());
+        }
+        parsed
+    }
+
+    // records a mistake at the current token, so callers can give up with `?`
+    fn fail<T>(&mut self, message: String) -> Option<T> {
+        let line = self.get_current().line;
+        self.fail_at(line, message)
+    }
+
+    fn fail_at<T>(&mut self, line: usize, message: String) -> Option<T> {
+        if self.error.is_none() {
+            self.error = Some(SyntaxError::new(message, line));
+        }
+        None
+    }
+
+    fn peek_after(&self, token: TokenType) -> bool {
+        return self.current + 1 < self.size && &self.tokens[self.current + 1].token_type == &token;
     }
 
     fn peek_next(&self, token: TokenType) -> bool {
-        return self.current < self.size && &self.tokens[self.current].token_type == &token;
+        return self.current < self.size && &self.get_current().token_type == &token;
     }
 
     fn advance(&mut self) {
         self.current += 1;
     }
     fn consume(&mut self, token: TokenType, error: String) {
-        let x = self.get_current();
-        if self.current < self.size && x.token_type == token {
+        if self.peek_next(token) {
             self.advance();
         } else {
-            println!("Token {:?} found with : {}", x, error)
+            self.fail::<()>(error);
         }
     }
 }
\ No newline at end of file
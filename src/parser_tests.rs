@@ -56,6 +56,47 @@ fn parse_if_else() {
     println!("{:#?}", parser)
 }
 
+#[test]
+fn parse_if_without_braces() {
+    let x1 = "if (a) print 1; else print 2;";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 1);
+    match &*parser[0] {
+        IfStatement { body, else_body, .. } => {
+            assert!(matches!(**body, Statement::PrintStatement {..}));
+            assert!(matches!(**else_body.as_ref().unwrap(), Statement::PrintStatement {..}));
+        }
+        _ => panic!("expected if statement"),
+    }
+}
+
+#[test]
+fn parse_else_if_chain() {
+    let x1 =
+        "if (a) {
+        print 1;
+        } else if (b) {
+        print 2;
+        } else print 3;
+        print 4;";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 2);
+    match &*parser[0] {
+        IfStatement { else_body: Some(else_body), .. } => {
+            match &**else_body {
+                IfStatement { body, else_body: Some(last), .. } => {
+                    assert!(matches!(**body, BlockStatement {..}));
+                    assert!(matches!(**last, Statement::PrintStatement {..}));
+                }
+                _ => panic!("expected nested if statement after else"),
+            }
+        }
+        _ => panic!("expected if statement with else"),
+    }
+}
+
 #[test]
 fn parse_logical() {
     let x1 = "if (true or false) {
@@ -70,11 +111,24 @@ fn parse_logical() {
     assert!(matches!(*parser[0], IfStatement { ..}));
 }
 
+#[test]
+fn parse_for_increment() {
+    let x1 = "for (var i = 0; i < 3; i = i + 1) print i;";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).parse().unwrap();
+    match &*parser[0] {
+        ForStatement { increment: Some(increment), .. } => {
+            assert!(matches!(&**increment, Stmt { expr } if matches!(**expr, Expression::Assignment { .. })));
+        }
+        _ => panic!("expected for statement with an increment"),
+    }
+}
+
 #[test]
 fn parse_while() {
     let x1 =
         "while(x) {
-        print \"hello\"
+        print \"hello\";
         }";
     let vec = Scanner::new().tokenize_string(x1.to_string());
     let mut parser = Parser::new(vec).program();
@@ -87,7 +141,7 @@ fn parse_while() {
 fn parse_for() {
     let x1 =
         "for(var x = 0; x < 10; x = x + 1) {
-        print \"hello\"
+        print \"hello\";
         }";
     let vec = Scanner::new().tokenize_string(x1.to_string());
     let mut parser = Parser::new(vec).program();
@@ -146,7 +200,8 @@ fn assignment() {
     let mut parser = Parser::new(vec).program();
     println!("{:#?}", parser);
 
-    assert!(matches!(*parser[0], FunStatement {..}));
+    assert!(matches!(*parser[0], Statement::VarDeclaration {..}));
+    assert!(matches!(*parser[1], Stmt {..}));
 }
 
 
@@ -176,3 +231,172 @@ fn class_statement() {
 
 
 
+
+#[test]
+fn parse_conditional_right_associative() {
+    let statement = "x = a or b ? 1 : c ? 2 : 3;";
+    let vec = Scanner::new().tokenize_string(statement.to_string());
+    let parser = Parser::new(vec).program();
+    let Stmt { expr } = &*parser[0] else { panic!("expected expression statement") };
+    let Expression::Assignment { value, .. } = &**expr else { panic!("expected assignment") };
+    match &**value {
+        Expression::Conditional { condition, else_branch, .. } => {
+            assert!(matches!(**condition, Expression::Logical {..}));
+            assert!(matches!(**else_branch, Expression::Conditional {..}));
+        }
+        _ => panic!("expected conditional expression"),
+    }
+}
+
+#[test]
+fn parse_chained_logical() {
+    let statement = r#"print false or false or true ? "x" : "z";
+    print a and b and c or d;"#;
+    let vec = Scanner::new().tokenize_string(statement.to_string());
+    let parser = Parser::new(vec).parse().unwrap();
+    assert_eq!(crate::statements::printer::print_program(&parser),
+               "print (? (or (or false false) true) \"x\" \"z\")\nprint (or (and (and a b) c) d)");
+}
+
+#[test]
+fn parse_lambda() {
+    let statement = "var add = fun (a, b) { return a + b; };
+    fun (x) { print x; }(1);";
+    let vec = Scanner::new().tokenize_string(statement.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 2);
+    match &*parser[0] {
+        Statement::VarDeclaration { expr: Some(expr), .. } => {
+            match &**expr {
+                Expression::Lambda { args, block } => {
+                    assert_eq!(args.len(), 2);
+                    assert!(matches!(**block, BlockStatement {..}));
+                }
+                _ => panic!("expected lambda"),
+            }
+        }
+        _ => panic!("expected variable declaration"),
+    }
+    assert!(matches!(*parser[1], Stmt {..}));
+}
+
+#[test]
+fn parse_list_index_assignment() {
+    let statement = "xs[0] = [1, 2][1];";
+    let vec = Scanner::new().tokenize_string(statement.to_string());
+    let parser = Parser::new(vec).program();
+    let Stmt { expr } = &*parser[0] else { panic!("expected expression statement") };
+    match &**expr {
+        Expression::Assignment { identifier, value } => {
+            assert!(matches!(**identifier, Expression::Index {..}));
+            match &**value {
+                Expression::Index { expr, .. } => {
+                    assert!(matches!(**expr, Expression::ListExpr {..}));
+                }
+                _ => panic!("expected index expression"),
+            }
+        }
+        _ => panic!("expected assignment"),
+    }
+}
+
+#[test]
+fn parse_map_literal_and_block() {
+    let statement = "var m = {\"a\": 1, 2: \"b\"};
+    { var x = m; }";
+    let vec = Scanner::new().tokenize_string(statement.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 2);
+    match &*parser[0] {
+        Statement::VarDeclaration { expr: Some(expr), .. } => {
+            match &**expr {
+                Expression::MapExpr { entries } => assert_eq!(entries.len(), 2),
+                _ => panic!("expected map literal"),
+            }
+        }
+        _ => panic!("expected variable declaration"),
+    }
+    assert!(matches!(*parser[1], BlockStatement {..}));
+}
+
+#[test]
+fn parse_for_in() {
+    let x1 = "for (var x in xs) print x;
+    for (y in ys) { print y; }";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 2);
+    assert!(matches!(*parser[0], Statement::ForInStatement {..}));
+    match &*parser[1] {
+        Statement::ForInStatement { identifier, body, .. } => {
+            assert_eq!(identifier.value, "y");
+            assert!(matches!(**body, BlockStatement {..}));
+        }
+        _ => panic!("expected for in statement"),
+    }
+}
+
+#[test]
+fn parse_string_interpolation() {
+    let x1 = r#"print "x = ${x + 1}!";"#;
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).program();
+    match &*parser[0] {
+        Statement::PrintStatement { expr } => match &**expr {
+            Expression::Interpolation { parts } => {
+                assert_eq!(parts.len(), 3);
+                assert!(matches!(*parts[1], Expression::BinaryExpr {..}));
+            }
+            _ => panic!("expected interpolation"),
+        },
+        _ => panic!("expected print statement"),
+    }
+}
+
+#[test]
+fn parse_broken_interpolation() {
+    let x1 = "var x = 1;\nprint \"x${ 1 + }\";";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let error = Parser::new(vec).parse().unwrap_err();
+    assert_eq!(error.report(), "[line 2] Error: Invalid expression in string interpolation");
+}
+
+#[test]
+fn parse_number_too_large() {
+    let x1 = "var x = 9223372036854775808;";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let error = Parser::new(vec).parse().unwrap_err();
+    assert_eq!(error.report(), "[line 1] Error: Number literal 9223372036854775808 is too large");
+}
+
+#[test]
+fn parse_parameters_need_commas() {
+    for source in ["fun f(a b) { return a; }", "var f = fun (a b) { return a; };"] {
+        let vec = Scanner::new().tokenize_string(source.to_string());
+        let error = Parser::new(vec).parse().unwrap_err();
+        assert_eq!(error.report(), "[line 1] Error: Expected , or ) after a parameter");
+    }
+}
+
+#[test]
+fn parse_try_catch_finally() {
+    let x1 = "try { throw \"x\"; } catch (e) { print e; } finally { print 1; }
+    try { f(); } finally { }";
+    let vec = Scanner::new().tokenize_string(x1.to_string());
+    let parser = Parser::new(vec).program();
+    assert_eq!(parser.len(), 2);
+    match &*parser[0] {
+        Statement::TryStatement { body, catch_name, finally_body, .. } => {
+            assert_eq!(catch_name.as_ref().unwrap().value, "e");
+            assert!(finally_body.is_some());
+            match &**body {
+                BlockStatement { statements } => {
+                    assert!(matches!(**statements.front().unwrap(), Statement::ThrowStatement { line: 0, .. }));
+                }
+                _ => panic!("expected try block"),
+            }
+        }
+        _ => panic!("expected try statement"),
+    }
+    assert!(matches!(*parser[1], Statement::TryStatement { catch_body: None, finally_body: Some(_), .. }));
+}
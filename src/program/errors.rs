@@ -0,0 +1,156 @@
+use std::cell::{Cell, RefCell};
+
+use crate::env::environment::Environment;
+use crate::interner::Symbol;
+use crate::expressions::expression::{ExpressionRes, ExprResType};
+use crate::program::runtime::Class;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Runtime,
+    OutOfMemory,
+    // limits set by the host, these stop the program and cannot be caught by the script
+    OutOfFuel,
+    Timeout,
+}
+
+impl ErrorKind {
+    pub fn is_catchable(&self) -> bool {
+        matches!(self, ErrorKind::Runtime | ErrorKind::OutOfMemory)
+    }
+}
+
+/// A mistake in the source found by the scanner or the parser, before anything runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    // counted from 1, like runtime error lines
+    pub line: usize,
+}
+
+impl SyntaxError {
+    pub fn new(message: String, token_line: usize) -> SyntaxError {
+        SyntaxError { message, line: token_line + 1 }
+    }
+
+    pub fn report(&self) -> String {
+        format!("[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+/// A Lox error on its way out through the interpreter: a thrown value, or a runtime error raised as an Error instance.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub value: ExpressionRes,
+    pub line: usize,
+    // filled from the call stack by the innermost function the error leaves, or by the host
+    pub trace: Vec<String>,
+}
+
+thread_local! {
+    static LINE: Cell<usize> = const { Cell::new(0) };
+}
+
+impl RuntimeError {
+    pub fn new(value: ExpressionRes, line: usize) -> RuntimeError {
+        RuntimeError {
+            kind: ErrorKind::Runtime,
+            value,
+            line,
+            trace: vec![],
+        }
+    }
+
+    pub fn message(&self) -> String {
+        if self.value.type_ == ExprResType::Instance {
+            let field = self.value.instance.as_ref().unwrap().borrow().env.borrow_mut().get_variable(Symbol::intern("message"));
+            if let Some(message) = field {
+                return message.borrow().print();
+            }
+        }
+        self.value.print()
+    }
+
+    /// Message followed by the Lox stack trace, innermost call first.
+    pub fn report(&self) -> String {
+        let mut report = format!("Error: {}", self.message());
+        for frame in &self.trace {
+            report.push('\n');
+            report.push_str(frame);
+        }
+        if self.trace.is_empty() {
+            report.push_str(&format!("\n[line {}] in script", self.line));
+        }
+        report
+    }
+}
+
+/// The class of errors raised by the interpreter, also available to scripts as Error(message).
+pub fn error_class() -> Class {
+    Class::new_class("Error".to_string(), vec![ExpressionRes::from_variable("message".to_string())], vec![])
+}
+
+pub fn error_value(message: String, line: usize) -> ExpressionRes {
+    let mut env = Environment::new();
+    env.define_variable(Symbol::intern("message"), ExpressionRes::from_str(message));
+    env.define_variable(Symbol::intern("line"), ExpressionRes::from_number(line as isize));
+    let class = std::rc::Rc::new(error_class());
+    let instance = class.call(std::rc::Rc::new(RefCell::new(env)), class.clone()).unwrap();
+    ExpressionRes::from_instance(instance)
+}
+
+/// An Error(message) made by the script learns its line when thrown.
+pub fn mark_error_line(value: &ExpressionRes) {
+    if value.type_ != ExprResType::Instance {
+        return;
+    }
+    let instance = value.instance.as_ref().unwrap().borrow();
+    if instance.class.name != "Error" {
+        return;
+    }
+    let mut env = instance.env.borrow_mut();
+    if env.get_variable(Symbol::intern("line")).is_none() {
+        env.define_variable(Symbol::intern("line"), ExpressionRes::from_number(current_line() as isize));
+    }
+}
+
+/// Line of the last evaluated node that carried one, used for errors raised without a token at hand.
+pub fn current_line() -> usize {
+    LINE.with(|line| line.get())
+}
+
+/// Takes a token line, which the scanner counts from 0, and keeps it counted from 1 for reports.
+pub fn set_line(token_line: usize) {
+    LINE.with(|current| current.set(token_line + 1));
+}
+
+/// The thrown value as an error, on the line being run.
+pub fn thrown(value: ExpressionRes) -> RuntimeError {
+    RuntimeError::new(value, current_line())
+}
+
+/// A catchable runtime error with the message, on the line being run.
+pub fn error(message: String) -> RuntimeError {
+    raise(ErrorKind::Runtime, message)
+}
+
+pub fn raise(kind: ErrorKind, message: String) -> RuntimeError {
+    let line = current_line();
+    let mut error = RuntimeError::new(error_value(message, line), line);
+    error.kind = kind;
+    error
+}
+
+/// The error for calling a function or class with the wrong number of arguments.
+pub fn expect_arity(name: &str, arity: usize, count: usize) -> Result<(), RuntimeError> {
+    if arity != count {
+        return Err(error(format!("{} expects {} arguments but got {}", name, arity, count)));
+    }
+    Ok(())
+}
+
+/// A mistake found while compiling, handed to the host like an uncaught runtime error.
+pub fn from_syntax(error: SyntaxError) -> RuntimeError {
+    RuntimeError::new(error_value(error.message, error.line), error.line)
+}
@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::expressions::expression::ExpressionRes;
+use crate::program::errors::{self, ErrorKind, RuntimeError};
+use crate::program::heap::{self, Heap};
+
+// the tree-walker nests Rust calls for every Lox call, in debug builds each one takes up to tens of kilobytes,
+// so the default stays within the 2MB stack of a spawned thread, like the 64 frames of clox
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+// long tracebacks keep this many frames from each end
+const TRACEBACK_EDGE: usize = 10;
+
+/// A function call in progress.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    // line of the call in the caller
+    pub line: usize,
+    pub callee: ExpressionRes,
+}
+
+/// State shared by every interpreter taking part in one program run.
+#[derive(Debug)]
+pub struct ExecutionState {
+    frames: Vec<Frame>,
+    pub max_depth: usize,
+    // fuel charged so far, against an optional budget
+    pub fuel_used: u64,
+    pub fuel_limit: Option<u64>,
+    pub deadline: Option<Instant>,
+    // cap on the approximate heap usage, in bytes
+    pub memory_limit: Option<usize>,
+    pub heap: Rc<Heap>,
+}
+
+impl ExecutionState {
+    pub fn new() -> ExecutionState {
+        ExecutionState {
+            frames: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel_used: 0,
+            fuel_limit: None,
+            deadline: None,
+            memory_limit: None,
+            heap: Rc::new(Heap::default()),
+        }
+    }
+
+    /// Charges one unit of fuel, stopping the program once the budget or the deadline is spent
+    /// and raising an out of memory error when the heap is over its limit.
+    pub fn charge(&mut self) -> Result<(), RuntimeError> {
+        self.fuel_used += 1;
+        if let Some(limit) = self.fuel_limit {
+            if self.fuel_used > limit {
+                return Err(errors::raise(ErrorKind::OutOfFuel, format!("Out of fuel after {} statements", limit)));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(errors::raise(ErrorKind::Timeout, "Execution deadline exceeded".to_string()));
+            }
+        }
+        self.heap.check(self.memory_limit)
+    }
+
+    /// Charges the containers created until the guard is dropped to the heap of this state.
+    pub fn enter_heap(&self) -> heap::Entered {
+        heap::enter(&self.heap)
+    }
+
+    pub fn push_frame(&mut self, frame: Frame) -> Result<(), RuntimeError> {
+        if self.frames.len() >= self.max_depth {
+            return Err(errors::error(format!("Stack overflow, calling {} would nest more than {} calls", frame.name, self.max_depth)));
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    pub fn pop_frame(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    /// Drops the frames of calls an error unwound out of.
+    pub fn unwind_to(&mut self, depth: usize) {
+        self.frames.truncate(depth);
+    }
+
+    pub fn frames(&self) -> &Vec<Frame> {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Lox traceback for an error on `line`, innermost call first and the script last.
+    pub fn traceback(&self, line: usize) -> Vec<String> {
+        let mut trace = vec![];
+        let mut current_line = line;
+        for frame in self.frames.iter().rev() {
+            trace.push(format!("[line {}] in {}()", current_line, frame.name));
+            current_line = frame.line;
+        }
+        trace.push(format!("[line {}] in script", current_line));
+        if trace.len() > 2 * TRACEBACK_EDGE {
+            let hidden = trace.len() - 2 * TRACEBACK_EDGE;
+            trace.splice(TRACEBACK_EDGE..trace.len() - TRACEBACK_EDGE, [format!("... {} more calls", hidden)]);
+        }
+        trace
+    }
+}
+
+/// Limits a host sets on a backend, they are kept in its execution state.
+pub trait Limits {
+    fn state(&self) -> &Rc<RefCell<ExecutionState>>;
+
+    /// Limits how deeply calls may nest before a catchable stack overflow error is raised.
+    /// The tree-walker nests Rust calls for every Lox call, a limit above the default needs a thread with a larger stack.
+    fn set_max_depth(&self, max_depth: usize) {
+        self.state().borrow_mut().max_depth = max_depth;
+    }
+
+    /// Limits how much work may run, the program stops with an OutOfFuel error past it.
+    /// The tree-walker charges every statement, the VM every loop iteration and call.
+    fn set_fuel(&self, fuel: u64) {
+        self.state().borrow_mut().fuel_limit = Some(fuel);
+    }
+
+    /// Stops the program with a Timeout error once the deadline passes.
+    fn set_deadline(&self, deadline: Instant) {
+        self.state().borrow_mut().deadline = Some(deadline);
+    }
+
+    /// Caps the approximate heap usage, growing past it raises a catchable out of memory error.
+    fn set_memory_limit(&self, bytes: usize) {
+        self.state().borrow_mut().memory_limit = Some(bytes);
+    }
+
+    /// Fuel charged so far, counted across every run.
+    fn fuel_used(&self) -> u64 {
+        self.state().borrow().fuel_used
+    }
+
+    /// Approximate bytes held by the environments, lists and maps of this program.
+    fn heap_used(&self) -> usize {
+        self.state().borrow().heap.used()
+    }
+
+    fn heap_peak(&self) -> usize {
+        self.state().borrow().heap.peak()
+    }
+}
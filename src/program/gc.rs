@@ -0,0 +1,318 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::env::environment::{Environment, Scope};
+use crate::expressions::expression::ExpressionRes;
+use crate::program::heap;
+use crate::program::runtime::{Class, Closure, Instance, List, Map, Method, Native, Upvalue};
+
+// Trial deletion cycle collector. Every cycle between runtime objects passes through a list, map,
+// instance, class, function or closure, so those are remembered when created. A collection traces everything
+// reachable from them and counts the references the traced objects hold to each other. An object with
+// more strong references than that is also referenced from outside, by the interpreter or a Rust local,
+// and keeps alive what it reaches. The rest is only referenced from garbage cycles and gets cleared,
+// which lets Rc free it.
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub runs: usize,
+    // objects of the last run
+    pub collected: usize,
+    pub live: usize,
+    pub freed_bytes: usize,
+    pub total_collected: usize,
+}
+
+enum Seed {
+    List(Weak<RefCell<List>>),
+    Map(Weak<RefCell<Map>>),
+    Instance(Weak<RefCell<Instance>>),
+    Method(Weak<Method>),
+    Class(Weak<Class>),
+    Closure(Weak<Closure>),
+}
+
+#[derive(Clone)]
+enum Node {
+    Env(Rc<RefCell<Environment>>),
+    Scope(Rc<RefCell<Scope>>),
+    Cell(Rc<RefCell<ExpressionRes>>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+    Instance(Rc<RefCell<Instance>>),
+    Method(Rc<Method>),
+    Class(Rc<Class>),
+    Native(Rc<Native>),
+    Closure(Rc<Closure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+}
+
+thread_local! {
+    static SEEDS: RefCell<Vec<Seed>> = const { RefCell::new(vec![]) };
+    static STATS: RefCell<GcStats> = RefCell::new(GcStats::default());
+}
+
+pub fn track_list(list: &Rc<RefCell<List>>) {
+    remember(Seed::List(Rc::downgrade(list)));
+}
+
+pub fn track_map(map: &Rc<RefCell<Map>>) {
+    remember(Seed::Map(Rc::downgrade(map)));
+}
+
+pub fn track_instance(instance: &Rc<RefCell<Instance>>) {
+    remember(Seed::Instance(Rc::downgrade(instance)));
+}
+
+pub fn track_method(method: &Rc<Method>) {
+    remember(Seed::Method(Rc::downgrade(method)));
+}
+
+pub fn track_class(class: &Rc<Class>) {
+    remember(Seed::Class(Rc::downgrade(class)));
+}
+
+pub fn track_closure(closure: &Rc<Closure>) {
+    remember(Seed::Closure(Rc::downgrade(closure)));
+}
+
+fn remember(seed: Seed) {
+    SEEDS.with(|seeds| {
+        let mut seeds = seeds.borrow_mut();
+        // dropped objects leave dead entries behind, sweep them once they pile up
+        if seeds.len() >= 1024 && seeds.len().is_power_of_two() {
+            seeds.retain(|seed| seed.upgrade().is_some());
+        }
+        seeds.push(seed);
+    });
+}
+
+pub fn stats() -> GcStats {
+    STATS.with(|stats| *stats.borrow())
+}
+
+/// Frees objects kept alive only by reference cycles and returns the stats of this run.
+pub fn collect() -> GcStats {
+    let used_before = heap::current().used();
+    let roots: Vec<Node> = SEEDS.with(|seeds| {
+        let mut seeds = seeds.borrow_mut();
+        seeds.retain(|seed| seed.upgrade().is_some());
+        seeds.iter().filter_map(|seed| seed.upgrade()).collect()
+    });
+
+    // trace the object graph, holding one reference to every node
+    let mut nodes: HashMap<usize, (Node, Vec<usize>)> = HashMap::new();
+    let mut pending = roots;
+    while let Some(node) = pending.pop() {
+        let address = node.address();
+        if nodes.contains_key(&address) {
+            continue;
+        }
+        let children = node.children();
+        let addresses = children.iter().map(|child| child.address()).collect();
+        pending.extend(children);
+        nodes.insert(address, (node, addresses));
+    }
+
+    let mut internal: HashMap<usize, usize> = HashMap::new();
+    for (_, children) in nodes.values() {
+        for child in children {
+            *internal.entry(*child).or_insert(0) += 1;
+        }
+    }
+
+    // objects referenced from outside the traced graph keep everything they reach alive
+    let mut live: HashMap<usize, ()> = HashMap::new();
+    let mut reachable: Vec<usize> = nodes.iter()
+        .filter(|(address, (node, _))| node.strong_count() - 1 > *internal.get(address).unwrap_or(&0))
+        .map(|(address, _)| *address)
+        .collect();
+    while let Some(address) = reachable.pop() {
+        if live.insert(address, ()).is_some() {
+            continue;
+        }
+        reachable.extend(nodes[&address].1.iter().filter(|child| !live.contains_key(child)));
+    }
+
+    let garbage: Vec<Node> = nodes.iter()
+        .filter(|(address, _)| !live.contains_key(address))
+        .map(|(_, (node, _))| node.clone())
+        .collect();
+    let collected = garbage.len();
+    for node in &garbage {
+        node.clear();
+    }
+    drop(garbage);
+    drop(nodes);
+
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.runs += 1;
+        stats.collected = collected;
+        stats.live = live.len();
+        stats.freed_bytes = used_before.saturating_sub(heap::current().used());
+        stats.total_collected += collected;
+        *stats
+    })
+}
+
+impl Seed {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Seed::List(list) => list.upgrade().map(Node::List),
+            Seed::Map(map) => map.upgrade().map(Node::Map),
+            Seed::Instance(instance) => instance.upgrade().map(Node::Instance),
+            Seed::Method(method) => method.upgrade().map(Node::Method),
+            Seed::Class(class) => class.upgrade().map(Node::Class),
+            Seed::Closure(closure) => closure.upgrade().map(Node::Closure),
+        }
+    }
+}
+
+impl Node {
+    fn address(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Scope(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Cell(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::List(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Map(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Instance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Method(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Class(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Native(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Upvalue(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::strong_count(rc),
+            Node::Scope(rc) => Rc::strong_count(rc),
+            Node::Cell(rc) => Rc::strong_count(rc),
+            Node::List(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Instance(rc) => Rc::strong_count(rc),
+            Node::Method(rc) => Rc::strong_count(rc),
+            Node::Class(rc) => Rc::strong_count(rc),
+            Node::Native(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::Upvalue(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // one entry per reference the object holds, a node busy elsewhere is treated as a leaf
+    fn children(&self) -> Vec<Node> {
+        let mut children = vec![];
+        match self {
+            Node::Env(env) => {
+                if let Ok(env) = env.try_borrow() {
+                    children.extend(env.cells().map(|cell| Node::Cell(cell.clone())));
+                }
+            }
+            Node::Scope(scope) => {
+                if let Ok(scope) = scope.try_borrow() {
+                    scope.values().for_each(|value| value_children(value, &mut children));
+                    children.extend(scope.enclosing.clone().map(Node::Scope));
+                }
+            }
+            Node::Cell(cell) => {
+                if let Ok(value) = cell.try_borrow() {
+                    value_children(&value, &mut children);
+                }
+            }
+            Node::List(list) => {
+                if let Ok(list) = list.try_borrow() {
+                    list.iter().for_each(|item| value_children(item, &mut children));
+                }
+            }
+            Node::Map(map) => {
+                if let Ok(map) = map.try_borrow() {
+                    map.entries().iter().for_each(|(_, value)| value_children(value, &mut children));
+                }
+            }
+            Node::Instance(instance) => {
+                if let Ok(instance) = instance.try_borrow() {
+                    children.push(Node::Class(instance.class.clone()));
+                    children.push(Node::Env(instance.env.clone()));
+                }
+            }
+            Node::Method(method) => {
+                method.args.iter().for_each(|arg| value_children(arg, &mut children));
+                children.extend(method.captured.clone().map(Node::Scope));
+            }
+            Node::Class(class) => {
+                class.args.iter().for_each(|arg| value_children(arg, &mut children));
+                children.extend(class.methods.iter().map(|method| Node::Method(method.clone())));
+                children.extend(class.closures.iter().map(|closure| Node::Closure(closure.clone())));
+            }
+            Node::Native(native) => value_children(&native.receiver, &mut children),
+            Node::Closure(closure) => {
+                children.extend(closure.upvalues.iter().map(|upvalue| Node::Upvalue(upvalue.clone())));
+                if let Some(receiver) = &closure.receiver {
+                    value_children(receiver, &mut children);
+                }
+            }
+            Node::Upvalue(upvalue) => {
+                // an open upvalue points at a stack slot, which is a root of its own
+                if let Ok(upvalue) = upvalue.try_borrow() {
+                    if let Upvalue::Closed(value) = &*upvalue {
+                        value_children(value, &mut children);
+                    }
+                }
+            }
+        }
+        children
+    }
+
+    // emptying the mutable containers breaks every cycle running through them
+    fn clear(&self) {
+        match self {
+            Node::Env(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    env.clear();
+                }
+            }
+            Node::Scope(scope) => {
+                if let Ok(mut scope) = scope.try_borrow_mut() {
+                    scope.clear();
+                }
+            }
+            Node::Cell(cell) => {
+                if let Ok(mut value) = cell.try_borrow_mut() {
+                    *value = ExpressionRes::from_none();
+                }
+            }
+            Node::List(list) => {
+                if let Ok(mut list) = list.try_borrow_mut() {
+                    list.clear();
+                }
+            }
+            Node::Map(map) => {
+                if let Ok(mut map) = map.try_borrow_mut() {
+                    map.clear();
+                }
+            }
+            Node::Upvalue(upvalue) => {
+                if let Ok(mut upvalue) = upvalue.try_borrow_mut() {
+                    if let Upvalue::Closed(value) = &mut *upvalue {
+                        *value = ExpressionRes::from_none();
+                    }
+                }
+            }
+            Node::Instance(_) | Node::Method(_) | Node::Class(_) | Node::Native(_) | Node::Closure(_) => {}
+        }
+    }
+}
+
+fn value_children(value: &ExpressionRes, children: &mut Vec<Node>) {
+    children.extend(value.method.clone().map(Node::Method));
+    children.extend(value.class.clone().map(Node::Class));
+    children.extend(value.instance.clone().map(Node::Instance));
+    children.extend(value.list.clone().map(Node::List));
+    children.extend(value.map.clone().map(Node::Map));
+    children.extend(value.native.clone().map(Node::Native));
+    children.extend(value.closure.clone().map(Node::Closure));
+}
@@ -0,0 +1,96 @@
+use std::cell::{Cell, RefCell};
+use std::mem::size_of;
+use std::rc::Rc;
+
+use crate::expressions::expression::ExpressionRes;
+use crate::program::errors::{self, ErrorKind, RuntimeError};
+use crate::program::gc;
+
+/// Approximate bytes held by the environments, lists and maps of one execution state. Containers
+/// add the shallow size of what they hold when it is stored and give it back when they are dropped,
+/// always to the heap they were created on.
+#[derive(Debug, Default)]
+pub struct Heap {
+    used: Cell<usize>,
+    peak: Cell<usize>,
+    // set when collecting garbage could not bring the usage under the limit
+    exhausted: Cell<bool>,
+}
+
+thread_local! {
+    // heap of the interpreter that is running, new containers are charged to it
+    static CURRENT: RefCell<Rc<Heap>> = RefCell::new(Rc::new(Heap::default()));
+}
+
+/// The heap new containers are charged to.
+pub fn current() -> Rc<Heap> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Charges new containers to the heap until the guard is dropped.
+pub fn enter(heap: &Rc<Heap>) -> Entered {
+    let previous = CURRENT.with(|current| current.replace(heap.clone()));
+    Entered { previous }
+}
+
+/// Puts back the heap that was current before `enter`.
+pub struct Entered {
+    previous: Rc<Heap>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.replace(self.previous.clone()));
+    }
+}
+
+impl Heap {
+    pub fn allocate(&self, bytes: usize) {
+        self.used.set(self.used.get() + bytes);
+        self.peak.set(self.peak.get().max(self.used.get()));
+    }
+
+    pub fn release(&self, bytes: usize) {
+        self.used.set(self.used.get().saturating_sub(bytes));
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+
+    /// Raises an out of memory error when the heap has grown past the limit.
+    pub fn check(&self, limit: Option<usize>) -> Result<(), RuntimeError> {
+        self.reserve(limit, 0)
+    }
+
+    /// Raises an out of memory error when `bytes` more would not fit under the limit, even after
+    /// collecting garbage cycles. Once a collection did not help, the next ones are skipped until
+    /// the usage is back under the limit, so a program that catches the error does not collect
+    /// on every statement.
+    pub fn reserve(&self, limit: Option<usize>, bytes: usize) -> Result<(), RuntimeError> {
+        if let Some(limit) = limit {
+            if self.used() + bytes <= limit {
+                self.exhausted.set(false);
+                return Ok(());
+            }
+            if !self.exhausted.get() {
+                gc::collect();
+            }
+            let needed = self.used() + bytes;
+            if needed > limit {
+                self.exhausted.set(true);
+                return Err(errors::raise(ErrorKind::OutOfMemory, format!("Out of memory, {} bytes needed but the limit is {}", needed, limit)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Size a container is charged for holding the value, strings count with their text.
+pub fn value_size(value: &ExpressionRes) -> usize {
+    size_of::<ExpressionRes>() + value.str.len()
+}
@@ -2,4 +2,10 @@ use crate::expressions::expression::ExpressionRes;
 
 pub mod program;
 pub mod runtime;
+pub mod natives;
+pub mod operations;
+pub mod errors;
+pub mod execution;
+pub mod heap;
+pub mod gc;
 
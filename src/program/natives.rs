@@ -0,0 +1,357 @@
+use std::mem::size_of;
+
+use crate::expressions::expression::{ExpressionRes, ExprResType};
+use crate::program::{errors, gc, heap, operations};
+use crate::program::errors::RuntimeError;
+use crate::program::runtime::{Map, MapKey, Native, NativeFn};
+
+/// What a native can ask of the interpreter running it.
+pub trait Host {
+    /// Calls a function, native or class and runs it to completion, this is how user code like toString() runs.
+    fn call(&mut self, callee: ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError>;
+    fn memory_limit(&self) -> Option<usize>;
+}
+
+/// Functions available in the global scope of every program.
+pub fn globals() -> Vec<(&'static str, NativeFn)> {
+    vec![("range", range), ("str", str), ("gc", gc)]
+}
+
+pub fn list_method(name: &str) -> Option<NativeFn> {
+    let function: NativeFn = match name {
+        "len" => list_len,
+        "push" => list_push,
+        "pop" => list_pop,
+        "insert" => list_insert,
+        "remove" => list_remove,
+        "slice" => list_slice,
+        "contains" => list_contains,
+        _ => return None,
+    };
+    Some(function)
+}
+
+pub fn map_method(name: &str) -> Option<NativeFn> {
+    let function: NativeFn = match name {
+        "len" => map_len,
+        "has" => map_has,
+        "remove" => map_remove,
+        "keys" => map_keys,
+        "values" => map_values,
+        _ => return None,
+    };
+    Some(function)
+}
+
+pub fn string_method(name: &str) -> Option<NativeFn> {
+    let function: NativeFn = match name {
+        "len" => string_len,
+        "substring" => string_substring,
+        "indexOf" => string_index_of,
+        "split" => string_split,
+        "join" => string_join,
+        "upper" => string_upper,
+        "lower" => string_lower,
+        "trim" => string_trim,
+        "replace" => string_replace,
+        "startsWith" => string_starts_with,
+        "chars" => string_chars,
+        "toNumber" => string_to_number,
+        _ => return None,
+    };
+    Some(function)
+}
+
+/// Method of a list, map or string bound to it, None for values of other types.
+pub fn builtin_method(object: &ExpressionRes, name: &str) -> Result<Option<ExpressionRes>, RuntimeError> {
+    let (kind, function) = match object.type_ {
+        ExprResType::List => ("list", list_method(name)),
+        ExprResType::Map => ("map", map_method(name)),
+        ExprResType::String => ("string", string_method(name)),
+        _ => return Ok(None),
+    };
+    match function {
+        Some(function) => Ok(Some(bind(name, object, function))),
+        None => Err(errors::error(format!("Undefined {} method '{}'", kind, name))),
+    }
+}
+
+pub fn get_index(object: &ExpressionRes, index: &ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    match object.type_ {
+        ExprResType::List => {
+            let list = object.list.as_ref().unwrap().borrow();
+            let position = expect_index(index, list.len(), false)?;
+            Ok(ExpressionRes::copy(&list[position]))
+        }
+        ExprResType::Map => {
+            let map = object.map.as_ref().unwrap().borrow();
+            match map.get(&MapKey::from_res(index)?) {
+                None => Ok(ExpressionRes::from_none()),
+                Some(value) => Ok(ExpressionRes::copy(value)),
+            }
+        }
+        _ => Err(errors::error(format!("Only lists and maps can be indexed, found {}", object.print()))),
+    }
+}
+
+pub fn set_index(object: &ExpressionRes, index: &ExpressionRes, value: ExpressionRes) -> Result<(), RuntimeError> {
+    match object.type_ {
+        ExprResType::List => {
+            let mut list = object.list.as_ref().unwrap().borrow_mut();
+            let position = expect_index(index, list.len(), false)?;
+            list.set(position, value);
+        }
+        ExprResType::Map => {
+            object.map.as_ref().unwrap().borrow_mut().insert(MapKey::from_res(index)?, value);
+        }
+        _ => return Err(errors::error(format!("Only lists and maps can be indexed, found {}", object.print()))),
+    }
+    Ok(())
+}
+
+/// Items a for-in loop visits: list items, map keys or the characters of a string.
+pub fn iteration_items(iterable: &ExpressionRes) -> Result<Vec<ExpressionRes>, RuntimeError> {
+    match iterable.type_ {
+        ExprResType::List => Ok(iterable.list.as_ref().unwrap().borrow().to_vec()),
+        ExprResType::Map => Ok(iterable.map.as_ref().unwrap().borrow().keys().iter()
+            .map(|key| key.to_res())
+            .collect()),
+        ExprResType::String => Ok(iterable.str.chars()
+            .map(|char| ExpressionRes::from_str(char.to_string()))
+            .collect()),
+        _ => Err(errors::error(format!("Can only iterate over lists, maps, strings and iterators, found {}", iterable.print()))),
+    }
+}
+
+pub fn bind(name: &str, receiver: &ExpressionRes, function: NativeFn) -> ExpressionRes {
+    ExpressionRes::from_native(Native::new(name.to_string(), ExpressionRes::copy(receiver), function))
+}
+
+pub fn expect_arity(name: &str, arguments: &Vec<ExpressionRes>, arity: usize) -> Result<(), RuntimeError> {
+    errors::expect_arity(name, arity, arguments.len())
+}
+
+/// Checks that the argument is a number within 0..len, or 0..=len when `inclusive` is set.
+pub fn expect_index(argument: &ExpressionRes, len: usize, inclusive: bool) -> Result<usize, RuntimeError> {
+    expect_index_into("list", argument, len, inclusive)
+}
+
+fn expect_index_into(kind: &str, argument: &ExpressionRes, len: usize, inclusive: bool) -> Result<usize, RuntimeError> {
+    if argument.type_ != ExprResType::Number {
+        return Err(errors::error(format!("Index must be a number, found {}", argument.print())));
+    }
+    let index = argument.number;
+    if index < 0 || index as usize > len || (!inclusive && index as usize == len) {
+        return Err(errors::error(format!("Index {} out of bounds for {} of length {}", index, kind, len)));
+    }
+    Ok(index as usize)
+}
+
+pub fn expect_string<'a>(name: &str, argument: &'a ExpressionRes) -> Result<&'a str, RuntimeError> {
+    if argument.type_ != ExprResType::String {
+        return Err(errors::error(format!("{} expects a string but got {}", name, argument.print())));
+    }
+    Ok(&argument.str)
+}
+
+fn list_len(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("len", &arguments, 0)?;
+    Ok(ExpressionRes::from_number(receiver.list.as_ref().unwrap().borrow().len() as isize))
+}
+
+fn list_push(_host: &mut dyn Host, receiver: &ExpressionRes, mut arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("push", &arguments, 1)?;
+    receiver.list.as_ref().unwrap().borrow_mut().push(arguments.remove(0));
+    Ok(ExpressionRes::from_none())
+}
+
+fn list_pop(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("pop", &arguments, 0)?;
+    match receiver.list.as_ref().unwrap().borrow_mut().pop() {
+        None => Err(errors::error("pop from empty list".to_string())),
+        Some(value) => Ok(value),
+    }
+}
+
+fn list_insert(_host: &mut dyn Host, receiver: &ExpressionRes, mut arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("insert", &arguments, 2)?;
+    let mut list = receiver.list.as_ref().unwrap().borrow_mut();
+    let index = expect_index(&arguments[0], list.len(), true)?;
+    list.insert(index, arguments.remove(1));
+    Ok(ExpressionRes::from_none())
+}
+
+fn list_remove(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("remove", &arguments, 1)?;
+    let mut list = receiver.list.as_ref().unwrap().borrow_mut();
+    let index = expect_index(&arguments[0], list.len(), false)?;
+    Ok(list.remove(index))
+}
+
+fn list_slice(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("slice", &arguments, 2)?;
+    let list = receiver.list.as_ref().unwrap().borrow();
+    let start = expect_index(&arguments[0], list.len(), true)?;
+    let end = expect_index(&arguments[1], list.len(), true)?;
+    if start > end {
+        return Err(errors::error(format!("slice start {} is after its end {}", start, end)));
+    }
+    Ok(ExpressionRes::from_list(list[start..end].to_vec()))
+}
+
+fn list_contains(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("contains", &arguments, 1)?;
+    let list = receiver.list.as_ref().unwrap().borrow();
+    Ok(ExpressionRes::from_bool(list.iter().any(|item| item.equals(&arguments[0]))))
+}
+
+fn map_len(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("len", &arguments, 0)?;
+    Ok(ExpressionRes::from_number(receiver.map.as_ref().unwrap().borrow().len() as isize))
+}
+
+fn map_has(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("has", &arguments, 1)?;
+    let key = MapKey::from_res(&arguments[0])?;
+    Ok(ExpressionRes::from_bool(receiver.map.as_ref().unwrap().borrow().contains(&key)))
+}
+
+fn map_remove(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("remove", &arguments, 1)?;
+    let key = MapKey::from_res(&arguments[0])?;
+    Ok(receiver.map.as_ref().unwrap().borrow_mut().remove(&key).unwrap_or_else(ExpressionRes::from_none))
+}
+
+fn map_keys(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("keys", &arguments, 0)?;
+    let keys = receiver.map.as_ref().unwrap().borrow().keys();
+    Ok(ExpressionRes::from_list(keys.iter().map(|key| key.to_res()).collect()))
+}
+
+fn map_values(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("values", &arguments, 0)?;
+    let map = receiver.map.as_ref().unwrap().borrow();
+    Ok(ExpressionRes::from_list(map.entries().iter().map(|(_, value)| ExpressionRes::copy(value)).collect()))
+}
+
+// strings are indexed by characters rather than bytes
+fn string_len(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("len", &arguments, 0)?;
+    Ok(ExpressionRes::from_number(receiver.str.chars().count() as isize))
+}
+
+fn string_substring(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("substring", &arguments, 2)?;
+    let chars: Vec<char> = receiver.str.chars().collect();
+    let start = expect_index_into("string", &arguments[0], chars.len(), true)?;
+    let end = expect_index_into("string", &arguments[1], chars.len(), true)?;
+    if start > end {
+        return Err(errors::error(format!("substring start {} is after its end {}", start, end)));
+    }
+    Ok(ExpressionRes::from_str(chars[start..end].iter().collect::<String>()))
+}
+
+fn string_index_of(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("indexOf", &arguments, 1)?;
+    let needle = expect_string("indexOf", &arguments[0])?;
+    match receiver.str.find(needle) {
+        None => Ok(ExpressionRes::from_number(-1)),
+        Some(byte) => Ok(ExpressionRes::from_number(receiver.str[..byte].chars().count() as isize)),
+    }
+}
+
+fn string_split(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("split", &arguments, 1)?;
+    let separator = expect_string("split", &arguments[0])?;
+    if separator.is_empty() {
+        return string_chars(_host, receiver, vec![]);
+    }
+    Ok(ExpressionRes::from_list(receiver.str.split(separator).map(|part| ExpressionRes::from_str(part.to_string())).collect()))
+}
+
+// the receiver is the separator, as in ", ".join(items)
+fn string_join(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("join", &arguments, 1)?;
+    if arguments[0].type_ != ExprResType::List {
+        return Err(errors::error(format!("join expects a list but got {}", arguments[0].print())));
+    }
+    let items = arguments[0].list.as_ref().unwrap().borrow();
+    let parts: Vec<String> = items.iter().map(|item| item.print()).collect();
+    Ok(ExpressionRes::from_str(parts.join(&receiver.str)))
+}
+
+fn string_upper(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("upper", &arguments, 0)?;
+    Ok(ExpressionRes::from_str(receiver.str.to_uppercase()))
+}
+
+fn string_lower(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("lower", &arguments, 0)?;
+    Ok(ExpressionRes::from_str(receiver.str.to_lowercase()))
+}
+
+fn string_trim(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("trim", &arguments, 0)?;
+    Ok(ExpressionRes::from_str(receiver.str.trim().to_string()))
+}
+
+fn string_replace(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("replace", &arguments, 2)?;
+    let from = expect_string("replace", &arguments[0])?;
+    let to = expect_string("replace", &arguments[1])?;
+    if from.is_empty() {
+        return Err(errors::error("replace expects a non empty string to search for".to_string()));
+    }
+    Ok(ExpressionRes::from_str(receiver.str.replace(from, to)))
+}
+
+fn string_starts_with(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("startsWith", &arguments, 1)?;
+    let prefix = expect_string("startsWith", &arguments[0])?;
+    Ok(ExpressionRes::from_bool(receiver.str.starts_with(prefix)))
+}
+
+fn string_chars(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("chars", &arguments, 0)?;
+    Ok(ExpressionRes::from_list(receiver.str.chars().map(|char| ExpressionRes::from_str(char.to_string())).collect()))
+}
+
+// nil when the string is not an integer
+fn string_to_number(_host: &mut dyn Host, receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("toNumber", &arguments, 0)?;
+    match receiver.str.trim().parse::<isize>() {
+        Ok(number) => Ok(ExpressionRes::from_number(number)),
+        Err(_) => Ok(ExpressionRes::from_none()),
+    }
+}
+
+fn str(host: &mut dyn Host, _receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("str", &arguments, 1)?;
+    Ok(ExpressionRes::from_str(operations::stringify(host, &arguments[0])?))
+}
+
+// range(end) or range(start, end), counting up with the end excluded
+fn range(host: &mut dyn Host, _receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    let (start, end) = match arguments.len() {
+        1 => (ExpressionRes::from_number(0), &arguments[0]),
+        2 => (ExpressionRes::copy(&arguments[0]), &arguments[1]),
+        _ => return Err(errors::error(format!("range expects 1 or 2 arguments but got {}", arguments.len()))),
+    };
+    if start.type_ != ExprResType::Number || end.type_ != ExprResType::Number {
+        return Err(errors::error(format!("range bounds must be numbers, found {} and {}", start.print(), end.print())));
+    }
+    let count = end.number.saturating_sub(start.number).max(0) as usize;
+    heap::current().reserve(host.memory_limit(), count.saturating_mul(size_of::<ExpressionRes>()))?;
+    Ok(ExpressionRes::from_list((start.number..end.number).map(ExpressionRes::from_number).collect()))
+}
+
+// collects garbage cycles now and returns what this run found
+fn gc(_host: &mut dyn Host, _receiver: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    expect_arity("gc", &arguments, 0)?;
+    let stats = gc::collect();
+    let mut result = Map::new();
+    for (name, value) in [("collected", stats.collected), ("live", stats.live), ("freed", stats.freed_bytes), ("runs", stats.runs)] {
+        result.insert(MapKey::String(name.into()), ExpressionRes::from_number(value as isize));
+    }
+    Ok(ExpressionRes::from_map(result))
+}
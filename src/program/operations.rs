@@ -0,0 +1,281 @@
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::env::environment::Scope;
+use crate::expressions::expression::{ExpressionRes, ExprResType};
+use crate::interner::Symbol;
+use crate::program::errors::{self, RuntimeError};
+use crate::program::natives::{self, Host};
+use crate::token::TokenType;
+
+// Semantics of the language both backends share, user code they call back into runs through the Host.
+
+/// Reads a field of the instance, falling back to its class methods bound to it.
+pub fn get_property(object: &ExpressionRes, name: Symbol) -> Result<ExpressionRes, RuntimeError> {
+    if object.type_ != ExprResType::Instance {
+        if let Some(method) = natives::builtin_method(object, &name.as_str())? {
+            return Ok(method);
+        }
+        return Err(errors::error(format!("Only instances have properties, tried to get '{}' on {}", name, object.print())));
+    }
+    let instance = object.instance.as_ref().unwrap().borrow();
+    let field = instance.env.borrow().get_variable(name);
+    if let Some(value) = field {
+        return Ok(ExpressionRes::copy(value.borrow().deref()));
+    }
+    if let Some(method) = instance.class.find_method(name) {
+        // the tree-walker finds `this` in the first slot of the scope the method closes over
+        let mut bound = Scope::new(method.captured.clone());
+        bound.set(0, ExpressionRes::copy(object));
+        return Ok(ExpressionRes::from_method(method.prepare_for_call(bound)));
+    }
+    match instance.class.find_closure(name) {
+        Some(closure) => Ok(ExpressionRes::from_closure(closure.bind(object.clone()))),
+        None => Err(errors::error(format!("Undefined property '{}'", name))),
+    }
+}
+
+pub fn set_property(object: &ExpressionRes, name: Symbol, value: ExpressionRes) -> Result<(), RuntimeError> {
+    if object.type_ != ExprResType::Instance {
+        return Err(errors::error(format!("Only instances have fields, tried to set '{}' on {}", name, object.print())));
+    }
+    object.instance.as_ref().unwrap().borrow().env.borrow_mut().define_variable(name, value);
+    Ok(())
+}
+
+/// Whether the class of the instance declares the method, fields do not count.
+pub fn has_method(instance: &ExpressionRes, name: Symbol) -> bool {
+    let class = instance.instance.as_ref().unwrap().borrow().class.clone();
+    class.find_method(name).is_some() || class.find_closure(name).is_some()
+}
+
+/// Applies a binary operator, on an instance it calls the method for it, a + b calls a.__add(b).
+pub fn binary(host: &mut dyn Host, operator: TokenType, lexeme: &str, lhs: ExpressionRes, rhs: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    if lhs.type_ != ExprResType::Instance {
+        return binary_values(operator, lexeme, lhs, rhs);
+    }
+    let name = match operator_method(operator) {
+        Some(name) => name,
+        None => return Err(errors::error(format!("Unsupported operator '{}' on {}", lexeme, lhs.print()))),
+    };
+    if name == Symbol::EQ && !has_method(&lhs, name) {
+        // instances without __eq compare by identity
+        let equal = lhs.equals(&rhs);
+        return Ok(ExpressionRes::from_bool(equal == (operator == TokenType::EqualEqual)));
+    }
+    let result = call_operator(host, lexeme, name, &lhs, vec![rhs])?;
+    if operator != TokenType::BangEqual {
+        return Ok(result);
+    }
+    // a != b negates a.__eq(b)
+    if result.type_ != ExprResType::Boolean {
+        return Err(errors::error(format!("__eq must return a boolean, got {}", result.print())));
+    }
+    Ok(ExpressionRes::from_bool(!result.boolean))
+}
+
+/// Applies a unary operator, -a on an instance calls a.__neg().
+pub fn unary(host: &mut dyn Host, operator: TokenType, lexeme: &str, value: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    match (value.type_, operator) {
+        (ExprResType::Number, TokenType::Minus) => negate(value.number),
+        (ExprResType::Boolean, TokenType::Bang) => Ok(ExpressionRes::from_bool(!value.boolean)),
+        (ExprResType::Instance, TokenType::Minus) => call_operator(host, lexeme, Symbol::NEG, &value, vec![]),
+        _ => Err(errors::error(format!("Unsupported operand for unary '{}': {}", lexeme, value.print()))),
+    }
+}
+
+/// Whether a condition of if, while, for or ?: holds, only booleans are conditions.
+pub fn condition(value: &ExpressionRes) -> Result<bool, RuntimeError> {
+    if value.type_ != ExprResType::Boolean {
+        return Err(errors::error(format!("Condition has to be a boolean, found {}", value.print())));
+    }
+    Ok(value.boolean)
+}
+
+/// Whether an operand of `and` or `or` decides the result, `false and` and `true or` skip their
+/// right side. Both operands have to be booleans, the result is the last one evaluated.
+pub fn short_circuits(operator: TokenType, lexeme: &str, operand: &ExpressionRes) -> Result<bool, RuntimeError> {
+    if operand.type_ != ExprResType::Boolean {
+        return Err(errors::error(format!("Operands of '{}' must be booleans, found {}", lexeme, operand.print())));
+    }
+    Ok(operand.boolean == (operator == TokenType::Or))
+}
+
+fn call_operator(host: &mut dyn Host, lexeme: &str, name: Symbol, instance: &ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+    if !has_method(instance, name) {
+        let class = instance.instance.as_ref().unwrap().borrow().class.name.clone();
+        return Err(errors::error(format!("Operator '{}' is not defined for {}, add a {} method to the class", lexeme, class, name)));
+    }
+    let method = get_property(instance, name)?;
+    host.call(method, arguments)
+}
+
+/// Applies a binary operator to values that are not instances, `lexeme` names the operator in errors.
+pub fn binary_values(operator: TokenType, lexeme: &str, lhs_res: ExpressionRes, rhs_res: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    if operator == TokenType::BangEqual {
+        return Ok(ExpressionRes::from_bool(!lhs_res.equals(&rhs_res)));
+    }
+    if lhs_res.type_ == ExprResType::Number && lhs_res.eq_type(&rhs_res) {
+        let (lhs, rhs) = (lhs_res.number, rhs_res.number);
+        let number = match operator {
+            TokenType::Greater => return Ok(ExpressionRes::from_bool(lhs > rhs)),
+            TokenType::GreaterEqual => return Ok(ExpressionRes::from_bool(lhs >= rhs)),
+            TokenType::Less => return Ok(ExpressionRes::from_bool(lhs < rhs)),
+            TokenType::LessEqual => return Ok(ExpressionRes::from_bool(lhs <= rhs)),
+            TokenType::EqualEqual => return Ok(ExpressionRes::from_bool(lhs == rhs)),
+            TokenType::Slash | TokenType::Percent if rhs == 0 => {
+                return Err(errors::error("Division by zero".to_string()));
+            }
+            TokenType::Minus => lhs.checked_sub(rhs),
+            TokenType::Slash => lhs.checked_div(rhs),
+            TokenType::Star => lhs.checked_mul(rhs),
+            TokenType::Plus => lhs.checked_add(rhs),
+            TokenType::Percent => lhs.checked_rem_euclid(rhs),
+            _ => return Ok(ExpressionRes::from_none()),
+        };
+        match number {
+            Some(number) => Ok(ExpressionRes::from_number(number)),
+            None => Err(errors::error(format!("Integer overflow in {} {} {}", lhs, lexeme, rhs))),
+        }
+    } else if lhs_res.type_ == ExprResType::String && lhs_res.eq_type(&rhs_res) {
+        match operator {
+            TokenType::Plus => Ok(ExpressionRes::from_str(
+                lhs_res.str.to_string() + &*rhs_res.str)),
+            TokenType::EqualEqual => Ok(ExpressionRes::from_bool(lhs_res.equals(&rhs_res))),
+            TokenType::Greater => Ok(ExpressionRes::from_bool(lhs_res.str > rhs_res.str)),
+            TokenType::GreaterEqual => Ok(ExpressionRes::from_bool(lhs_res.str >= rhs_res.str)),
+            TokenType::Less => Ok(ExpressionRes::from_bool(lhs_res.str < rhs_res.str)),
+            TokenType::LessEqual => Ok(ExpressionRes::from_bool(lhs_res.str <= rhs_res.str)),
+            _ => Ok(ExpressionRes::from_none()),
+        }
+    } else if operator == TokenType::Plus
+        && (lhs_res.type_ == ExprResType::String || rhs_res.type_ == ExprResType::String)
+        && (lhs_res.type_ == ExprResType::Number || rhs_res.type_ == ExprResType::Number) {
+        Ok(ExpressionRes::from_str(lhs_res.print() + &rhs_res.print()))
+    } else if operator == TokenType::EqualEqual {
+        Ok(ExpressionRes::from_bool(lhs_res.equals(&rhs_res)))
+    } else {
+        Err(errors::error(format!("Unsupported operands for '{}': {} and {}", lexeme, lhs_res.print(), rhs_res.print())))
+    }
+}
+
+/// Negation of a number, an error where the result does not fit.
+pub fn negate(number: isize) -> Result<ExpressionRes, RuntimeError> {
+    match number.checked_neg() {
+        Some(number) => Ok(ExpressionRes::from_number(number)),
+        None => Err(errors::error(format!("Integer overflow in -({})", number))),
+    }
+}
+
+/// The value of ++ or --, an error for anything but a number or where the result does not fit.
+pub fn step(value: &ExpressionRes, lexeme: &str, offset: isize) -> Result<ExpressionRes, RuntimeError> {
+    if value.type_ != ExprResType::Number {
+        return Err(errors::error(format!("Operand of {} must be a number, found {}", lexeme, value.print())));
+    }
+    match value.number.checked_add(offset) {
+        Some(number) => Ok(ExpressionRes::from_number(number)),
+        None => Err(errors::error(format!("Integer overflow in {}{}", value.number, lexeme))),
+    }
+}
+
+/// Method an instance defines to support the operator, a + b calls a.__add(b).
+pub fn operator_method(operator: TokenType) -> Option<Symbol> {
+    let name = match operator {
+        TokenType::Plus => Symbol::ADD,
+        TokenType::Minus => Symbol::SUB,
+        TokenType::Star => Symbol::MUL,
+        TokenType::Slash => Symbol::DIV,
+        TokenType::Percent => Symbol::MOD,
+        TokenType::EqualEqual | TokenType::BangEqual => Symbol::EQ,
+        TokenType::Less => Symbol::LT,
+        TokenType::LessEqual => Symbol::LE,
+        TokenType::Greater => Symbol::GT,
+        TokenType::GreaterEqual => Symbol::GE,
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Converts a value to the text print shows for it, calling toString() on instances that define it.
+/// A list or map that contains itself shows up as [...] or {...} inside.
+pub fn stringify(host: &mut dyn Host, value: &ExpressionRes) -> Result<String, RuntimeError> {
+    stringify_nested(host, value, &mut vec![])
+}
+
+// `open` holds the addresses of the lists and maps being printed
+fn stringify_nested(host: &mut dyn Host, value: &ExpressionRes, open: &mut Vec<usize>) -> Result<String, RuntimeError> {
+    match value.type_ {
+        ExprResType::Instance => {
+            if !has_method(value, Symbol::TO_STRING) {
+                return Ok(value.print());
+            }
+            let method = get_property(value, Symbol::TO_STRING)?;
+            let text = host.call(method, vec![])?;
+            if text.type_ != ExprResType::String {
+                return Err(errors::error(format!("toString() has to return a string, found {}", text.print())));
+            }
+            Ok(text.str.to_string())
+        }
+        ExprResType::List => {
+            let list = value.list.as_ref().unwrap();
+            let address = Rc::as_ptr(list) as usize;
+            if open.contains(&address) {
+                return Ok("[...]".to_string());
+            }
+            // toString() may change the list while it is printed
+            let items = list.borrow().to_vec();
+            open.push(address);
+            let mut texts = vec![];
+            for item in &items {
+                texts.push(stringify_nested(host, item, open)?);
+            }
+            open.pop();
+            Ok(format!("[{}]", texts.join(", ")))
+        }
+        ExprResType::Map => {
+            let map = value.map.as_ref().unwrap();
+            let address = Rc::as_ptr(map) as usize;
+            if open.contains(&address) {
+                return Ok("{...}".to_string());
+            }
+            let entries = map.borrow().entries().clone();
+            open.push(address);
+            let mut texts = vec![];
+            for (key, value) in &entries {
+                texts.push(format!("{}: {}", key.to_res().print(), stringify_nested(host, value, open)?));
+            }
+            open.pop();
+            Ok(format!("{{{}}}", texts.join(", ")))
+        }
+        _ => Ok(value.print()),
+    }
+}
+
+/// What a for-in loop walks, the items of a list, map or string in a list, for an instance
+/// the result of its iterator() method or the instance itself when it has none.
+pub fn iterator(host: &mut dyn Host, iterable: ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    if iterable.type_ != ExprResType::Instance {
+        return Ok(ExpressionRes::from_list(natives::iteration_items(&iterable)?));
+    }
+    if !has_method(&iterable, Symbol::ITERATOR) {
+        return Ok(iterable);
+    }
+    let method = get_property(&iterable, Symbol::ITERATOR)?;
+    host.call(method, vec![])
+}
+
+/// Calls hasNext() on an iterator instance.
+pub fn has_next(host: &mut dyn Host, iterator: &ExpressionRes) -> Result<bool, RuntimeError> {
+    let method = get_property(iterator, Symbol::HAS_NEXT)?;
+    let has_next = host.call(method, vec![])?;
+    if has_next.type_ != ExprResType::Boolean {
+        return Err(errors::error(format!("hasNext() has to return a bool, found {}", has_next.print())));
+    }
+    Ok(has_next.boolean)
+}
+
+/// Calls next() on an iterator instance.
+pub fn next(host: &mut dyn Host, iterator: &ExpressionRes) -> Result<ExpressionRes, RuntimeError> {
+    let method = get_property(iterator, Symbol::NEXT)?;
+    host.call(method, vec![])
+}
@@ -1,135 +1,132 @@
 use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::iter::Rev;
-use std::ops::DerefMut;
+use std::ops::Deref;
 use std::rc::Rc;
 
 
 use log::{trace,info, warn, error};
-use crate::env::environment::Environment;
+use crate::env::environment::{Environment, Scope, Slot};
 use crate::expressions::expression::ExpressionRes;
+use crate::interner::Symbol;
+use crate::program::{errors, natives};
+use crate::program::errors::RuntimeError;
+
+/// Globals every program starts with, the natives and the Error class.
+pub fn builtins() -> Vec<(&'static str, ExpressionRes)> {
+    let mut builtins: Vec<(&'static str, ExpressionRes)> = natives::globals().into_iter()
+        .map(|(name, function)| (name, natives::bind(name, &ExpressionRes::from_none(), function)))
+        .collect();
+    builtins.push(("Error", ExpressionRes::from_class(errors::error_class())));
+    builtins
+}
 
+/// Globals live in a table looked up by name, locals in scopes addressed by the slots the resolver assigned.
 pub struct ProgramEnvs {
-    top: Rc<RefCell<Environment>>,
+    globals: Rc<RefCell<Environment>>,
+    top: Option<Rc<RefCell<Scope>>>,
 }
 
 impl Debug for ProgramEnvs {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ProgramEnvs debug")
-            .field("vec", &self.top)
+            .field("globals", &self.globals)
+            .field("top", &self.top)
             .finish()
     }
 }
 
 impl ProgramEnvs {
     pub fn new() -> ProgramEnvs {
+        let mut globals = Environment::new();
+        for (name, value) in builtins() {
+            globals.define_variable(Symbol::intern(name), value);
+        }
         ProgramEnvs {
-            top: Rc::new(RefCell::new(Environment::new())),
+            globals: Rc::new(RefCell::new(globals)),
+            top: None,
         }
     }
 
-    pub fn new_with_env(env: Rc<RefCell<Environment>>) -> ProgramEnvs {
+    /// Runs with the innermost scope of a call on top of the same globals.
+    pub fn new_with_scope(globals: Rc<RefCell<Environment>>, scope: Rc<RefCell<Scope>>) -> ProgramEnvs {
         ProgramEnvs {
-            top: env,
+            globals,
+            top: Some(scope),
         }
     }
 
-    pub fn get_top(&self) ->Rc<RefCell<Environment>> {
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        self.globals.clone()
+    }
+
+    pub fn get_top(&self) -> Option<Rc<RefCell<Scope>>> {
         self.top.clone()
     }
 
     pub fn push(&mut self) {
-        self.top = Rc::new(RefCell::new(Environment::new_with_enclosing(self.top.clone())));
+        self.top = Some(Rc::new(RefCell::new(Scope::new(self.top.take()))));
     }
 
-    pub fn pop(&mut self) -> Option<Rc<RefCell<Environment>>> {
-        let rc = self.top.borrow_mut().enclosing.clone().unwrap();
-        self.top = rc;
-        Some(self.top.clone())
+    /// Drops the scopes pushed after `top` was taken, used when an error unwound past their pops.
+    pub fn restore(&mut self, top: Option<Rc<RefCell<Scope>>>) {
+        self.top = top;
     }
 
-    pub fn define_at_top(&self, name: String, value: ExpressionRes) {
-        self.top.borrow_mut().define_variable(name.clone(), value);
+    pub fn pop(&mut self) -> Option<Rc<RefCell<Scope>>> {
+        let top = self.top.take().expect("popped the global scope");
+        self.top = top.borrow().enclosing.clone();
+        self.top.clone()
     }
 
-    pub fn define_ref_at_top(&self, name: String, value: Rc<RefCell<ExpressionRes>>) {
-        self.top.borrow_mut().define_ref(name.clone(), value);
+    pub fn define(&self, slot: Slot, name: Symbol, value: ExpressionRes) {
+        match slot {
+            Slot::Global => self.define_at_top(name, value),
+            Slot::Local { depth, index } => self.scope(depth).borrow_mut().set(index, value),
+        }
     }
 
-    pub fn assign_value_to_var(&self, name: String, value: ExpressionRes) {
-        match self.get_env(&name) {
-            None => {}
-            Some(rc) => {
-                let mut ref_mut = rc.borrow_mut();
-                let x = ref_mut.deref_mut();
-                x.define_variable(name, value);
-            }
-        };
+    pub fn get(&self, slot: Slot, name: Symbol) -> Result<ExpressionRes, RuntimeError> {
+        match slot {
+            Slot::Global => Ok(ExpressionRes::copy(self.lookup_var(name)?.borrow().deref())),
+            Slot::Local { depth, index } => Ok(self.scope(depth).borrow().get(index)),
+        }
     }
 
-    pub fn assign_ref_to_var(&self, name: String, value: Rc<RefCell<ExpressionRes>>) {
-        match self.get_env(&name) {
-            None => {}
-            Some(rc) => {
-                let mut ref_mut = rc.borrow_mut();
-                let x = ref_mut.deref_mut();
-                x.define_ref(name, value);
+    pub fn assign(&self, slot: Slot, name: Symbol, value: ExpressionRes) -> Result<(), RuntimeError> {
+        match slot {
+            Slot::Global => {
+                let mut globals = self.globals.borrow_mut();
+                if globals.get_variable(name).is_none() {
+                    return Err(errors::error(format!("Undefined variable '{}'", name)));
+                }
+                globals.redefine_variable(name, value);
             }
-        };
+            Slot::Local { depth, index } => self.scope(depth).borrow_mut().set(index, value),
+        }
+        Ok(())
     }
 
-    pub fn assign_to_existing(&self, name: String, value: ExpressionRes) {
-        match self.get_env(&name) {
-            None => {}
-            Some(rc) => {
-                let mut ref_mut = rc.borrow_mut();
-                let x = ref_mut.deref_mut();
-                x.redefine_variable(name, value);
-            }
-        };
+    fn scope(&self, depth: usize) -> Rc<RefCell<Scope>> {
+        match &self.top {
+            None => { panic!("local variable used outside of any scope"); }
+            Some(top) if depth == 0 => top.clone(),
+            Some(top) => Scope::ancestor(top, depth),
+        }
     }
 
-    pub fn remove_var(&self, name: String) {
-        match self.get_env(&name) {
-            None => {}
-            Some(env) => {
-                env.borrow_mut().remove_var(name);
-            }
-        }
+    pub fn define_at_top(&self, name: Symbol, value: ExpressionRes) {
+        self.globals.borrow_mut().define_variable(name, value);
     }
 
-    pub fn lookup_var(&self, name: String) -> Rc<RefCell<ExpressionRes>> {
-        match self.get_env(&name.clone()) {
-            None => { panic!("Missing variable {:?} \n state is {:#?}", name, &self.top); }
-            Some(value) => { return value.borrow_mut().get_variable(name.clone()).unwrap(); }
-        };
+    pub fn remove_var(&self, name: Symbol) {
+        self.globals.borrow_mut().remove_var(name);
     }
 
-    fn get_env(&self, name: &String) -> Option<Rc<RefCell<Environment>>> {
-        trace!("Resolving get_env {} ", &name);
-        let mut current = self.top.clone();
-        loop {
-            let mut next = None;
-            match current.borrow_mut().get_variable(name.clone()) {
-                Some(..) => {
-                    return Some(current.clone());
-                }
-                None => {}
-            }
-            if let Some(ref mut env) = current.borrow_mut().enclosing {
-                match env.borrow_mut().get_variable(name.clone()) {
-                    None => {
-                        next = Some(env.clone());
-                    }
-                    Some(val) => {
-                        return Some(env.clone());
-                    }
-                }
-            }
-            if next.is_none() {
-                return None;
-            }
-            current = next.unwrap();
+    pub fn lookup_var(&self, name: Symbol) -> Result<Rc<RefCell<ExpressionRes>>, RuntimeError> {
+        match self.globals.borrow().get_variable(name) {
+            None => Err(errors::error(format!("Undefined variable '{}'", name))),
+            Some(value) => Ok(value),
         }
     }
 }
@@ -137,11 +134,11 @@ impl ProgramEnvs {
 #[test]
 fn assign_get() {
     let mut envs = ProgramEnvs::new();
-    envs.define_at_top(String::from("x"),
+    envs.define_at_top(Symbol::intern("x"),
                        ExpressionRes::from_str(String::from("Value")));
 
-    let rc = envs.lookup_var(String::from("x"));
-    let rc2 = envs.lookup_var(String::from("x"));
+    let rc = envs.lookup_var(Symbol::intern("x")).unwrap();
+    let rc2 = envs.lookup_var(Symbol::intern("x")).unwrap();
     print!("{:?}, {rc2:?}", rc);
 }
 
@@ -149,39 +146,44 @@ fn assign_get() {
 fn push_scoped() {
     let mut envs = ProgramEnvs::new();
     envs.define_at_top(
-        String::from("x"),
+        Symbol::intern("x"),
         ExpressionRes::from_str(String::from("Value")));
     envs.push();
-    envs.define_at_top(
-        String::from("x"),
-        ExpressionRes::from_str(String::from("scoped")));
-
-    println!("{:#?}", envs);
-    let rc = envs.lookup_var(String::from("x"));
-    assert_eq!("scoped", rc.borrow().str);
+    let local = Slot::Local { depth: 0, index: 0 };
+    envs.define(local, Symbol::intern("x"), ExpressionRes::from_str(String::from("scoped")));
+    envs.push();
+    assert_eq!("scoped", &*envs.get(Slot::Local { depth: 1, index: 0 }, Symbol::intern("x")).unwrap().str);
+    assert_eq!("Value", &*envs.get(Slot::Global, Symbol::intern("x")).unwrap().str);
+    envs.pop();
     envs.pop();
-    println!("{:#?}", envs);
-    let rc2 = envs.lookup_var(String::from("x"));
-    assert_eq!("Value", rc2.borrow().str);
+    assert!(envs.get_top().is_none());
+    assert_eq!("Value", &*envs.lookup_var(Symbol::intern("x")).unwrap().borrow().str);
 }
 
 #[test]
-fn remove() {
+fn assign_slots() {
     let mut envs = ProgramEnvs::new();
-    envs.define_at_top(
-        String::from("x"),
-        ExpressionRes::from_str(String::from("Value")));
     envs.push();
+    // a slot past the ones defined so far reads as nil
+    assert_eq!("nil", &*envs.get(Slot::Local { depth: 0, index: 2 }, Symbol::intern("y")).unwrap().str);
+    envs.assign(Slot::Local { depth: 0, index: 2 }, Symbol::intern("y"), ExpressionRes::from_number(3)).unwrap();
+    assert_eq!(3, envs.get(Slot::Local { depth: 0, index: 2 }, Symbol::intern("y")).unwrap().number);
+}
+
+#[test]
+fn assign_undefined_global() {
+    let envs = ProgramEnvs::new();
+    let error = envs.assign(Slot::Global, Symbol::intern("x"), ExpressionRes::from_number(1)).unwrap_err();
+    assert_eq!(error.message(), "Undefined variable 'x'");
+}
+
+#[test]
+fn remove() {
+    let envs = ProgramEnvs::new();
     envs.define_at_top(
-        String::from("x"),
-        ExpressionRes::from_str(String::from("scoped")));
-
-    println!("{:#?}", envs);
-    let rc = envs.lookup_var(String::from("x"));
-    assert_eq!("scoped", rc.borrow().str);
-    envs.remove_var("x".to_string());
-    let rc1 = envs.lookup_var(String::from("x"));
-    println!("{:#?}", envs);
-    let rc2 = envs.lookup_var(String::from("x"));
-    assert_eq!("nil", rc2.borrow().str);
-}
\ No newline at end of file
+        Symbol::intern("x"),
+        ExpressionRes::from_str(String::from("Value")));
+    envs.remove_var(Symbol::intern("x"));
+    let rc = envs.lookup_var(Symbol::intern("x")).unwrap();
+    assert_eq!("nil", &*rc.borrow().str);
+}
@@ -1,44 +1,58 @@
 use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::mem::size_of;
 use std::ops::Deref;
 use std::rc::Rc;
 
-use crate::env::environment::Environment;
-use crate::expressions::expression::ExpressionRes;
+use crate::env::environment::{Environment, Scope};
+use crate::expressions::expression::{ExpressionRes, ExprResType};
+use crate::interner::Symbol;
+use crate::program::execution::ExecutionState;
+use crate::program::{errors, gc, heap};
+use crate::program::heap::Heap;
+use crate::program::errors::RuntimeError;
+use crate::program::natives::Host;
 use crate::program::program::ProgramEnvs;
 use crate::statements::statement::Statement;
 use crate::statements::stmt_visitor::{StatementInterpreter, StatementRes, StmtVisitor};
+use crate::vm::chunk::Function;
 
 #[derive(Debug,  Clone)]
 pub struct Method {
     pub name: String,
+    // the name interned, classes look their methods up by it
+    pub symbol: Symbol,
     pub args: Vec<ExpressionRes>,
-    pub body: Statement,
-    pub captured_env: Rc<RefCell<Environment>>,
+    pub body: Rc<Statement>,
+    // the scope the function was declared in, its calls run in a scope enclosed by it
+    pub captured: Option<Rc<RefCell<Scope>>>,
 }
 
 impl Method {
-    pub fn new(name: String, args: Vec<ExpressionRes>, body: Statement, captured: Environment) -> Method {
+    pub fn new(name: String, args: Vec<ExpressionRes>, body: Rc<Statement>, captured: Option<Rc<RefCell<Scope>>>) -> Method {
         Method {
+            symbol: Symbol::intern(&name),
             name,
             args,
             body,
-            captured_env: Rc::new(RefCell::new(captured)),
+            captured,
         }
     }
 
-    pub fn prepare_for_call(&self, env: Environment) -> Method {
+    pub fn prepare_for_call(&self, scope: Scope) -> Method {
         Method {
             name: self.name.clone(),
+            symbol: self.symbol,
             args: self.args.clone(),
             body: self.body.clone(),
-            captured_env: Rc::new(RefCell::new((env))),
+            captured: Some(Rc::new(RefCell::new(scope))),
         }
     }
 
     // this should be just the params right now, not the captured stuff
-    pub fn call(&self, arguments: Rc<RefCell<ProgramEnvs>>) -> Result<StatementRes, String> {
-        let mut interpreter = StatementInterpreter::new_with_envs(
-            arguments);
+    pub fn call(&self, arguments: Rc<RefCell<ProgramEnvs>>, state: Rc<RefCell<ExecutionState>>) -> Result<StatementRes, RuntimeError> {
+        let mut interpreter = StatementInterpreter::new_with_state(
+            arguments, state);
         return interpreter.eval(&self.body);
     }
 }
@@ -48,6 +62,8 @@ pub struct Class {
     pub name: String,
     pub args: Vec<ExpressionRes>,
     pub methods: Vec<Rc<Method>>,
+    // methods of a class declared in bytecode
+    pub closures: Vec<Rc<Closure>>,
 }
 
 impl Class {
@@ -56,6 +72,7 @@ impl Class {
             name,
             args: vec![],
             methods: vec![],
+            closures: vec![],
         }
     }
     pub fn new_class(name: String, args: Vec<ExpressionRes>, methods: Vec<Rc<Method>>) -> Class {
@@ -63,11 +80,39 @@ impl Class {
             name,
             args,
             methods,
+            closures: vec![],
         }
     }
 
-    pub fn add_method(&mut self, name: String, args: Vec<ExpressionRes>, body: Statement) {
-        let method = Method::new(name, args, body, Environment::new());
+    pub fn new_compiled(name: String, closures: Vec<Rc<Closure>>) -> Class {
+        Class {
+            name,
+            args: vec![],
+            methods: vec![],
+            closures,
+        }
+    }
+
+    /// Creates an instance whose fields are the declared class parameters bound to the arguments.
+    pub fn construct(class: Rc<Class>, arguments: Vec<ExpressionRes>) -> Result<Instance, RuntimeError> {
+        let mut fields = Environment::new();
+        errors::expect_arity(&class.name, class.args.len(), arguments.len())?;
+        for (name, value) in class.args.iter().zip(arguments) {
+            fields.define_variable(Symbol::intern(&name.str), value);
+        }
+        Ok(class.call(Rc::new(RefCell::new(fields)), class.clone()).unwrap())
+    }
+
+    pub fn find_closure(&self, name: Symbol) -> Option<Rc<Closure>> {
+        self.closures.iter().find(|closure| closure.function.symbol == name).cloned()
+    }
+
+    pub fn find_method(&self, name: Symbol) -> Option<Rc<Method>> {
+        self.methods.iter().find(|method| method.symbol == name).cloned()
+    }
+
+    pub fn add_method(&mut self, name: String, args: Vec<ExpressionRes>, body: Rc<Statement>) {
+        let method = Method::new(name, args, body, None);
         self.methods.push(Rc::new(method));
     }
 
@@ -78,6 +123,31 @@ impl Class {
     }
 }
 
+/// Compiled function together with the variables it captured, and the instance of a bound method.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub receiver: Option<ExpressionRes>,
+}
+
+impl Closure {
+    pub fn bind(&self, receiver: ExpressionRes) -> Closure {
+        Closure {
+            function: self.function.clone(),
+            upvalues: self.upvalues.clone(),
+            receiver: Some(receiver),
+        }
+    }
+}
+
+/// Variable captured by a closure, it stays on the VM stack until its scope ends and then moves in here.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(ExpressionRes),
+}
+
 #[derive(Debug)]
 pub struct Instance {
     pub class: Rc<Class>,
@@ -101,19 +171,232 @@ impl Instance {
         }
         panic!("did not find the method def")
     }
+}
+/// Natives get the interpreter so they can call back into user code, like a toString method.
+pub type NativeFn = fn(&mut dyn Host, &ExpressionRes, Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError>;
 
-    fn get_child_env(&self) -> Environment {
-        Environment::new_with_enclosing(self.env.clone())
+/// Builtin implemented in Rust, bound to the value it was read from.
+#[derive(Debug, Clone)]
+pub struct Native {
+    pub name: String,
+    pub receiver: ExpressionRes,
+    pub function: NativeFn,
+}
+
+impl Native {
+    pub fn new(name: String, receiver: ExpressionRes, function: NativeFn) -> Native {
+        Native {
+            name,
+            receiver,
+            function,
+        }
     }
 
-    fn call(&mut self, method: String, arguments: Environment) -> Result<StatementRes, String> {
-        let envs = ProgramEnvs::new_with_env(Rc::new(RefCell::new(arguments)));
-        let program_envs = Rc::new(RefCell::new(envs));
-        for x in &self.class.deref().methods  {
-            if x.name == method {
-                return x.call(program_envs.clone())
+    pub fn call(&self, host: &mut dyn Host, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+        (self.function)(host, &self.receiver, arguments)
+    }
+}
+
+/// Key of a map entry, only primitive values can be hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(Rc<str>),
+    Number(isize),
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    pub fn from_res(res: &ExpressionRes) -> Result<MapKey, RuntimeError> {
+        match res.type_ {
+            ExprResType::String => Ok(MapKey::String(res.str.clone())),
+            ExprResType::Number => Ok(MapKey::Number(res.number)),
+            ExprResType::Boolean => Ok(MapKey::Boolean(res.boolean)),
+            ExprResType::Nil => Ok(MapKey::Nil),
+            _ => Err(errors::error(format!("Map keys must be strings, numbers, booleans or nil, found {}", res.print()))),
+        }
+    }
+
+    pub fn to_res(&self) -> ExpressionRes {
+        match self {
+            MapKey::String(value) => ExpressionRes::from_str(value.clone()),
+            MapKey::Number(value) => ExpressionRes::from_number(*value),
+            MapKey::Boolean(value) => ExpressionRes::from_bool(*value),
+            MapKey::Nil => ExpressionRes::from_none(),
+        }
+    }
+}
+
+/// Items of a list value, changed only through methods so the heap accounting sees every change.
+#[derive(Debug)]
+pub struct List {
+    items: Vec<ExpressionRes>,
+    accounted: usize,
+    heap: Rc<Heap>,
+}
+
+impl List {
+    pub fn new(items: Vec<ExpressionRes>) -> List {
+        let mut list = List { items: vec![], accounted: 0, heap: heap::current() };
+        for item in &items {
+            list.allocate(item);
+        }
+        list.items = items;
+        list
+    }
+
+    pub fn push(&mut self, item: ExpressionRes) {
+        self.allocate(&item);
+        self.items.push(item);
+    }
+
+    pub fn pop(&mut self) -> Option<ExpressionRes> {
+        let item = self.items.pop()?;
+        self.release(&item);
+        Some(item)
+    }
+
+    pub fn insert(&mut self, index: usize, item: ExpressionRes) {
+        self.allocate(&item);
+        self.items.insert(index, item);
+    }
+
+    pub fn remove(&mut self, index: usize) -> ExpressionRes {
+        let item = self.items.remove(index);
+        self.release(&item);
+        item
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.heap.release(self.accounted);
+        self.accounted = 0;
+    }
+
+    pub fn set(&mut self, index: usize, item: ExpressionRes) {
+        self.allocate(&item);
+        let previous = std::mem::replace(&mut self.items[index], item);
+        self.release(&previous);
+    }
+
+    fn allocate(&mut self, item: &ExpressionRes) {
+        let size = heap::value_size(item);
+        self.heap.allocate(size);
+        self.accounted += size;
+    }
+
+    fn release(&mut self, item: &ExpressionRes) {
+        let size = heap::value_size(item).min(self.accounted);
+        self.heap.release(size);
+        self.accounted -= size;
+    }
+}
+
+impl Deref for List {
+    type Target = Vec<ExpressionRes>;
+
+    fn deref(&self) -> &Vec<ExpressionRes> {
+        &self.items
+    }
+}
+
+impl Drop for List {
+    fn drop(&mut self) {
+        self.heap.release(self.accounted);
+    }
+}
+
+/// Hash map that remembers the order in which keys were first inserted.
+#[derive(Debug)]
+pub struct Map {
+    entries: Vec<(MapKey, ExpressionRes)>,
+    positions: HashMap<MapKey, usize>,
+    accounted: usize,
+    heap: Rc<Heap>,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map {
+            entries: vec![],
+            positions: HashMap::new(),
+            accounted: 0,
+            heap: heap::current(),
+        }
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&ExpressionRes> {
+        self.positions.get(key).map(|position| &self.entries[*position].1)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: ExpressionRes) {
+        let size = Map::entry_size(&key, &value);
+        self.heap.allocate(size);
+        self.accounted += size;
+        match self.positions.get(&key) {
+            Some(position) => {
+                let previous = std::mem::replace(&mut self.entries[*position].1, value);
+                self.release(Map::entry_size(&key, &previous));
+            }
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
             }
         }
-        panic!("did not find the method def")
     }
-}
\ No newline at end of file
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<ExpressionRes> {
+        let position = self.positions.remove(key)?;
+        let (key, value) = self.entries.remove(position);
+        self.release(Map::entry_size(&key, &value));
+        for (_, index) in self.positions.iter_mut() {
+            if *index > position {
+                *index -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+        self.heap.release(self.accounted);
+        self.accounted = 0;
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn keys(&self) -> Vec<MapKey> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn entries(&self) -> &Vec<(MapKey, ExpressionRes)> {
+        &self.entries
+    }
+
+    fn entry_size(key: &MapKey, value: &ExpressionRes) -> usize {
+        let key_text = match key {
+            MapKey::String(text) => text.len(),
+            _ => 0,
+        };
+        size_of::<MapKey>() + key_text + heap::value_size(value)
+    }
+
+    fn release(&mut self, size: usize) {
+        let size = size.min(self.accounted);
+        self.heap.release(size);
+        self.accounted -= size;
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        self.heap.release(self.accounted);
+    }
+}
@@ -1,139 +1,248 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
-use crate::env::environment::Environment;
-use crate::expressions::expression::{Expression, ExpressionRes};
-use crate::program::program::ProgramEnvs;
+use crate::env::environment::Slot;
+use crate::expressions::expression::Expression;
+use crate::interner::Symbol;
 use crate::statements::statement::Statement;
 
-pub struct CaptureResolver<'a> {
-    captured: HashMap<String, Rc<RefCell<ExpressionRes>>>,
-    parameters: &'a Vec<ExpressionRes>,
-    local_vars: Vec<String>,
-    env: Rc<RefCell<ProgramEnvs>>,
+/// Assigns every variable the slot it lives in, mirroring the scopes the interpreter pushes at runtime:
+/// blocks, for loops, each for-in iteration, catch clauses, calls and the `this` of bound methods.
+/// Names not declared in any enclosing scope are globals and stay looked up by name.
+pub struct SlotResolver {
+    scopes: Vec<Vec<Symbol>>,
 }
 
 pub trait Resolve {
-     fn resolve_statement(&mut self, statement: &Statement);
-    fn resolve_expression(&mut self, expression: &Expression);
+    fn resolve_statement(&mut self, statement: &mut Statement);
+    fn resolve_expression(&mut self, expression: &mut Expression);
 }
 
-impl Resolve for CaptureResolver<'_> {
-    fn resolve_statement(&mut self, statement: &Statement) {
+impl Resolve for SlotResolver {
+    fn resolve_statement(&mut self, statement: &mut Statement) {
         match statement {
-            Statement::Stmt { expr } => {}
-            Statement::IfStatement { expr, body, else_body } => {
+            Statement::Stmt { expr } => {
+                self.resolve_expression(expr);
+            }
+            Statement::IfStatement { expr, body, else_body, .. } => {
                 self.resolve_expression(expr);
                 self.resolve_statement(body);
-                match else_body {
-                    None => {}
-                    Some(ref val)=> {
-                        self.resolve_statement(&*val)
-
-                    }
+                if let Some(else_body) = else_body {
+                    self.resolve_statement(else_body);
                 }
             }
-            Statement::FunStatement { identifier, args, block } => {}
-            Statement::WhileStatement { expr, body } => {
+            Statement::FunStatement { identifier, args, block, slot } => {
+                *slot = self.declare(identifier.value);
+                if let Some(block) = block {
+                    self.function(args, block);
+                }
+            }
+            Statement::WhileStatement { expr, body, .. } => {
                 self.resolve_expression(expr);
                 self.resolve_statement(body);
             }
-            Statement::ForStatement {
-                initiation, condition,
-                increment, body
-            } => {
-                self.resolve_statement(&*initiation.clone().unwrap());
-                self.resolve_statement(&*condition.clone().unwrap());
-                self.resolve_statement(&*body.clone());
+            Statement::ForStatement { initiation, condition, increment, body, .. } => {
+                self.scopes.push(vec![]);
+                for part in [initiation, condition, increment].into_iter().flatten() {
+                    self.resolve_statement(part);
+                }
+                self.resolve_statement(body);
+                self.scopes.pop();
+            }
+            Statement::ForInStatement { identifier, iterable, body } => {
+                self.resolve_expression(iterable);
+                self.scopes.push(vec![identifier.value]);
+                self.resolve_statement(body);
+                self.scopes.pop();
             }
-            Statement::PrintStatement { expr } => {
+            Statement::PrintStatement { expr } | Statement::ThrowStatement { expr, .. } => {
                 self.resolve_expression(expr);
             }
             Statement::BlockStatement { statements } => {
-                for statement in statements {
-                    self.resolve_statement(statement)
+                self.scopes.push(vec![]);
+                // functions and classes of a block can call each other whatever order they are declared in
+                for statement in statements.iter_mut() {
+                    match statement.as_mut() {
+                        Statement::FunStatement { identifier, .. } | Statement::ClassDeclaration { identifier, .. } => {
+                            self.declare(identifier.value);
+                        }
+                        _ => {}
+                    }
+                }
+                for statement in statements.iter_mut() {
+                    self.resolve_statement(statement);
                 }
+                self.scopes.pop();
             }
             Statement::VarDeclaration { identifier, expr } => {
-                if let Expression::VariableExpr{ token_type, ref value } = **identifier {
-                    self.local_vars.push(value.clone().to_string())
+                // a lambda can call itself through the variable it is assigned to
+                let recursive = matches!(expr.as_deref(), Some(Expression::Lambda { .. }));
+                if recursive {
+                    self.declare_variable(identifier);
+                }
+                if let Some(value) = expr {
+                    self.resolve_expression(value);
+                }
+                if !recursive {
+                    self.declare_variable(identifier);
                 }
-                self.resolve_expression(&*expr.clone().unwrap());
             }
             Statement::ReturnStatement { expr } => {
-                self.resolve_expression(expr.as_ref().unwrap().deref());
+                if let Some(value) = expr {
+                    self.resolve_expression(value);
+                }
+            }
+            Statement::TryStatement { body, catch_name, catch_body, finally_body } => {
+                self.resolve_statement(body);
+                if let (Some(name), Some(catch_body)) = (catch_name, catch_body) {
+                    self.scopes.push(vec![name.value]);
+                    self.resolve_statement(catch_body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_body) = finally_body {
+                    self.resolve_statement(finally_body);
+                }
+            }
+            Statement::ClassDeclaration { identifier, functions, slot } => {
+                *slot = self.declare(identifier.value);
+                for function in functions {
+                    if let Statement::FunStatement { args, block: Some(block), .. } = function.as_mut() {
+                        self.scopes.push(vec![Symbol::intern("this")]);
+                        self.function(args, block);
+                        self.scopes.pop();
+                    }
+                }
             }
-            Statement::ClassDeclaration { .. } => {}
         }
     }
 
-    fn resolve_expression(&mut self, expression: &Expression) {
+    fn resolve_expression(&mut self, expression: &mut Expression) {
         match expression {
-            Expression::Expr { value, equality } => {
-                self.resolve_expression(&*equality.clone().unwrap())
-            }
-            Expression::Equality { token, value } => {}
-            Expression::Comparison { token_type, value } => {}
-            Expression::GroupingExpr { value } => {
-                self.resolve_expression(&*value.clone())
-            }
-            Expression::BinaryExpr { token, rhs, lhs } => {
-                self.resolve_expression(&*rhs.clone());
-                self.resolve_expression(&*lhs.clone());
-            }
-            Expression::UnaryExpr { token, rhs } => {
-                self.resolve_expression(&*rhs.clone());
-            }
-            Expression::LiteralExpr { token_type, value } => {}
-            Expression::VariableExpr { token_type, value } => {
-                if value != "EOF" && !self.local_vars.contains(value) {
-                    println!("{:#?}", &self.local_vars);
-                    let mut resolve = true;
-                    for parameter in self.parameters {
-                        if parameter.str == value.to_string() {
-                            resolve = false;
-                        }
-                    }
-                    if resolve {
-                        match self.env.borrow_mut().lookup_var(value.clone()) {
-                           reference => {
-                                self.captured.insert(value.clone(), reference);
-                            }
-                        }
-                    }
+            Expression::Expr { equality, .. } => {
+                if let Some(equality) = equality {
+                    self.resolve_expression(equality);
+                }
+            }
+            Expression::Equality { .. } | Expression::Comparison { .. } | Expression::LiteralExpr { .. } | Expression::NumberExpr { .. } => {}
+            Expression::GroupingExpr { value } | Expression::UnaryExpr { rhs: value, .. } => {
+                self.resolve_expression(value);
+            }
+            Expression::BinaryExpr { rhs, lhs, .. } | Expression::Logical { rhs, lhs, .. } => {
+                self.resolve_expression(lhs);
+                self.resolve_expression(rhs);
+            }
+            Expression::VariableExpr { value, slot, .. } => {
+                *slot = self.lookup(*value);
+            }
+            Expression::Assignment { identifier, value } | Expression::CompoundAssignment { identifier, value, .. } => {
+                self.resolve_expression(identifier);
+                self.resolve_expression(value);
+            }
+            Expression::Increment { identifier, .. } => {
+                self.resolve_expression(identifier);
+            }
+            Expression::Call { identifier, args, .. } => {
+                self.resolve_expression(identifier);
+                for arg in args {
+                    self.resolve_expression(arg);
+                }
+            }
+            Expression::Get { expr, .. } => {
+                self.resolve_expression(expr);
+            }
+            Expression::Lambda { args, block } => {
+                self.function(args, block);
+            }
+            Expression::ListExpr { items } |
+            Expression::Interpolation { parts: items } => {
+                for item in items {
+                    self.resolve_expression(item);
                 }
             }
-            Expression::Assignment { identifier, value } => {
-                if let Expression::VariableExpr{ token_type, ref value } = **identifier {
-                    self.local_vars.push(value.clone().to_string())
+            Expression::MapExpr { entries } => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
                 }
-                self.resolve_expression(&*value.clone());
             }
-            Expression::Logical { token, rhs, lhs } => {
-                self.resolve_expression(&*rhs.clone());
-                self.resolve_expression(&*lhs.clone());
+            Expression::Index { expr, index } => {
+                self.resolve_expression(expr);
+                self.resolve_expression(index);
+            }
+            Expression::Conditional { condition, then_branch, else_branch, .. } => {
+                self.resolve_expression(condition);
+                self.resolve_expression(then_branch);
+                self.resolve_expression(else_branch);
             }
-            Expression::Call { identifier, args } => {}
-            Expression::Get { .. } => {}
         }
     }
 }
 
-impl CaptureResolver<'_> {
-    pub fn new_with_environment(env: Rc<RefCell<ProgramEnvs>>, parameters: &Vec<ExpressionRes>) ->  CaptureResolver  {
-            CaptureResolver {
-                captured: Default::default(),
-                parameters,
-                local_vars: vec![],
-                env,
+impl SlotResolver {
+    pub fn new() -> SlotResolver {
+        SlotResolver { scopes: vec![] }
+    }
+
+    pub fn resolve_program(&mut self, program: &mut [Box<Statement>]) {
+        for statement in program {
+            self.resolve_statement(statement);
+        }
+    }
+
+    // calls run in a scope holding the parameters, in the order they are passed
+    fn function(&mut self, args: &mut [Expression], block: &mut Rc<Statement>) {
+        self.scopes.push(vec![]);
+        for arg in args {
+            self.declare_variable(arg);
+        }
+        // no function shares the body before the program runs, so this does not copy it
+        self.resolve_statement(Rc::make_mut(block));
+        self.scopes.pop();
+    }
+
+    fn declare_variable(&mut self, identifier: &mut Expression) {
+        if let Expression::VariableExpr { value, slot, .. } = identifier {
+            *slot = self.declare(*value);
+        }
+    }
+
+    // declaring a name twice in one scope reuses its slot
+    fn declare(&mut self, name: Symbol) -> Slot {
+        let Some(scope) = self.scopes.last_mut() else {
+            return Slot::Global;
+        };
+        let index = match scope.iter().position(|declared| *declared == name) {
+            Some(index) => index,
+            None => {
+                scope.push(name);
+                scope.len() - 1
             }
+        };
+        Slot::Local { depth: 0, index }
     }
 
-    pub fn get_captured(mut self) -> HashMap<String, Rc<RefCell<ExpressionRes>>> {
-        let CaptureResolver { captured , ..} = self;
-          return captured;
+    fn lookup(&self, name: Symbol) -> Slot {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.iter().position(|declared| *declared == name) {
+                return Slot::Local { depth, index };
+            }
+        }
+        Slot::Global
     }
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_resolve_slots() {
+    let program = "var g = 1;
+    fun f(a) {
+        var b = a;
+        return fun() { return a + b + g; };
+    }";
+    let tokens = crate::token::Scanner::new().tokenize_string(program.to_string());
+    let mut statements = crate::parser::Parser::new(tokens).program();
+    SlotResolver::new().resolve_program(&mut statements);
+    let printed = format!("{:?}", statements);
+    // past the lambda's parameters, b sits in the body block of f and a in the parameters of f
+    assert!(printed.contains("value: \"g\", line: 3, slot: Global"));
+    assert!(printed.contains("value: \"b\", line: 3, slot: Local { depth: 2, index: 0 }"));
+    assert!(printed.contains("value: \"a\", line: 3, slot: Local { depth: 3, index: 0 }"));
+}
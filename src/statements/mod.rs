@@ -1,5 +1,6 @@
 pub mod statement;
 pub mod stmt_visitor;
+pub mod printer;
 
 
 
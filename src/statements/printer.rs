@@ -0,0 +1,170 @@
+use crate::expressions::expression::Expression;
+use crate::statements::statement::Statement;
+use crate::token::TokenType;
+
+/// Renders the parsed program as an indented tree of statements, expressions are written as s-expressions.
+pub fn print_program(program: &[Box<Statement>]) -> String {
+    let mut lines = vec![];
+    for statement in program {
+        statement_lines(statement, 0, &mut lines);
+    }
+    lines.join("\n")
+}
+
+fn statement_lines(statement: &Statement, indent: usize, lines: &mut Vec<String>) {
+    match statement {
+        Statement::Stmt { expr } => push(lines, indent, format!("expr {}", expression(expr))),
+        Statement::PrintStatement { expr } => push(lines, indent, format!("print {}", expression(expr))),
+        Statement::VarDeclaration { identifier, expr } => match expr {
+            None => push(lines, indent, format!("var {}", expression(identifier))),
+            Some(value) => push(lines, indent, format!("var {} = {}", expression(identifier), expression(value))),
+        },
+        Statement::ReturnStatement { expr } => match expr {
+            None => push(lines, indent, "return".to_string()),
+            Some(value) => push(lines, indent, format!("return {}", expression(value))),
+        },
+        Statement::ThrowStatement { expr, .. } => push(lines, indent, format!("throw {}", expression(expr))),
+        Statement::IfStatement { expr, body, else_body, .. } => {
+            push(lines, indent, format!("if {}", expression(expr)));
+            statement_lines(body, indent + 1, lines);
+            if let Some(else_body) = else_body {
+                push(lines, indent, "else".to_string());
+                statement_lines(else_body, indent + 1, lines);
+            }
+        }
+        Statement::WhileStatement { expr, body, .. } => {
+            push(lines, indent, format!("while {}", expression(expr)));
+            statement_lines(body, indent + 1, lines);
+        }
+        Statement::ForStatement { initiation, condition, increment, body, .. } => {
+            let part = |part: &Option<Box<Statement>>| match part.as_deref() {
+                None => "_".to_string(),
+                Some(Statement::Stmt { expr }) => expression(expr),
+                Some(other) => inline(other),
+            };
+            push(lines, indent, format!("for {}; {}; {}", part(initiation), part(condition), part(increment)));
+            statement_lines(body, indent + 1, lines);
+        }
+        Statement::ForInStatement { identifier, iterable, body } => {
+            push(lines, indent, format!("for {} in {}", identifier.value, expression(iterable)));
+            statement_lines(body, indent + 1, lines);
+        }
+        Statement::BlockStatement { statements } => {
+            push(lines, indent, "block".to_string());
+            for statement in statements {
+                statement_lines(statement, indent + 1, lines);
+            }
+        }
+        Statement::FunStatement { identifier, args, block, .. } => {
+            push(lines, indent, format!("fun {}({})", identifier.value, parameters(args)));
+            if let Some(block) = block {
+                statement_lines(block, indent + 1, lines);
+            }
+        }
+        Statement::ClassDeclaration { identifier, functions, .. } => {
+            push(lines, indent, format!("class {}", identifier.value));
+            for function in functions {
+                statement_lines(function, indent + 1, lines);
+            }
+        }
+        Statement::TryStatement { body, catch_name, catch_body, finally_body } => {
+            push(lines, indent, "try".to_string());
+            statement_lines(body, indent + 1, lines);
+            if let Some(catch_body) = catch_body {
+                let name = catch_name.as_ref().map_or(String::new(), |name| format!(" {}", name.value));
+                push(lines, indent, format!("catch{}", name));
+                statement_lines(catch_body, indent + 1, lines);
+            }
+            if let Some(finally_body) = finally_body {
+                push(lines, indent, "finally".to_string());
+                statement_lines(finally_body, indent + 1, lines);
+            }
+        }
+    }
+}
+
+fn push(lines: &mut Vec<String>, indent: usize, text: String) {
+    lines.push(format!("{}{}", "  ".repeat(indent), text));
+}
+
+// a statement on one line, for lambda bodies and for loop initializers
+fn inline(statement: &Statement) -> String {
+    let mut lines = vec![];
+    statement_lines(statement, 0, &mut lines);
+    let lines: Vec<&str> = lines.iter().map(|line| line.trim()).filter(|line| *line != "block").collect();
+    lines.join("; ")
+}
+
+fn parameters(args: &[Expression]) -> String {
+    args.iter().map(expression).collect::<Vec<String>>().join(", ")
+}
+
+fn expression(expr: &Expression) -> String {
+    match expr {
+        Expression::Expr { equality, .. } => match equality {
+            None => "nil".to_string(),
+            Some(value) => expression(value),
+        },
+        Expression::Equality { value, .. } | Expression::Comparison { value, .. } => value.clone(),
+        Expression::GroupingExpr { value } => format!("(group {})", expression(value)),
+        Expression::BinaryExpr { token, rhs, lhs } | Expression::Logical { token, rhs, lhs } => {
+            format!("({} {} {})", token.value, expression(lhs), expression(rhs))
+        }
+        Expression::UnaryExpr { token, rhs } => format!("({} {})", token.value, expression(rhs)),
+        Expression::LiteralExpr { token_type, value } => match token_type {
+            TokenType::String => format!("{:?}", value),
+            _ => value.to_string(),
+        },
+        Expression::NumberExpr { value } => value.to_string(),
+        Expression::VariableExpr { value, .. } => value.to_string(),
+        Expression::Assignment { identifier, value } => format!("(= {} {})", expression(identifier), expression(value)),
+        Expression::CompoundAssignment { identifier, token, value } => {
+            format!("({} {} {})", token.value, expression(identifier), expression(value))
+        }
+        Expression::Increment { identifier, token, prefix } => match prefix {
+            true => format!("({} {})", token.value, expression(identifier)),
+            false => format!("({} {})", expression(identifier), token.value),
+        },
+        Expression::Call { identifier, args, .. } => {
+            let args: Vec<String> = args.iter().map(|arg| format!(" {}", expression(arg))).collect();
+            format!("(call {}{})", expression(identifier), args.concat())
+        }
+        Expression::Get { expr, name } => format!("(. {} {})", expression(expr), name),
+        Expression::Index { expr, index } => format!("([] {} {})", expression(expr), expression(index)),
+        Expression::Conditional { condition, then_branch, else_branch, .. } => {
+            format!("(? {} {} {})", expression(condition), expression(then_branch), expression(else_branch))
+        }
+        Expression::Lambda { args, block } => format!("(fun ({}) {{ {} }})", parameters(args), inline(block)),
+        Expression::ListExpr { items } => {
+            let items: Vec<String> = items.iter().map(|item| expression(item)).collect();
+            format!("(list {})", items.join(" "))
+        }
+        Expression::MapExpr { entries } => {
+            let entries: Vec<String> = entries.iter()
+                .map(|(key, value)| format!("{}: {}", expression(key), expression(value)))
+                .collect();
+            format!("(map {})", entries.join(", "))
+        }
+        Expression::Interpolation { parts } => {
+            let parts: Vec<String> = parts.iter().map(|part| expression(part)).collect();
+            format!("(interpolate {})", parts.join(" "))
+        }
+    }
+}
+
+#[test]
+fn test_print_program() {
+    let program = "var xs = [1, \"a\"];
+    fun add(a, b) { return a + b; }
+    if (!done) print add(1, 2); else xs[0] += 1;";
+    let tokens = crate::token::Scanner::new().tokenize_string(program.to_string());
+    let statements = crate::parser::Parser::new(tokens).program();
+    assert_eq!(print_program(&statements), "var xs = (list 1 \"a\")
+fun add(a, b)
+  block
+    return (+ a b)
+if (! done)
+  print (call add 1 2)
+else
+  expr (+= ([] xs 0) 1)");
+}
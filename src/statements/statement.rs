@@ -1,6 +1,8 @@
 use std::collections::LinkedList;
 use std::fmt::Debug;
+use std::rc::Rc;
 
+use crate::env::environment::Slot;
 use crate::expressions::expression::{Expression, ExpressionRes};
 use crate::token::Token;
 
@@ -13,21 +15,32 @@ pub enum Statement {
         expr: Expression,
         body: Box<Statement>,
         else_body: Option<Box<Statement>>,
+        // line of the keyword, a condition that is not a boolean is reported there
+        line: usize,
     },
     FunStatement {
         identifier: Token,
         args: Vec<Expression>,
-        block: Option<Box<Statement>>,
+        // shared with the functions created from it
+        block: Option<Rc<Statement>>,
+        slot: Slot,
     },
     WhileStatement {
         expr: Box<Expression>,
         body: Box<Statement>,
+        line: usize,
     },
     ForStatement {
         initiation: Option<Box<Statement>>,
         condition: Option<Box<Statement>>,
         increment: Option<Box<Statement>>,
         body: Box<Statement>,
+        line: usize,
+    },
+    ForInStatement {
+        identifier: Token,
+        iterable: Box<Expression>,
+        body: Box<Statement>,
     },
     PrintStatement {
         expr: Box<Expression>,
@@ -41,9 +54,20 @@ pub enum Statement {
     },
     ClassDeclaration {
         identifier: Token,
-        functions: Vec<Box<Statement>>
+        functions: Vec<Box<Statement>>,
+        slot: Slot,
     },
     ReturnStatement {
         expr: Option<Box<Expression>>,
     },
+    ThrowStatement {
+        expr: Box<Expression>,
+        line: usize,
+    },
+    TryStatement {
+        body: Box<Statement>,
+        catch_name: Option<Token>,
+        catch_body: Option<Box<Statement>>,
+        finally_body: Option<Box<Statement>>,
+    },
 }
\ No newline at end of file
@@ -1,238 +1,226 @@
 use std::cell::RefCell;
+use std::collections::LinkedList;
 use std::ops::{Deref, DerefMut};
 use std::os::linux::raw::stat;
 use std::rc::Rc;
+use std::time::Instant;
 
 use log::{trace,info, warn, error};
 
-
-use crate::env::environment::Environment;
-use crate::expressions::expression::{ExpressionRes, ExprResType};
-use crate::expressions::expression::ExprResType::{Boolean, Identifier, Instance};
-use crate::expressions::visitor::{ExpressionInterpreter, Visitor};
+use crate::env::environment::Slot;
+use crate::expressions::expression::{Expression, ExpressionRes, ExprResType};
+use crate::expressions::expression::ExprResType::{Identifier, Instance};
+use crate::expressions::visitor::{parameters, ExpressionInterpreter, Visitor};
+use crate::interner::Symbol;
 use crate::program::program::ProgramEnvs;
+use crate::program::errors::{self, RuntimeError};
+use crate::program::{natives, operations};
+use crate::program::gc::{self, GcStats};
+use crate::program::heap;
+use crate::program::execution::{ExecutionState, Limits};
 use crate::program::runtime::{Class, Method};
-use crate::resolver_visitor::resolver::{CaptureResolver, Resolve};
+use crate::resolver_visitor::resolver::SlotResolver;
 use crate::statements::statement::Statement;
+use crate::token::Token;
 use crate::statements::stmt_visitor::StatementRes::{Expr, Void};
 
+// the single variable of a for-in iteration or a catch clause is the first in its own scope
+const CAUGHT: Slot = Slot::Local { depth: 0, index: 0 };
+
 pub trait StmtVisitor {
-    fn eval(&mut self, object: &Statement) -> Result<StatementRes, String>;
+    fn eval(&mut self, object: &Statement) -> Result<StatementRes, RuntimeError>;
 }
 
 pub struct StatementInterpreter {
-    pub expression_visitor: Rc<dyn Visitor<ExpressionRes>>,
+    pub expression_visitor: Rc<ExpressionInterpreter>,
     pub envs: Rc<RefCell<ProgramEnvs>>,
     pub statements: Vec<Box<Statement>>,
 }
 
+// like the expression visitor, each arm calls out to its own method to keep the frame of eval small
 impl StmtVisitor for StatementInterpreter {
-    fn eval(&mut self, object: &Statement) -> Result<StatementRes, String> {
+    fn eval(&mut self, object: &Statement) -> Result<StatementRes, RuntimeError> {
+        self.expression_visitor.state.borrow_mut().charge()?;
         match object {
             Statement::Stmt { expr } => {
-                trace!("Entering {} ", "Stmt");
-                self.expression_visitor.eval((**expr).clone());
+                self.expression_visitor.eval(expr)?;
                 Ok(Void)
             }
-            Statement::IfStatement { expr, body, else_body } => {
-                trace!("Entering {} ", "IfStatement");
-                let res = self.expression_visitor.eval((*expr).clone());
-                if res.type_ != Boolean {
-                    panic!("if (.expr.) not evaluatable to bool")
-                }
-                if res.boolean {
-                    match self.eval(body) {
-                        Ok(Void) => {}
-                        Ok(Expr { res }) => { return Ok(Expr { res }); }
-                        Err(_) => {}
-                    };
-                } else {
-                    match else_body.as_ref() {
-                        None => {}
-                        Some(value) => {
-                            match self.eval(value) {
-                                Ok(Void) => {}
-                                Ok(Expr { res }) => { return Ok(Expr { res }); }
-                                Err(_) => {}
-                            };
-                        }
-                    };
-                }
-                Ok(Void)
+            Statement::IfStatement { expr, body, else_body, line } => self.if_statement(expr, body, else_body, *line),
+            Statement::FunStatement { identifier, args, block, slot } => self.fun_statement(identifier, args, block, *slot),
+            Statement::WhileStatement { expr, body, line } => self.while_statement(expr, body, *line),
+            Statement::ForStatement { initiation, condition, increment, body, line } => {
+                self.envs.borrow_mut().push();
+                let result = self.for_loop(initiation, condition, increment, body, *line);
+                self.envs.borrow_mut().pop();
+                result
             }
-            Statement::FunStatement { identifier, args, block } => {
-                trace!("Entering {} ", "FunStatement");
-                let mut arguments = vec![];
-                for arg in args {
-                    arguments.push(self.expression_visitor.eval((*arg).clone()));
-                }
-                let body1 = (**block.as_ref().unwrap()).clone();
-                let mut resolver = CaptureResolver::new_with_environment(self.envs.clone(), &arguments);
-                resolver.resolve_statement(&body1);
-                let map = resolver.get_captured();
-                let mut environment = Environment::new_with_enclosing(self.envs.borrow().get_top());
-                for (key, value) in map.into_iter() {
-                    environment.define_ref(key, value)
-                }
-                let method = ExpressionRes::from_method(Method::new(identifier.value.clone(),
-                                                                    arguments,
-                                                                    body1, environment));
+            Statement::ForInStatement { identifier, iterable, body } => self.for_in_statement(identifier, iterable, body),
+            Statement::PrintStatement { expr } => self.print_statement(expr),
+            Statement::BlockStatement { statements } => self.block_statement(statements),
+            Statement::VarDeclaration { identifier, expr } => self.var_declaration(identifier, expr),
+            Statement::ReturnStatement { expr } => self.return_statement(expr),
+            Statement::ThrowStatement { expr, line } => self.throw_statement(expr, *line),
+            Statement::TryStatement { body, catch_name, catch_body, finally_body } => {
+                self.try_statement(body, catch_name, catch_body, finally_body)
+            }
+            Statement::ClassDeclaration { identifier, functions, slot } => self.class_declaration(identifier, functions, *slot),
+        }
+    }
+}
 
+impl Limits for StatementInterpreter {
+    fn state(&self) -> &Rc<RefCell<ExecutionState>> {
+        &self.expression_visitor.state
+    }
+}
 
-                // figure out the what variables does the method contain
-                self.envs.try_borrow_mut().unwrap().define_at_top(identifier.value.clone(),
-                                                                  method);
-                return Ok(Void);
-            }
-            Statement::WhileStatement { expr, body: statements } => {
-                trace!("Entering {} ", "WhileStatement");
-                let condition = expr.clone();
-                let mut res1 = self.expression_visitor.eval((*condition).clone());
-
-                while res1.boolean {
-                    match self.eval(statements) {
-                        Ok(Void) => {}
-                        Ok(Expr { res }) => { return Ok(Expr { res }); }
-                        Err(_) => {}
-                    };
-                    res1 = self.expression_visitor.eval((*condition).clone());
-                }
-                Ok(Void)
+impl StatementInterpreter {
+    fn if_statement(&mut self, expr: &Expression, body: &Statement, else_body: &Option<Box<Statement>>, line: usize) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "IfStatement");
+        if self.condition(expr, line)? {
+            self.eval(body)
+        } else {
+            match else_body.as_ref() {
+                None => Ok(Void),
+                Some(value) => self.eval(value),
             }
-            Statement::ForStatement { initiation, condition, increment, body } => {
-                trace!("Entering {} ", "ForStatement");
-                match initiation {
-                    None => {}
-                    Some(value) => {
-                        self.eval(value);
-                    }
-                }
+        }
+    }
 
-                let mut res1 = ExpressionRes::from_bool(true);
-
-                match condition {
-                    None => { res1 = ExpressionRes::from_bool(false) }
-                    Some(value) => {
-                        let statement = value.clone();
-                        match *statement {
-                            Statement::Stmt { expr } => {
-                                res1 = self.expression_visitor.eval(*expr);
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+    fn fun_statement(&mut self, identifier: &Token, args: &[Expression], block: &Option<Rc<Statement>>, slot: Slot) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "FunStatement");
+        let body = block.clone().unwrap();
+        let method = ExpressionRes::from_method(Method::new(identifier.value.to_string(),
+                                                            parameters(args),
+                                                            body, self.envs.borrow().get_top()));
+        self.envs.borrow().define(slot, identifier.value, method);
+        Ok(Void)
+    }
 
-                while res1.boolean {
-                    match self.eval(body) {
-                        Ok(Void) => {}
-                        Ok(Expr { res }) => { return Ok(Expr { res }); }
-                        Err(_) => {}
-                    };
-                    match increment {
-                        None => {}
-                        Some(value) => { self.eval(value); }
-                    }
-                    match condition {
-                        None => { res1 = ExpressionRes::from_bool(false) }
-                        Some(value) => {
-                            let statement = value.clone();
-                            match *statement {
-                                Statement::Stmt { expr } => {
-                                    res1 = self.expression_visitor.eval(*expr);
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                return Ok(Void);
+    fn while_statement(&mut self, expr: &Expression, statements: &Statement, line: usize) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "WhileStatement");
+        while self.condition(expr, line)? {
+            if let Expr { res } = self.eval(statements)? {
+                return Ok(Expr { res });
             }
-            Statement::PrintStatement { expr } => {
-                trace!("Entering {} ", "PrintStatement");
-                let x1 = (*expr).clone();
-                let res = self.expression_visitor.eval(*x1);
-
-                if res.type_ == Identifier {
-                    let x = self.lookup_variable(res.str);
-                    println!("{}", x.borrow().print())
-                } else {
-                    println!("{}", res.print())
-                }
-                return Ok(Void);
+        }
+        Ok(Void)
+    }
+
+    fn for_in_statement(&mut self, identifier: &Token, iterable: &Expression, body: &Statement) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "ForInStatement");
+        let iterable = self.evaluate(iterable)?;
+        let iterator = operations::iterator(&mut self.host(), iterable)?;
+        let items = match &iterator.list {
+            Some(items) => items.borrow().to_vec(),
+            None => return self.for_in_iterator(identifier, iterator, body),
+        };
+        for item in items {
+            if let Expr { res } = self.for_in_body(identifier, item, body)? {
+                return Ok(Expr { res });
             }
-            Statement::BlockStatement { statements } => {
-                trace!("Entering {} ", "BlockStatement");
-                {
-                    let mut ref_mut = self.envs.try_borrow_mut().unwrap();
-                    let envs = ref_mut.deref_mut();
-                    envs.push();
-                }
-                for statement in statements {
-                    if matches!(**statement, Statement::ReturnStatement{..} ) {
-                        match self.eval(statement) {
-                            Ok(Void) => {}
-                            Ok(Expr { res }) => { return Ok(Expr { res }); }
-                            Err(_) => {}
-                        };
-                    } else {
-                        self.eval(statement);
-                    }
-                }
-                let mut ref_mut_post = self.envs.try_borrow_mut().unwrap();
-                let envs_after = ref_mut_post.deref_mut();
-                envs_after.pop();
-                return Ok(Void);
+        }
+        Ok(Void)
+    }
+
+    fn print_statement(&mut self, expr: &Expression) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "PrintStatement");
+        let res = self.evaluate(expr)?;
+        println!("{}", operations::stringify(&mut self.host(), &res)?);
+        Ok(Void)
+    }
+
+    fn block_statement(&mut self, statements: &LinkedList<Box<Statement>>) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "BlockStatement");
+        {
+            let mut ref_mut = self.envs.try_borrow_mut().unwrap();
+            let envs = ref_mut.deref_mut();
+            envs.push();
+        }
+        let result = self.block(statements);
+        let mut ref_mut_post = self.envs.try_borrow_mut().unwrap();
+        let envs_after = ref_mut_post.deref_mut();
+        envs_after.pop();
+        result
+    }
+
+    fn var_declaration(&mut self, identifier: &Expression, expr: &Option<Box<Expression>>) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "VarDeclaration");
+        let content = match expr {
+            None => ExpressionRes::from_none(),
+            Some(value) => self.evaluate(value)?,
+        };
+        if let Expression::VariableExpr { value, slot, .. } = identifier {
+            self.envs.borrow().define(*slot, *value, content);
+        }
+        Ok(Void)
+    }
+
+    fn return_statement(&mut self, expr: &Option<Box<Expression>>) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "ReturnStatement");
+        let res = match expr {
+            None => ExpressionRes::from_none(),
+            Some(value) => self.evaluate(value)?,
+        };
+        Ok(Expr { res })
+    }
+
+    fn throw_statement(&mut self, expr: &Expression, line: usize) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "ThrowStatement");
+        let value = self.evaluate(expr)?;
+        errors::set_line(line);
+        errors::mark_error_line(&value);
+        Err(errors::thrown(value))
+    }
+
+    fn try_statement(&mut self, body: &Statement, catch_name: &Option<Token>, catch_body: &Option<Box<Statement>>,
+                     finally_body: &Option<Box<Statement>>) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "TryStatement");
+        let top = self.envs.borrow().get_top();
+        let mut outcome = self.eval(body);
+        if let Err(error) = &outcome {
+            self.envs.borrow_mut().restore(top.clone());
+            if !error.kind.is_catchable() {
+                return outcome;
             }
-            Statement::VarDeclaration { identifier, expr } => {
-                trace!("Entering {} ", "VarDeclaration");
-                let identifier_res = self.expression_visitor.eval(*(*identifier).clone());
-                let expression = *((*expr).clone().unwrap()).clone();
-                let content = self.expression_visitor.eval(expression);
-                if content.type_ == Identifier {
-                    let mut ref_mut = self.envs.try_borrow_mut().unwrap();
-                    let envs = ref_mut.deref_mut();
-                    let value = envs.lookup_var(content.str);
-                    envs.define_ref_at_top(identifier_res.str, value.clone())
-                } else if content.type_ == ExprResType::Instance {
-                    let mut ref_mut = self.envs.try_borrow_mut().unwrap();
-                    let envs = ref_mut.deref_mut();
-                    envs.define_ref_at_top(identifier_res.str, Rc::new(RefCell::new(content)))
-                } else {
-                    let mut ref_mut = self.envs.try_borrow_mut().unwrap();
-                    let envs = ref_mut.deref_mut();
-                    envs.define_at_top(identifier_res.str, ExpressionRes::copy(&content))
-                };
-                return Ok(Void);
+        }
+        if let (Err(error), Some(name), Some(catch_body)) = (&outcome, catch_name, catch_body) {
+            let caught = ExpressionRes::copy(&error.value);
+            self.envs.borrow_mut().push();
+            self.envs.borrow().define(CAUGHT, name.value, caught);
+            outcome = self.eval(catch_body);
+            self.envs.borrow_mut().restore(top);
+        }
+        if let Err(error) = &outcome {
+            if !error.kind.is_catchable() {
+                return outcome;
             }
-            Statement::ReturnStatement { expr } => {
-                trace!("Entering {} ", "ReturnStatement");
-                let option = (*expr).clone().unwrap();
-                let res = self.expression_visitor.eval(*option);
+        }
+        if let Some(finally_body) = finally_body {
+            // a return from finally replaces the outcome of try and catch
+            if let Expr { res } = self.eval(finally_body)? {
                 return Ok(Expr { res });
             }
-            Statement::ClassDeclaration { identifier, functions } => {
-                trace!("Entering {} ", "ClassDeclaration");
-
-                let mut result_fn = vec![];
-                for fn_ in functions {
-                    if let Statement::FunStatement { identifier,
-                        args, block }  = *fn_.clone() {
-                        let mut arguments = vec![];
-                        for arg in args {
-                            arguments.push(self.expression_visitor.eval((arg).clone()));
-                        }
-                        let method1 = Method::new(identifier.value.clone(), arguments, *block.unwrap(), Environment::new());
-                        result_fn.push(Rc::new(method1));
-                    } ;
-                }
-                let class = Class::new_class(identifier.value.clone(), vec![], result_fn);
-                let mut ref_mut = self.envs.try_borrow_mut().unwrap();
-                let envs = ref_mut.deref_mut();
-                envs.define_at_top(identifier.value.clone(), ExpressionRes::from_class(class));
-                Ok(Void)
-            }
         }
+        outcome
+    }
+
+    fn class_declaration(&mut self, identifier: &Token, functions: &[Box<Statement>], slot: Slot) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "ClassDeclaration");
+
+        let mut result_fn = vec![];
+        for fn_ in functions {
+            if let Statement::FunStatement { identifier,
+                args, block, .. }  = fn_.as_ref() {
+                let method1 = Method::new(identifier.value.to_string(), parameters(args),
+                                          block.clone().unwrap(), self.envs.borrow().get_top());
+                result_fn.push(Rc::new(method1));
+            } ;
+        }
+        let class = Class::new_class(identifier.value.to_string(), vec![], result_fn);
+        self.envs.borrow().define(slot, identifier.value, ExpressionRes::from_class(class));
+        Ok(Void)
     }
 }
 
@@ -245,13 +233,13 @@ pub enum StatementRes {
 
 impl StatementInterpreter {
     pub fn new_default() -> StatementInterpreter {
-        let envs = Rc::new(RefCell::new(ProgramEnvs::new()));
-        let expression_visitor = Rc::new(ExpressionInterpreter::new_with_envs(envs.clone()));
-        StatementInterpreter {
-            expression_visitor,
-            envs,
-            statements: vec![],
-        }
+        let state = ExecutionState::new();
+        // the globals are charged to the heap of this program
+        let envs = {
+            let _entered = state.enter_heap();
+            Rc::new(RefCell::new(ProgramEnvs::new()))
+        };
+        StatementInterpreter::new_with_state(envs, Rc::new(RefCell::new(state)))
     }
 
     pub fn new(expression_visitor: ExpressionInterpreter) -> StatementInterpreter {
@@ -268,30 +256,148 @@ impl StatementInterpreter {
             statements: vec![],
         }
     }
+    pub fn new_with_state(envs: Rc<RefCell<ProgramEnvs>>, state: Rc<RefCell<ExecutionState>>) -> StatementInterpreter {
+        StatementInterpreter {
+            expression_visitor: Rc::new(ExpressionInterpreter::new_with_state(envs.clone(), state)),
+            envs,
+            statements: vec![],
+        }
+    }
+
+    /// Frees objects kept alive only by reference cycles, like the script's gc().
+    pub fn collect_garbage(&self) -> GcStats {
+        let _entered = self.expression_visitor.state.borrow().enter_heap();
+        gc::collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        gc::stats()
+    }
 
+    /// Runs the program, an uncaught Lox error panics with its message and stack trace.
     pub fn interpret(&mut self, program: Vec<Box<Statement>>) -> Result<StatementRes, String> {
-        // self.statements = program;
-        for statement in program {
-            match self.eval(&*statement) {
-                Ok(Void) => { continue; }
-                Ok(Expr { res }) => { return Ok(Expr { res }); }
-                Err(err) => { println!("{}", err) }
-            };
+        match self.run(program) {
+            Ok(result) => Ok(result),
+            Err(error) => { panic!("{}", error.report()) }
+        }
+    }
+
+    /// Runs the program and hands an uncaught Lox error back to the host.
+    pub fn run(&mut self, mut program: Vec<Box<Statement>>) -> Result<StatementRes, RuntimeError> {
+        SlotResolver::new().resolve_program(&mut program);
+        let _entered = self.expression_visitor.state.borrow().enter_heap();
+        match self.block(&program) {
+            Ok(result) => Ok(result),
+            Err(mut error) => {
+                if error.trace.is_empty() {
+                    error.trace = self.expression_visitor.state.borrow().traceback(error.line);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // runs statements in order until one returns a value
+    fn block<'a>(&mut self, statements: impl IntoIterator<Item = &'a Box<Statement>>) -> Result<StatementRes, RuntimeError> {
+        for statement in statements {
+            if let Expr { res } = self.eval(statement)? {
+                return Ok(Expr { res });
+            }
         }
         Ok(Void)
     }
 
+    // user code the shared operations call back into runs in the expression interpreter
+    fn host(&self) -> ExpressionInterpreter {
+        self.expression_visitor.deref().clone()
+    }
+
+    /// Evaluates the expression and resolves a variable result to the value it holds.
+    pub fn evaluate(&self, expr: &Expression) -> Result<ExpressionRes, RuntimeError> {
+        self.expression_visitor.evaluate(expr)
+    }
+
+    fn for_loop(&mut self, initiation: &Option<Box<Statement>>, condition: &Option<Box<Statement>>,
+                increment: &Option<Box<Statement>>, body: &Statement, line: usize) -> Result<StatementRes, RuntimeError> {
+        trace!("Entering {} ", "ForStatement");
+        if let Some(value) = initiation {
+            self.eval(value)?;
+        }
+        while self.for_condition(condition, line)? {
+            if let Expr { res } = self.eval(body)? {
+                return Ok(Expr { res });
+            }
+            if let Some(value) = increment {
+                self.eval(value)?;
+            }
+        }
+        Ok(Void)
+    }
+
+    // a missing condition in for (;;) means loop forever
+    fn for_condition(&self, condition: &Option<Box<Statement>>, line: usize) -> Result<bool, RuntimeError> {
+        match condition.as_deref() {
+            None => Ok(true),
+            Some(Statement::Stmt { expr }) => self.condition(expr, line),
+            Some(_) => panic!("for loop condition has to be an expression"),
+        }
+    }
+
+    // evaluates the condition of the statement on `line`
+    fn condition(&self, expr: &Expression, line: usize) -> Result<bool, RuntimeError> {
+        let value = self.evaluate(expr)?;
+        errors::set_line(line);
+        operations::condition(&value)
+    }
+
+    fn for_in_iterator(&mut self, identifier: &Token, iterator: ExpressionRes, body: &Statement) -> Result<StatementRes, RuntimeError> {
+        loop {
+            if !operations::has_next(&mut self.host(), &iterator)? {
+                return Ok(Void);
+            }
+            let item = operations::next(&mut self.host(), &iterator)?;
+            if let Expr { res } = self.for_in_body(identifier, item, body)? {
+                return Ok(Expr { res });
+            }
+        }
+    }
+
+    // every iteration gets a fresh scope so closures capture that iteration's value
+    fn for_in_body(&mut self, identifier: &Token, item: ExpressionRes, body: &Statement) -> Result<StatementRes, RuntimeError> {
+        {
+            let mut envs = self.envs.borrow_mut();
+            envs.push();
+            envs.define(CAUGHT, identifier.value, item);
+        }
+        let result = self.eval(body);
+        self.envs.borrow_mut().pop();
+        result
+    }
+
     pub fn lookup_variable(&self, name: String) -> Rc<RefCell<ExpressionRes>> {
-        self.envs.borrow().lookup_var(name)
+        self.envs.borrow().lookup_var(Symbol::intern(&name)).unwrap()
     }
 
     pub fn insert_variable(&self, name: String, expr: ExpressionRes) {
         let mut ref_mut = self.envs.try_borrow_mut().unwrap();
         let envs = ref_mut.deref_mut();
-        envs.define_at_top(name, expr);
+        envs.define_at_top(Symbol::intern(&name), expr);
     }
 }
 
+#[cfg(test)]
+fn parse(program: &str) -> Vec<Box<Statement>> {
+    let tokens = crate::token::Scanner::new().tokenize_string(program.to_string());
+    crate::parser::Parser::new(tokens).program()
+}
+
+// runs the program on a fresh interpreter, whose globals the test then looks up
+#[cfg(test)]
+fn run(program: &str) -> StatementInterpreter {
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.interpret(parse(program));
+    interpreter
+}
 
 #[test]
 fn test_lookup() {
@@ -299,7 +405,7 @@ fn test_lookup() {
     let interpreter = StatementInterpreter::new_default();
     interpreter.insert_variable(String::from("test"), res);
     let rc = interpreter.lookup_variable(String::from("test"));
-    assert_eq!(rc.borrow().str, "test");
+    assert_eq!(&*rc.borrow().str, "test");
 }
 
 #[test]
@@ -308,5 +414,233 @@ fn test_null() {
     let interpreter = StatementInterpreter::new_default();
     interpreter.insert_variable(String::from("test"), res);
     let rc = interpreter.lookup_variable(String::from("test"));
-    assert_eq!(rc.borrow().str, "test");
+    assert_eq!(&*rc.borrow().str, "test");
+}
+
+#[test]
+fn test_functions_share_their_body() {
+    let program = "fun make(n) { return fun () { return n; }; }
+    var one = make(1);
+    var two = make(2);
+    var got = one() + two();";
+    let interpreter = run(program);
+    assert_eq!(interpreter.lookup_variable(String::from("got")).borrow().number, 3);
+    let one = interpreter.lookup_variable(String::from("one")).borrow().get_method().clone();
+    let two = interpreter.lookup_variable(String::from("two")).borrow().get_method().clone();
+    assert!(Rc::ptr_eq(&one.body, &two.body));
+}
+
+#[test]
+fn test_stack_overflow_is_catchable() {
+    let program = r#"fun forever(n) {
+        return forever(n + 1);
+    }
+    var message;
+    try {
+        forever(0);
+    } catch (e) {
+        message = e.message;
+    }
+    fun twice() { return 2; }
+    var after = twice();"#;
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_max_depth(8);
+    interpreter.interpret(statements);
+    assert_eq!(interpreter.lookup_variable(String::from("message")).borrow().print(),
+               "Stack overflow, calling forever would nest more than 8 calls");
+    assert_eq!(interpreter.lookup_variable(String::from("after")).borrow().number, 2);
+    assert_eq!(interpreter.expression_visitor.state.borrow().depth(), 0);
+}
+
+#[test]
+fn test_default_depth_fits_thread_stack() {
+    // a spawned thread gets a smaller stack than the main thread
+    let message = std::thread::spawn(|| {
+        let program = r#"fun forever(n) {
+            return forever(n + 1);
+        }
+        var message;
+        try {
+            forever(0);
+        } catch (e) {
+            message = e.message;
+        }"#;
+        let interpreter = run(program);
+        let message = interpreter.lookup_variable(String::from("message")).borrow().print();
+        message
+    }).join().unwrap();
+    assert_eq!(message, format!("Stack overflow, calling forever would nest more than {} calls", crate::program::execution::DEFAULT_MAX_DEPTH));
+}
+
+#[test]
+fn test_traceback_elides_deep_stacks() {
+    let program = "fun down(n) {
+        if (n == 0) { return nil + 1; }
+        return down(n - 1);
+    }
+    down(25);";
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    let error = match interpreter.run(statements) {
+        Ok(_) => panic!("expected an uncaught error"),
+        Err(error) => error,
+    };
+    assert_eq!(error.trace.len(), 21);
+    assert_eq!(error.trace[0], "[line 2] in down()");
+    assert_eq!(error.trace[1], "[line 3] in down()");
+    assert_eq!(error.trace[10], "... 7 more calls");
+    assert_eq!(error.trace[20], "[line 5] in script");
+}
+
+#[test]
+fn test_fuel_budget_stops_infinite_loop() {
+    let program = "var caught = false;
+    try {
+        while (true) {}
+    } catch (e) {
+        caught = true;
+    }";
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_fuel(1000);
+    let error = match interpreter.run(statements) {
+        Ok(_) => panic!("expected the fuel to run out"),
+        Err(error) => error,
+    };
+    assert_eq!(error.kind, errors::ErrorKind::OutOfFuel);
+    assert_eq!(error.message(), "Out of fuel after 1000 statements");
+    assert_eq!(interpreter.fuel_used(), 1001);
+    assert!(!interpreter.lookup_variable(String::from("caught")).borrow().boolean);
+}
+
+#[test]
+fn test_fuel_used_is_reported() {
+    let program = "var x = 0;
+    for (var i = 0; i < 3; i++) x += i;";
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_fuel(100);
+    assert!(interpreter.run(statements).is_ok());
+    assert_eq!(interpreter.fuel_used(), 9);
+}
+
+#[test]
+fn test_deadline_stops_execution() {
+    let program = "while (true) {}";
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_deadline(Instant::now() + std::time::Duration::from_millis(20));
+    let error = match interpreter.run(statements) {
+        Ok(_) => panic!("expected the deadline to pass"),
+        Err(error) => error,
+    };
+    assert_eq!(error.kind, errors::ErrorKind::Timeout);
+}
+
+#[test]
+fn test_memory_limit_raises_out_of_memory() {
+    let program = r#"var message;
+    try {
+        var s = "ab";
+        while (true) { s = s + s; }
+    } catch (e) {
+        message = e.message;
+    }
+    var big;
+    try {
+        big = range(100000000);
+    } catch (e) {
+        big = "refused";
+    }"#;
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_memory_limit(interpreter.heap_used() + 1024 * 1024);
+    interpreter.interpret(statements);
+    assert!(interpreter.lookup_variable(String::from("message")).borrow().str.starts_with("Out of memory"));
+    assert_eq!(interpreter.lookup_variable(String::from("big")).borrow().print(), "refused");
+    assert!(interpreter.heap_peak() > 1024 * 1024);
+}
+
+#[test]
+fn test_heap_usage_is_reported() {
+    let mut interpreter = StatementInterpreter::new_default();
+    let before = interpreter.heap_used();
+    let program = "var xs = range(1000);";
+    let statements = parse(program);
+    interpreter.interpret(statements);
+    let with_list = interpreter.heap_used();
+    assert!(with_list >= before + 1000 * std::mem::size_of::<ExpressionRes>());
+    interpreter.interpret(parse("xs = nil;"));
+    assert!(interpreter.heap_used() < with_list - 900 * std::mem::size_of::<ExpressionRes>());
+}
+
+#[test]
+fn test_interpreters_have_their_own_heap() {
+    let big = run("var xs = range(10000);");
+    let mut small = StatementInterpreter::new_default();
+    small.set_memory_limit(small.heap_used() + 64 * 1024);
+    assert!(small.run(parse("var ys = range(100);")).is_ok());
+    assert!(big.heap_used() > 10000 * std::mem::size_of::<ExpressionRes>());
+    assert!(small.heap_used() < 10000 * std::mem::size_of::<ExpressionRes>());
+}
+
+#[test]
+fn test_gc_runs_once_while_over_the_limit() {
+    let mut interpreter = run("var keep = range(2000);");
+    interpreter.set_memory_limit(interpreter.heap_used() / 2);
+    let runs = interpreter.gc_stats().runs;
+    for _ in 0..5 {
+        let error = interpreter.run(parse("var x = 1;")).err().unwrap();
+        assert_eq!(error.kind, errors::ErrorKind::OutOfMemory);
+    }
+    assert_eq!(interpreter.gc_stats().runs, runs + 1);
+}
+
+#[test]
+fn test_gc_collects_cycles() {
+    let program = "class Node {}
+    fun make() {
+        var a = Node();
+        var b = Node();
+        a.other = b;
+        b.other = a;
+        var xs = [];
+        xs.push(xs);
+        fun recurse() { return recurse; }
+    }
+    for (var i = 0; i < 5; i++) make();
+    var first = gc();
+    var second = gc();
+    var keep = Node();
+    keep.self = keep;
+    fun add(a, b) { return a + b; }
+    var third = gc();
+    var still_works = [keep.self == keep, add(1, 2)];";
+    let interpreter = run(program);
+    let collected = |name: &str| {
+        let stats = interpreter.lookup_variable(name.to_string()).borrow().clone();
+        let key = crate::program::runtime::MapKey::String("collected".into());
+        let number = stats.map.as_ref().unwrap().borrow().get(&key).unwrap().number;
+        number
+    };
+    assert!(collected("first") > 0);
+    assert_eq!(collected("second"), 0);
+    assert_eq!(collected("third"), 0);
+    assert_eq!(interpreter.lookup_variable(String::from("still_works")).borrow().print(), "[true, 3]");
+    assert_eq!(interpreter.gc_stats().runs, 3);
+}
+
+#[test]
+fn test_gc_runs_before_out_of_memory() {
+    let program = "fun leak() {
+        var xs = range(100);
+        xs.push(xs);
+    }
+    for (var i = 0; i < 200; i++) leak();";
+    let statements = parse(program);
+    let mut interpreter = StatementInterpreter::new_default();
+    interpreter.set_memory_limit(interpreter.heap_used() + 200 * 1024);
+    assert!(interpreter.run(statements).is_ok());
+    assert!(interpreter.gc_stats().total_collected > 0);
 }
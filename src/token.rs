@@ -1,6 +1,9 @@
 use std::fs;
 use log::{trace,info, warn, error};
 
+use crate::interner::Symbol;
+use crate::program::errors::SyntaxError;
+
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum TokenType {
@@ -9,6 +12,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -17,6 +22,8 @@ pub enum TokenType {
     Percent,
     Slash,
     Star,
+    Question,
+    Colon,
 
     // one or two character tokens
     Bang,
@@ -27,27 +34,40 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusPlus,
+    MinusMinus,
 
     // literals
     Identifier,
     String,
+    Interpolation,
     Number,
 
     // keywords
     And,
+    Catch,
     Class,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -58,7 +78,7 @@ pub enum TokenType {
 #[derive(Debug, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub value: String,
+    pub value: Symbol,
     pub line: usize,
 }
 
@@ -66,7 +86,7 @@ impl Clone for Token {
     fn clone(&self) -> Self {
         Token {
             token_type: self.token_type.clone(),
-            value: self.value.clone(),
+            value: self.value,
             line: self.line.clone(),
         }
     }
@@ -76,7 +96,7 @@ impl Token {
     pub fn new(token_type: TokenType, value: String, line: usize) -> Token {
         Token {
             token_type,
-            value,
+            value: Symbol::intern(&value),
             line,
         }
     }
@@ -84,7 +104,7 @@ impl Token {
     pub fn new_simple(token_type: TokenType, value: String) -> Token {
         Token {
             token_type,
-            value,
+            value: Symbol::intern(&value),
             line: 0,
         }
     }
@@ -95,6 +115,8 @@ pub struct Scanner {
     size: usize,
     line: usize,
     chars: Vec<char>,
+    // the first mistake found, scanning stops at most of them
+    error: Option<SyntaxError>,
 }
 
 impl Scanner {
@@ -104,6 +126,7 @@ impl Scanner {
             size: 0,
             line: 0,
             chars: vec![],
+            error: None,
         }
     }
 
@@ -112,11 +135,22 @@ impl Scanner {
         self.tokenize_string(content)
     }
 
+    /// Tokens of the source, or the first mistake in it.
+    pub fn scan(&mut self, content: String) -> Result<Vec<Token>, SyntaxError> {
+        let tokens = self.tokenize_string(content);
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(tokens),
+        }
+    }
+
+    // the tokens before the first mistake, which is only kept for scan
     pub fn tokenize_string(&mut self, content: String) -> Vec<Token> {
         self.chars = content.chars().map(|ch| ch as char).collect::<Vec<_>>();
         self.size = self.chars.len();
         self.current = 0;
         self.line = 0;
+        self.error = None;
 
         self.tokenize()
     }
@@ -128,78 +162,87 @@ impl Scanner {
     pub fn tokenize(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
         while self.current < self.size {
-            let initial = self.current;
-            match self.tokenize_single_char(self.current) {
-                Some(TokenType::Space) => {
-                    self.advance();
-                    continue;
-                }
-                Some(token_type) => {
-                    let value = self.get_string_from_char_range(
-                        self.current,
-                        self.current + 1,
-                        &self.chars,
-                    );
-                    tokens.push(Token {
-                        token_type,
-                        value,
-                        line: self.line,
-                    });
-                    self.advance();
-                    continue;
-                }
-                None => {}
-            };
+            if !self.scan_token(&mut tokens) {
+                break;
+            }
+        }
 
-            match self.tokenize_two_chars(self.current) {
-                Some(token_type) => {
-                    let value =
-                        self.get_string_from_char_range(initial, self.current + 1, &self.chars);
-                    tokens.push(Token {
-                        token_type,
-                        value,
-                        line: self.line,
-                    });
-                    self.advance();
-                    continue;
-                }
-                None => {}
-            };
+        tokens
+    }
 
-            match self.consume_comment_or_divide() {
-                Some(TokenType::Space) => {
-                    continue;
-                }
-                Some(_value) => {
-                    tokens.push(Token {
-                        token_type: TokenType::Slash,
-                        value: "/".to_string(),
-                        line: self.line,
-                    });
-                    continue;
-                }
-                None => {}
+    // returns false when scanning cannot continue, like after an unterminated string
+    fn scan_token(&mut self, tokens: &mut Vec<Token>) -> bool {
+        let initial = self.current;
+        match self.tokenize_two_chars(self.current) {
+            Some(token_type) => {
+                let value =
+                    self.get_string_from_char_range(initial, self.current + 1, &self.chars);
+                tokens.push(Token {
+                    token_type,
+                    value: Symbol::intern(&value),
+                    line: self.line,
+                });
+                self.advance();
+                return true;
             }
+            None => {}
+        };
 
-            if self.chars[self.current].is_alphabetic() || self.chars[self.current] == '_' {
-                tokens.push(self.get_alphabetic_token(initial));
-                continue;
+        match self.tokenize_single_char(self.current) {
+            Some(TokenType::Space) => {
+                self.advance();
+                return true;
             }
-
-            if self.chars[self.current].is_numeric() {
-                tokens.push(self.get_numeric_token(initial));
-                continue;
+            Some(token_type) => {
+                let value = self.get_string_from_char_range(
+                    self.current,
+                    self.current + 1,
+                    &self.chars,
+                );
+                tokens.push(Token {
+                    token_type,
+                    value: Symbol::intern(&value),
+                    line: self.line,
+                });
+                self.advance();
+                return true;
             }
+            None => {}
+        };
 
-            if self.chars[self.current] == '"' {
-                match self.get_string_token(initial) {
-                    Some(token) => tokens.push(token),
-                    None => break,
-                };
+        match self.consume_comment_or_divide() {
+            Some(TokenType::Space) => {
+                return true;
             }
+            Some(token_type) => {
+                let value = self.get_string_from_char_range(initial, self.current, &self.chars);
+                tokens.push(Token {
+                    token_type,
+                    value: Symbol::intern(&value),
+                    line: self.line,
+                });
+                return true;
+            }
+            None => {}
         }
 
-        tokens
+        if self.chars[self.current].is_alphabetic() || self.chars[self.current] == '_' {
+            tokens.push(self.get_alphabetic_token(initial));
+            return true;
+        }
+
+        if self.chars[self.current].is_numeric() {
+            tokens.push(self.get_numeric_token(initial));
+            return true;
+        }
+
+        if self.chars[self.current] == '"' {
+            return self.get_string_token(initial, tokens).is_some();
+        }
+
+        self.fail(self.line, format!("Unexpected character {}", self.chars[self.current]));
+        self.advance();
+        true
     }
 
     pub(crate) fn tokenize_single_char(&mut self, value: usize) -> Option<TokenType> {
@@ -208,6 +251,8 @@ impl Scanner {
             ')' => Some(TokenType::RightParen),
             '{' => Some(TokenType::LeftBrace),
             '}' => Some(TokenType::RightBrace),
+            '[' => Some(TokenType::LeftBracket),
+            ']' => Some(TokenType::RightBracket),
             ',' => Some(TokenType::Comma),
             '.' => Some(TokenType::Dot),
             '-' => Some(TokenType::Minus),
@@ -215,6 +260,8 @@ impl Scanner {
             ';' => Some(TokenType::Semicolon),
             '*' => Some(TokenType::Star),
             '%' => Some(TokenType::Percent),
+            '?' => Some(TokenType::Question),
+            ':' => Some(TokenType::Colon),
             ' ' => Some(TokenType::Space),
             '\t' => Some(TokenType::Space),
             '\n' => {
@@ -255,6 +302,38 @@ impl Scanner {
                     Some(TokenType::Greater)
                 };
             }
+            '+' => {
+                return if self.peek_advance(value + 1, &'=') {
+                    Some(TokenType::PlusEqual)
+                } else if self.peek_advance(value + 1, &'+') {
+                    Some(TokenType::PlusPlus)
+                } else {
+                    Some(TokenType::Plus)
+                };
+            }
+            '-' => {
+                return if self.peek_advance(value + 1, &'=') {
+                    Some(TokenType::MinusEqual)
+                } else if self.peek_advance(value + 1, &'-') {
+                    Some(TokenType::MinusMinus)
+                } else {
+                    Some(TokenType::Minus)
+                };
+            }
+            '*' => {
+                return if self.peek_advance(value + 1, &'=') {
+                    Some(TokenType::StarEqual)
+                } else {
+                    Some(TokenType::Star)
+                };
+            }
+            '%' => {
+                return if self.peek_advance(value + 1, &'=') {
+                    Some(TokenType::PercentEqual)
+                } else {
+                    Some(TokenType::Percent)
+                };
+            }
             _ => None,
         }
     }
@@ -270,6 +349,9 @@ impl Scanner {
                     self.line += 1;
                     return Some(TokenType::Space);
                 }
+                if self.peek_advance(self.current, &'=') {
+                    return Some(TokenType::SlashEqual);
+                }
                 return Some(TokenType::Slash);
             }
             _ => None,
@@ -286,7 +368,7 @@ impl Scanner {
         let value = self.get_string_from_char_range(initial, self.current, &self.chars);
         let token = Token {
             token_type: self.identifier_alternatives(&value),
-            value,
+            value: Symbol::intern(&value),
             line: self.line,
         };
         token
@@ -295,17 +377,22 @@ impl Scanner {
     fn identifier_alternatives(&mut self, value: &str) -> TokenType {
         match value {
             "and" => TokenType::And,
+            "catch" => TokenType::Catch,
             "class" => TokenType::Class,
             "else" => TokenType::Else,
+            "finally" => TokenType::Finally,
             "fun" => TokenType::Fun,
             "for" => TokenType::For,
             "if" => TokenType::If,
+            "in" => TokenType::In,
             "nil" => TokenType::Nil,
             "or" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
             "this" => TokenType::This,
+            "throw" => TokenType::Throw,
+            "try" => TokenType::Try,
             "true" => TokenType::True,
             "false" => TokenType::False,
             "var" => TokenType::Var,
@@ -322,39 +409,158 @@ impl Scanner {
         let value = self.get_string_from_char_range(initial, self.current, &self.chars);
         let token = Token {
             token_type: TokenType::Number,
-            value,
+            value: Symbol::intern(&value),
             line: self.line,
         };
         token
     }
 
-    fn get_string_token(&mut self, initial: usize) -> Option<Token> {
-        self.advance();
-        if self.current == self.size {
-            return None;
+    // a string with ${expr} parts is emitted as Interpolation tokens, each followed by the
+    // tokens of its expression, and ends with a String token for the rest of the literal
+    fn get_string_token(&mut self, initial: usize, tokens: &mut Vec<Token>) -> Option<()> {
+        if self.peek_is(initial + 1, '"') && self.peek_is(initial + 2, '"') {
+            tokens.push(self.get_raw_string_token(initial)?);
+            return Some(());
         }
-        while self.chars[self.current] != '"' {
-            if self.current >= (self.size - 1) {
-                error!("{} {}",self.line, "Missing closing \" in a string");
+        let line = self.line;
+        self.advance();
+        let mut value = String::new();
+        loop {
+            if self.current >= self.size {
+                self.fail(line, "Missing closing \" in a string".to_string());
                 return None;
             }
+            match self.chars[self.current] {
+                '"' => break,
+                '\\' => {
+                    self.advance();
+                    value.push(self.get_escaped_char()?);
+                }
+                '\n' => {
+                    self.line += 1;
+                    value.push('\n');
+                }
+                '$' if self.peek_is(self.current + 1, '{') => {
+                    tokens.push(Token {
+                        token_type: TokenType::Interpolation,
+                        value: Symbol::intern(&std::mem::take(&mut value)),
+                        line,
+                    });
+                    self.current += 2;
+                    self.scan_interpolated_expression(tokens)?;
+                }
+                char => value.push(char),
+            }
             self.advance();
         }
         self.advance();
-        let value = self
-            .get_string_from_char_range(initial, self.current, &self.chars)
-            .strip_prefix("\"")
-            .unwrap()
-            .strip_suffix("\"")
-            .unwrap()
-            .to_string();
+        tokens.push(Token {
+            token_type: TokenType::String,
+            value: Symbol::intern(&value),
+            line,
+        });
+        Some(())
+    }
+
+    // scans tokens up to the } closing the interpolation and leaves current on it
+    fn scan_interpolated_expression(&mut self, tokens: &mut Vec<Token>) -> Option<()> {
+        let mut depth = 0;
+        loop {
+            if self.current >= self.size {
+                self.fail(self.line, "Missing closing } in string interpolation".to_string());
+                return None;
+            }
+            match self.chars[self.current] {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some(()),
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if !self.scan_token(tokens) {
+                return None;
+            }
+        }
+    }
+
+    // """raw strings""" keep every character, including backslashes and newlines
+    fn get_raw_string_token(&mut self, initial: usize) -> Option<Token> {
+        let line = self.line;
+        self.current = initial + 3;
+        let start = self.current;
+        while !(self.peek_is(self.current, '"') && self.peek_is(self.current + 1, '"')
+            && self.peek_is(self.current + 2, '"')) {
+            if self.current >= self.size {
+                self.fail(line, "Missing closing \"\"\" in a raw string".to_string());
+                return None;
+            }
+            if self.chars[self.current] == '\n' {
+                self.line += 1;
+            }
+            self.advance();
+        }
+        let value = self.get_string_from_char_range(start, self.current, &self.chars);
+        self.current += 3;
         Some(Token {
             token_type: TokenType::String,
-            value,
-            line: self.line,
+            value: Symbol::intern(&value),
+            line,
         })
     }
 
+    // expects current to be just after the backslash, leaves it on the last char of the escape
+    fn get_escaped_char(&mut self) -> Option<char> {
+        if self.current >= self.size {
+            self.fail(self.line, "Missing escape sequence after \\".to_string());
+            return None;
+        }
+        match self.chars[self.current] {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '$' => Some('$'),
+            'u' => {
+                if !self.peek_is(self.current + 1, '{') {
+                    self.fail(self.line, "Expected { after \\u in a string".to_string());
+                    return None;
+                }
+                self.current += 2;
+                let start = self.current;
+                while self.current < self.size && self.chars[self.current].is_ascii_hexdigit() {
+                    self.advance();
+                }
+                if !self.peek_is(self.current, '}') || start == self.current {
+                    self.fail(self.line, "Invalid unicode escape in a string".to_string());
+                    return None;
+                }
+                let digits = self.get_string_from_char_range(start, self.current, &self.chars);
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(char) => Some(char),
+                    None => {
+                        self.fail(self.line, format!("\\u{{{}}} is not a valid unicode scalar value", digits));
+                        None
+                    }
+                }
+            }
+            other => {
+                self.fail(self.line, format!("Unknown escape sequence \\{} in a string", other));
+                None
+            }
+        }
+    }
+
+    fn fail(&mut self, line: usize, message: String) {
+        if self.error.is_none() {
+            self.error = Some(SyntaxError::new(message, line));
+        }
+    }
+
+    fn peek_is(&self, position: usize, expected: char) -> bool {
+        position < self.size && self.chars[position] == expected
+    }
+
     pub(crate) fn get_string_from_char_range(
         &self,
         start_inclusive: usize,
@@ -1,3 +1,4 @@
+use crate::interner::Symbol;
 
 use crate::token::{Scanner, Token, TokenType};
 use crate::token::TokenType::{Else, Equal, Identifier, If, LeftBrace, LeftParen, Number, RightBrace, RightParen, Semicolon, Var};
@@ -24,7 +25,7 @@ fn tokenize() {
     let variable = tokenizer.tokenize_string("+".to_string());
     let token = Token {
         token_type: TokenType::Plus,
-        value: "+".to_string(),
+        value: Symbol::intern("+"),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -36,7 +37,7 @@ fn tokenize_two_chars() {
     let variable = tokenizer.tokenize_string("!= ".to_string());
     let token = Token {
         token_type: TokenType::BangEqual,
-        value: "!=".to_string(),
+        value: Symbol::intern("!="),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -64,7 +65,7 @@ fn tokenize_and() {
     let variable = tokenizer.tokenize_string(" and ".to_string());
     let token = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -76,7 +77,7 @@ fn remove_comment() {
     let variable = tokenizer.tokenize_string(" and // lots of text ".to_string());
     let token = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -92,12 +93,12 @@ fn remove_comment_keep_next() {
     );
     let token = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 0,
     };
     let token2 = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 1,
     };
     assert_eq!(vec![token, token2], variable)
@@ -113,12 +114,12 @@ fn check_correct_line_tokenizing() {
     );
     let token = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 0,
     };
     let token2 = Token {
         token_type: TokenType::And,
-        value: "and".to_string(),
+        value: Symbol::intern("and"),
         line: 1,
     };
     assert_eq!(vec![token, token2], variable)
@@ -130,32 +131,32 @@ fn handle_example() {
     let variable = tokenizer.tokenize_string("(( )){}".to_string());
     let token1 = Token {
         token_type: TokenType::LeftParen,
-        value: "(".to_string(),
+        value: Symbol::intern("("),
         line: 0,
     };
     let token2 = Token {
         token_type: TokenType::LeftParen,
-        value: "(".to_string(),
+        value: Symbol::intern("("),
         line: 0,
     };
     let token3 = Token {
         token_type: TokenType::RightParen,
-        value: ")".to_string(),
+        value: Symbol::intern(")"),
         line: 0,
     };
     let token4 = Token {
         token_type: TokenType::RightParen,
-        value: ")".to_string(),
+        value: Symbol::intern(")"),
         line: 0,
     };
     let token5 = Token {
         token_type: TokenType::LeftBrace,
-        value: "{".to_string(),
+        value: Symbol::intern("{"),
         line: 0,
     };
     let token6 = Token {
         token_type: TokenType::RightBrace,
-        value: "}".to_string(),
+        value: Symbol::intern("}"),
         line: 0,
     };
     assert_eq!(
@@ -170,12 +171,12 @@ fn tokenize_numeric() {
     let variable = tokenizer.tokenize_string(" a 1".to_string());
     let token = Token {
         token_type: TokenType::Identifier,
-        value: "a".to_string(),
+        value: Symbol::intern("a"),
         line: 0,
     };
     let token2 = Token {
         token_type: TokenType::Number,
-        value: "1".to_string(),
+        value: Symbol::intern("1"),
         line: 0,
     };
     assert_eq!(vec![token, token2], variable)
@@ -187,7 +188,7 @@ fn tokenize_string_empty() {
     let variable = tokenizer.tokenize_string(" \"hello\"".to_string());
     let token = Token {
         token_type: TokenType::String,
-        value: "hello".to_string(),
+        value: Symbol::intern("hello"),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -203,9 +204,8 @@ fn tokenize_string_multiline() {
     );
     let token = Token {
         token_type: TokenType::String,
-        value: "
-    "
-            .to_string(),
+        value: Symbol::intern("
+    "),
         line: 0,
     };
     assert_eq!(vec![token], variable)
@@ -277,4 +277,57 @@ fn tokenize_if() {
 
     assert_eq!(vec![if_tok, l, boo, r, bracel, var, x, semi, bracer, else_tok, bracel2, vary,
                     y, sem2, bracer2], variable)
-}
\ No newline at end of file
+}
+#[test]
+fn tokenize_compound_assignment() {
+    let variable = Scanner::new().tokenize_string("+= -= *= /= %= ++ -- + - /".to_string());
+    let types: Vec<TokenType> = variable.iter().map(|token| token.token_type).collect();
+    assert_eq!(vec![TokenType::PlusEqual, TokenType::MinusEqual, TokenType::StarEqual,
+                    TokenType::SlashEqual, TokenType::PercentEqual, TokenType::PlusPlus,
+                    TokenType::MinusMinus, TokenType::Plus, TokenType::Minus, TokenType::Slash], types);
+    assert_eq!(variable[3].value, "/=");
+}
+
+#[test]
+fn tokenize_string_escapes() {
+    let variable = Scanner::new().tokenize_string(r#""a\"b\\c\nd\te\u{1F600}""#.to_string());
+    assert_eq!(vec![Token::new(TokenType::String, "a\"b\\c\nd\te\u{1F600}".to_string(), 0)], variable)
+}
+
+#[test]
+fn tokenize_string_unknown_escape() {
+    let error = Scanner::new().scan("var x = 1;\nprint \"a\\qb\";".to_string()).unwrap_err();
+    assert_eq!(error.report(), "[line 2] Error: Unknown escape sequence \\q in a string");
+}
+
+#[test]
+fn tokenize_raw_string() {
+    let variable = Scanner::new().tokenize_string("\"\"\"line \\n \"quoted\"\nnext\"\"\" x".to_string());
+    assert_eq!(vec![
+        Token::new(TokenType::String, "line \\n \"quoted\"\nnext".to_string(), 0),
+        Token::new(Identifier, "x".to_string(), 1),
+    ], variable)
+}
+
+#[test]
+fn tokenize_string_counts_lines() {
+    let variable = Scanner::new().tokenize_string("\"a\nb\" x".to_string());
+    assert_eq!(vec![
+        Token::new(TokenType::String, "a\nb".to_string(), 0),
+        Token::new(Identifier, "x".to_string(), 1),
+    ], variable)
+}
+
+#[test]
+fn tokenize_string_interpolation() {
+    let variable = Scanner::new().tokenize_string(r#""a ${m["k"]} b ${ {} } c""#.to_string());
+    let types: Vec<TokenType> = variable.iter().map(|token| token.token_type).collect();
+    assert_eq!(vec![
+        TokenType::Interpolation, Identifier, TokenType::LeftBracket, TokenType::String, TokenType::RightBracket,
+        TokenType::Interpolation, TokenType::LeftBrace, TokenType::RightBrace,
+        TokenType::String,
+    ], types);
+    assert_eq!(variable[0].value, "a ");
+    assert_eq!(variable[5].value, " b ");
+    assert_eq!(variable[8].value, " c");
+}
@@ -0,0 +1,181 @@
+use std::rc::Rc;
+
+use crate::interner::Symbol;
+
+/// Instruction of the virtual machine. Operands follow the opcode, indexes and jump offsets
+/// take two bytes, most significant first.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    // constant index
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    // duplicates the top value, or the top two
+    Dup,
+    Dup2,
+    // stack slot of the current call
+    GetLocal,
+    SetLocal,
+    // index into the global variables of the program
+    GetGlobal,
+    SetGlobal,
+    DefineGlobal,
+    // index into the upvalues of the running closure
+    GetUpvalue,
+    SetUpvalue,
+    // constant index of the property name
+    GetProperty,
+    SetProperty,
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    // forward offset, taken when the operand on the stack decides the result
+    And,
+    Or,
+    Not,
+    Negate,
+    Increment,
+    Decrement,
+    Print,
+    // forward offset
+    Jump,
+    JumpIfFalse,
+    // backward offset
+    Loop,
+    // one byte argument count
+    Call,
+    // function constant, then a local flag byte and an index for every upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    // name constant and method count, the method closures are on the stack
+    Class,
+    // item count, a map counts its entries
+    List,
+    Map,
+    Interpolate,
+    Throw,
+    // forward offset of the handler
+    PushHandler,
+    PopHandler,
+    IterPrepare,
+    // slot of the iterator, the position follows in the next slot
+    IterHasNext,
+    IterNext,
+}
+
+// every opcode at the position of its byte
+pub const OPCODES: [OpCode; 53] = [
+    OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False, OpCode::Pop, OpCode::Dup, OpCode::Dup2,
+    OpCode::GetLocal, OpCode::SetLocal, OpCode::GetGlobal, OpCode::SetGlobal, OpCode::DefineGlobal,
+    OpCode::GetUpvalue, OpCode::SetUpvalue, OpCode::GetProperty, OpCode::SetProperty, OpCode::GetIndex,
+    OpCode::SetIndex, OpCode::Equal, OpCode::NotEqual, OpCode::Greater, OpCode::GreaterEqual, OpCode::Less,
+    OpCode::LessEqual, OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide, OpCode::Modulo,
+    OpCode::And, OpCode::Or, OpCode::Not, OpCode::Negate, OpCode::Increment, OpCode::Decrement, OpCode::Print,
+    OpCode::Jump, OpCode::JumpIfFalse, OpCode::Loop, OpCode::Call, OpCode::Closure, OpCode::CloseUpvalue,
+    OpCode::Return, OpCode::Class, OpCode::List, OpCode::Map, OpCode::Interpolate, OpCode::Throw,
+    OpCode::PushHandler, OpCode::PopHandler, OpCode::IterPrepare, OpCode::IterHasNext, OpCode::IterNext,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(isize),
+    String(Rc<str>),
+    // name of a property or class, interned when the chunk is compiled or loaded
+    Name(Symbol),
+    Function(Rc<Function>),
+}
+
+/// Bytecode of one function, with the source line of every byte.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    // token lines, counted from 0 like the scanner does
+    pub lines: Vec<usize>,
+    pub constants: Vec<Constant>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Default::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        self.lines.push(line);
+    }
+
+    pub fn write_u16(&mut self, value: u16, line: usize) {
+        self.write((value >> 8) as u8, line);
+        self.write(value as u8, line);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        (self.code[offset] as u16) << 8 | self.code[offset + 1] as u16
+    }
+
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        self.code[offset] = (value >> 8) as u8;
+        self.code[offset + 1] = value as u8;
+    }
+
+    /// Index of the constant, numbers, strings and names already in the pool are reused.
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let existing = self.constants.iter().position(|known| match (known, &constant) {
+            (Constant::Number(known), Constant::Number(number)) => known == number,
+            (Constant::String(known), Constant::String(text)) => known == text,
+            (Constant::Name(known), Constant::Name(name)) => known == name,
+            _ => false,
+        });
+        if let Some(index) = existing {
+            return index;
+        }
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+}
+
+/// Compiled function, the script itself runs as one without parameters.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    // the name interned, classes look their methods up by it
+    pub symbol: Symbol,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    // names of the global variables the Get/Set/DefineGlobal operands index, only the script has them
+    pub globals: Vec<Rc<str>>,
+}
+
+impl Function {
+    pub fn new(name: String, arity: usize) -> Function {
+        Function {
+            symbol: Symbol::intern(&name),
+            name,
+            arity,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            globals: vec![],
+        }
+    }
+}
@@ -0,0 +1,826 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::expressions::expression::Expression;
+use crate::interner::Symbol;
+use crate::program::errors::SyntaxError;
+use crate::program::program::builtins;
+use crate::statements::statement::Statement;
+use crate::token::TokenType;
+use crate::vm::chunk::{Chunk, Constant, Function, OpCode};
+
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u16,
+    is_local: bool,
+}
+
+// function being compiled, locals are stack slots of its calls
+struct FunctionState {
+    function: Function,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    // finally blocks of the try statements around the current code, a return runs them on its way out
+    handlers: Vec<Option<Box<Statement>>>,
+}
+
+impl FunctionState {
+    // slot 0 holds the called function, or the instance a method runs on
+    fn new(name: String, arity: usize, slot_zero: &str) -> FunctionState {
+        FunctionState {
+            function: Function::new(name, arity),
+            locals: vec![Local { name: slot_zero.to_string(), depth: 0, captured: false }],
+            upvalues: vec![],
+            scope_depth: 0,
+            handlers: vec![],
+        }
+    }
+}
+
+// where a compound assignment or increment writes back the value it read
+enum Target {
+    Variable(String),
+    Property(u16),
+    Element,
+}
+
+/// Compiles the parsed program to bytecode for the virtual machine.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    // line of the last node that carried one, given to the instructions emitted for it
+    line: usize,
+    // the first limit of the bytecode the program went past
+    error: Option<SyntaxError>,
+    globals: Vec<Rc<str>>,
+    global_indexes: HashMap<Rc<str>, u16>,
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::with_globals(builtins().into_iter().map(|(name, _)| name.into()).collect())
+    }
+
+    /// Compiles against the globals a virtual machine already has, the ones the program adds get the next indexes.
+    pub fn with_globals(globals: Vec<Rc<str>>) -> Compiler {
+        let global_indexes = globals.iter().enumerate().map(|(index, name)| (name.clone(), index as u16)).collect();
+        Compiler {
+            functions: vec![],
+            line: 0,
+            error: None,
+            globals,
+            global_indexes,
+        }
+    }
+
+    /// Compiles the program to the function the script runs as, or reports the bytecode limit it went past.
+    pub fn compile(mut self, program: &[Box<Statement>]) -> Result<Rc<Function>, SyntaxError> {
+        self.functions.push(FunctionState::new("script".to_string(), 0, ""));
+        for statement in program {
+            self.statement(statement);
+        }
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+        match self.error {
+            Some(error) => Err(error),
+            None => {
+                let mut script = self.functions.pop().unwrap().function;
+                script.globals = self.globals;
+                Ok(Rc::new(script))
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Stmt { expr } => {
+                self.expression(expr);
+                self.emit_op(OpCode::Pop);
+            }
+            Statement::IfStatement { expr, body, else_body, line } => {
+                self.expression(expr);
+                self.line = *line;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(body);
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump);
+                self.emit_op(OpCode::Pop);
+                if let Some(else_body) = else_body {
+                    self.statement(else_body);
+                }
+                self.patch_jump(end_jump);
+            }
+            Statement::FunStatement { identifier, args, block, .. } => {
+                let body = block.as_ref().expect("function without a body");
+                self.line = identifier.line;
+                if self.current().scope_depth == 0 {
+                    self.function(identifier.value.to_string(), args, body, "");
+                    let index = self.global(&identifier.value.as_str());
+                    self.emit_op_u16(OpCode::DefineGlobal, index);
+                } else if let Some(slot) = self.hoisted(&identifier.value.as_str()) {
+                    self.function(identifier.value.to_string(), args, body, "");
+                    self.emit_op_u16(OpCode::SetLocal, slot);
+                    self.emit_op(OpCode::Pop);
+                } else {
+                    // declared first so the body can call itself
+                    self.add_local(identifier.value.to_string());
+                    self.function(identifier.value.to_string(), args, body, "");
+                }
+            }
+            Statement::WhileStatement { expr, body, line } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(expr);
+                self.line = *line;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(body);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+            }
+            Statement::ForStatement { initiation, condition, increment, body, line } => {
+                self.begin_scope();
+                if let Some(initiation) = initiation {
+                    self.statement(initiation);
+                }
+                let loop_start = self.chunk().code.len();
+                let mut exit_jump = None;
+                match condition.as_deref() {
+                    None => {}
+                    Some(Statement::Stmt { expr }) => {
+                        self.expression(expr);
+                        self.line = *line;
+                        exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+                        self.emit_op(OpCode::Pop);
+                    }
+                    Some(_) => panic!("for loop condition has to be an expression"),
+                }
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.statement(increment);
+                }
+                self.emit_loop(loop_start);
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit_op(OpCode::Pop);
+                }
+                self.end_scope();
+            }
+            Statement::ForInStatement { identifier, iterable, body } => {
+                self.line = identifier.line;
+                // the iterator and the position in it live in hidden locals
+                self.begin_scope();
+                self.expression(iterable);
+                self.emit_op(OpCode::IterPrepare);
+                self.add_local("$iterator".to_string());
+                let zero = self.constant(Constant::Number(0));
+                self.emit_op_u16(OpCode::Constant, zero);
+                self.add_local("$position".to_string());
+                let slot = (self.current().locals.len() - 2) as u16;
+
+                let loop_start = self.chunk().code.len();
+                self.emit_op_u16(OpCode::IterHasNext, slot);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                // every iteration gets a fresh scope so closures capture that iteration's value
+                self.begin_scope();
+                self.emit_op_u16(OpCode::IterNext, slot);
+                self.add_local(identifier.value.to_string());
+                self.statement(body);
+                self.end_scope();
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+                self.end_scope();
+            }
+            Statement::PrintStatement { expr } => {
+                self.expression(expr);
+                self.emit_op(OpCode::Print);
+            }
+            Statement::BlockStatement { statements } => {
+                self.begin_scope();
+                self.hoist(statements);
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            Statement::VarDeclaration { identifier, expr } => {
+                let name = variable_name(identifier);
+                let recursive = matches!(expr.as_deref(), Some(Expression::Lambda { .. }));
+                if recursive && self.current().scope_depth > 0 {
+                    // a lambda can call itself through the local it is assigned to, like the resolver allows
+                    self.emit_op(OpCode::Nil);
+                    self.add_local(name);
+                    self.expression(expr.as_ref().unwrap());
+                    let slot = (self.current().locals.len() - 1) as u16;
+                    self.emit_op_u16(OpCode::SetLocal, slot);
+                    self.emit_op(OpCode::Pop);
+                } else {
+                    match expr {
+                        None => self.emit_op(OpCode::Nil),
+                        Some(value) => self.expression(value),
+                    }
+                    self.define_variable(name);
+                }
+            }
+            Statement::ClassDeclaration { identifier, functions, .. } => {
+                self.line = identifier.line;
+                let global = self.current().scope_depth == 0;
+                if !global && self.hoisted(&identifier.value.as_str()).is_none() {
+                    // the methods can refer to the class before it is built
+                    self.emit_op(OpCode::Nil);
+                    self.add_local(identifier.value.to_string());
+                }
+                let mut count = 0;
+                for function in functions {
+                    if let Statement::FunStatement { identifier, args, block, .. } = function.as_ref() {
+                        let body = block.as_ref().expect("method without a body");
+                        self.line = identifier.line;
+                        self.function(identifier.value.to_string(), args, body, "this");
+                        count += 1;
+                    }
+                }
+                let name = self.name_constant(identifier.value);
+                self.emit_op_u16(OpCode::Class, name);
+                self.emit_u16(count);
+                if global {
+                    let index = self.global(&identifier.value.as_str());
+                    self.emit_op_u16(OpCode::DefineGlobal, index);
+                } else {
+                    let slot = self.hoisted(&identifier.value.as_str()).unwrap();
+                    self.emit_op_u16(OpCode::SetLocal, slot);
+                    self.emit_op(OpCode::Pop);
+                }
+            }
+            Statement::ReturnStatement { expr } => {
+                match expr {
+                    None => self.emit_op(OpCode::Nil),
+                    Some(value) => self.expression(value),
+                }
+                self.emit_return();
+            }
+            Statement::ThrowStatement { expr, line } => {
+                self.expression(expr);
+                self.line = *line;
+                self.emit_op(OpCode::Throw);
+            }
+            Statement::TryStatement { body, catch_name, catch_body, finally_body } => {
+                self.try_statement(body, catch_name.as_ref().map(|name| name.value.to_string()), catch_body, finally_body);
+            }
+        }
+    }
+
+    // the handler code starts with the error on top of the stack, where the catch variable lives
+    fn try_statement(&mut self, body: &Statement, catch_name: Option<String>, catch_body: &Option<Box<Statement>>,
+                     finally_body: &Option<Box<Statement>>) {
+        let handler = self.emit_jump(OpCode::PushHandler);
+        self.current_mut().handlers.push(finally_body.clone());
+        self.statement(body);
+        self.current_mut().handlers.pop();
+        self.emit_op(OpCode::PopHandler);
+        if let Some(finally_body) = finally_body {
+            self.statement(finally_body);
+        }
+        let mut end_jumps = vec![self.emit_jump(OpCode::Jump)];
+        self.patch_jump(handler);
+
+        match (catch_body, finally_body) {
+            (Some(catch_body), finally) => {
+                self.begin_scope();
+                self.add_local(catch_name.unwrap_or_else(|| "$error".to_string()));
+                match finally {
+                    None => {
+                        self.statement(catch_body);
+                        self.end_scope();
+                    }
+                    Some(finally_body) => {
+                        // an error leaving the catch block still runs finally
+                        let rethrow = self.emit_jump(OpCode::PushHandler);
+                        self.current_mut().handlers.push(Some(finally_body.clone()));
+                        self.statement(catch_body);
+                        self.current_mut().handlers.pop();
+                        self.emit_op(OpCode::PopHandler);
+                        self.end_scope();
+                        self.statement(finally_body);
+                        end_jumps.push(self.emit_jump(OpCode::Jump));
+                        self.patch_jump(rethrow);
+                        self.finally_and_rethrow(finally_body);
+                    }
+                }
+            }
+            (None, Some(finally_body)) => self.finally_and_rethrow(finally_body),
+            (None, None) => panic!("try without catch or finally"),
+        }
+        for end_jump in end_jumps {
+            self.patch_jump(end_jump);
+        }
+    }
+
+    fn finally_and_rethrow(&mut self, finally_body: &Statement) {
+        self.begin_scope();
+        self.add_local("$error".to_string());
+        let slot = (self.current().locals.len() - 1) as u16;
+        self.statement(finally_body);
+        self.emit_op_u16(OpCode::GetLocal, slot);
+        self.emit_op(OpCode::Throw);
+        self.forget_scope();
+    }
+
+    // returns from inside try statements leave their handlers and run their finally blocks first
+    fn emit_return(&mut self) {
+        let handlers = self.current().handlers.clone();
+        if handlers.is_empty() {
+            self.emit_op(OpCode::Return);
+            return;
+        }
+        self.begin_scope();
+        self.add_local("$return".to_string());
+        let slot = (self.current().locals.len() - 1) as u16;
+        for depth in (0..handlers.len()).rev() {
+            self.emit_op(OpCode::PopHandler);
+            self.current_mut().handlers.truncate(depth);
+            if let Some(finally_body) = &handlers[depth] {
+                self.statement(finally_body);
+            }
+        }
+        self.current_mut().handlers = handlers;
+        self.emit_op_u16(OpCode::GetLocal, slot);
+        self.emit_op(OpCode::Return);
+        self.forget_scope();
+    }
+
+    fn function(&mut self, name: String, params: &[Expression], body: &Statement, slot_zero: &str) {
+        let line = self.line;
+        let mut state = FunctionState::new(name, params.len(), slot_zero);
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in params {
+            self.add_local(variable_name(param));
+        }
+        match body {
+            Statement::BlockStatement { statements } => {
+                self.hoist(statements);
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            other => self.statement(other),
+        }
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+
+        let state = self.functions.pop().unwrap();
+        // the closure is created where the function is declared
+        self.line = line;
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        let index = self.constant(Constant::Function(Rc::new(function)));
+        self.emit_op_u16(OpCode::Closure, index);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_u16(upvalue.index);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Expr { equality, .. } => match equality {
+                None => self.emit_op(OpCode::Nil),
+                Some(value) => self.expression(value),
+            },
+            Expression::Equality { .. } | Expression::Comparison { .. } => {
+                let empty = self.constant(Constant::String("".into()));
+                self.emit_op_u16(OpCode::Constant, empty);
+            }
+            Expression::GroupingExpr { value } => self.expression(value),
+            Expression::BinaryExpr { token, rhs, lhs } => {
+                self.expression(lhs);
+                self.expression(rhs);
+                self.line = token.line;
+                self.emit_op(binary_op(token.token_type));
+            }
+            Expression::UnaryExpr { token, rhs } => {
+                self.expression(rhs);
+                self.line = token.line;
+                match token.token_type {
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    TokenType::Bang => self.emit_op(OpCode::Not),
+                    other => panic!("Unsupported unary operator {:?}", other),
+                }
+            }
+            Expression::LiteralExpr { token_type, value } => match token_type {
+                TokenType::String => {
+                    let index = self.constant(Constant::String(value.as_str()));
+                    self.emit_op_u16(OpCode::Constant, index);
+                }
+                TokenType::True => self.emit_op(OpCode::True),
+                TokenType::False => self.emit_op(OpCode::False),
+                _ => self.emit_op(OpCode::Nil),
+            },
+            Expression::NumberExpr { value } => {
+                let index = self.constant(Constant::Number(*value));
+                self.emit_op_u16(OpCode::Constant, index);
+            }
+            Expression::VariableExpr { token_type, value, line, .. } => {
+                self.line = *line;
+                match token_type {
+                    TokenType::Nil => self.emit_op(OpCode::Nil),
+                    _ => self.get_variable(&value.as_str()),
+                }
+            }
+            Expression::Assignment { identifier, value } => match identifier.as_ref() {
+                Expression::VariableExpr { value: name, .. } => {
+                    self.expression(value);
+                    self.set_variable(&name.as_str());
+                }
+                Expression::Get { expr, name } => {
+                    self.expression(expr);
+                    self.expression(value);
+                    let name = self.name_constant(*name);
+                    self.emit_op_u16(OpCode::SetProperty, name);
+                }
+                Expression::Index { expr, index } => {
+                    self.expression(expr);
+                    self.expression(index);
+                    self.expression(value);
+                    self.emit_op(OpCode::SetIndex);
+                }
+                other => panic!("Invalid assignment target {:?}", other),
+            },
+            Expression::Logical { token, rhs, lhs } => {
+                // the right side runs only when the left one does not decide the result,
+                // the second And or Or only checks that it is a boolean
+                let op = if token.token_type == TokenType::And { OpCode::And } else { OpCode::Or };
+                self.expression(lhs);
+                self.line = token.line;
+                let end_jump = self.emit_jump(op);
+                self.emit_op(OpCode::Pop);
+                self.expression(rhs);
+                self.line = token.line;
+                self.emit_op_u16(op, 0);
+                self.patch_jump(end_jump);
+            }
+            Expression::Call { identifier, args, line } => {
+                self.expression(identifier);
+                for arg in args {
+                    self.expression(arg);
+                }
+                if args.len() > u8::MAX as usize {
+                    self.fail(format!("Can't have more than {} arguments", u8::MAX));
+                }
+                self.line = *line;
+                self.emit_op(OpCode::Call);
+                self.emit_byte(args.len() as u8);
+            }
+            Expression::Get { expr, name } => {
+                self.expression(expr);
+                let name = self.name_constant(*name);
+                self.emit_op_u16(OpCode::GetProperty, name);
+            }
+            Expression::CompoundAssignment { identifier, token, value } => {
+                let target = self.read_target(identifier);
+                self.expression(value);
+                self.line = token.line;
+                self.emit_op(match token.token_type {
+                    TokenType::PlusEqual => OpCode::Add,
+                    TokenType::MinusEqual => OpCode::Subtract,
+                    TokenType::StarEqual => OpCode::Multiply,
+                    TokenType::SlashEqual => OpCode::Divide,
+                    _ => OpCode::Modulo,
+                });
+                self.write_target(target);
+            }
+            Expression::Increment { identifier, token, prefix } => {
+                self.line = token.line;
+                let (step, undo) = if token.token_type == TokenType::PlusPlus {
+                    (OpCode::Increment, OpCode::Decrement)
+                } else {
+                    (OpCode::Decrement, OpCode::Increment)
+                };
+                let target = self.read_target(identifier);
+                self.emit_op(step);
+                self.write_target(target);
+                // the operand is a number, so stepping back gives the value it had
+                if !prefix {
+                    self.emit_op(undo);
+                }
+            }
+            Expression::Conditional { condition, then_branch, else_branch, line } => {
+                self.expression(condition);
+                self.line = *line;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.expression(then_branch);
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump);
+                self.emit_op(OpCode::Pop);
+                self.expression(else_branch);
+                self.patch_jump(end_jump);
+            }
+            Expression::Lambda { args, block } => {
+                self.function("anonymous".to_string(), args, block, "");
+            }
+            Expression::ListExpr { items } => {
+                for item in items {
+                    self.expression(item);
+                }
+                let count = self.count(items.len());
+                self.emit_op_u16(OpCode::List, count);
+            }
+            Expression::MapExpr { entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                let count = self.count(entries.len());
+                self.emit_op_u16(OpCode::Map, count);
+            }
+            Expression::Index { expr, index } => {
+                self.expression(expr);
+                self.expression(index);
+                self.emit_op(OpCode::GetIndex);
+            }
+            Expression::Interpolation { parts } => {
+                for part in parts {
+                    self.expression(part);
+                }
+                let count = self.count(parts.len());
+                self.emit_op_u16(OpCode::Interpolate, count);
+            }
+        }
+    }
+
+    // pushes the current value of the target, evaluating its object and index once
+    fn read_target(&mut self, target: &Expression) -> Target {
+        match target {
+            Expression::VariableExpr { value, .. } => {
+                self.get_variable(&value.as_str());
+                Target::Variable(value.to_string())
+            }
+            Expression::Get { expr, name } => {
+                self.expression(expr);
+                self.emit_op(OpCode::Dup);
+                let name = self.name_constant(*name);
+                self.emit_op_u16(OpCode::GetProperty, name);
+                Target::Property(name)
+            }
+            Expression::Index { expr, index } => {
+                self.expression(expr);
+                self.expression(index);
+                self.emit_op(OpCode::Dup2);
+                self.emit_op(OpCode::GetIndex);
+                Target::Element
+            }
+            other => panic!("Invalid assignment target {:?}", other),
+        }
+    }
+
+    fn write_target(&mut self, target: Target) {
+        match target {
+            Target::Variable(name) => self.set_variable(&name),
+            Target::Property(name) => self.emit_op_u16(OpCode::SetProperty, name),
+            Target::Element => self.emit_op(OpCode::SetIndex),
+        }
+    }
+
+    fn define_variable(&mut self, name: String) {
+        if self.current().scope_depth == 0 {
+            let index = self.global(&name);
+            self.emit_op_u16(OpCode::DefineGlobal, index);
+        } else {
+            // the value is already in the slot the new local takes
+            self.add_local(name);
+        }
+    }
+
+    fn get_variable(&mut self, name: &str) {
+        let (op, operand) = self.resolve(name, OpCode::GetLocal, OpCode::GetUpvalue, OpCode::GetGlobal);
+        self.emit_op_u16(op, operand);
+    }
+
+    fn set_variable(&mut self, name: &str) {
+        let (op, operand) = self.resolve(name, OpCode::SetLocal, OpCode::SetUpvalue, OpCode::SetGlobal);
+        self.emit_op_u16(op, operand);
+    }
+
+    fn resolve(&mut self, name: &str, local: OpCode, upvalue: OpCode, global: OpCode) -> (OpCode, u16) {
+        let current = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(current, name) {
+            return (local, slot);
+        }
+        if let Some(index) = self.resolve_upvalue(current, name) {
+            return (upvalue, index);
+        }
+        (global, self.global(name))
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u16> {
+        self.functions[function].locals.iter().rposition(|local| local.name == name).map(|slot| slot as u16)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u16> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return Some(self.add_upvalue(function, UpvalueRef { index: slot, is_local: true }));
+        }
+        let index = self.resolve_upvalue(function - 1, name)?;
+        Some(self.add_upvalue(function, UpvalueRef { index, is_local: false }))
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueRef) -> u16 {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|known| *known == upvalue) {
+            return index as u16;
+        }
+        upvalues.push(upvalue);
+        let index = upvalues.len() - 1;
+        self.count(index)
+    }
+
+    fn add_local(&mut self, name: String) {
+        if self.current().locals.len() > u16::MAX as usize {
+            let message = format!("Too many local variables in {}", self.current().function.name);
+            self.fail(message);
+        }
+        let state = self.current_mut();
+        let depth = state.scope_depth;
+        state.locals.push(Local { name, depth, captured: false });
+    }
+
+    // functions and classes of a block can call each other whatever order they are declared in,
+    // like the resolver allows, so their locals exist before the first statement runs
+    fn hoist<'a>(&mut self, statements: impl IntoIterator<Item=&'a Box<Statement>>) {
+        for statement in statements {
+            if let Statement::FunStatement { identifier, .. } | Statement::ClassDeclaration { identifier, .. } = statement.as_ref() {
+                self.emit_op(OpCode::Nil);
+                self.add_local(identifier.value.to_string());
+            }
+        }
+    }
+
+    // slot of a function or class declared in the current scope before its declaration runs
+    fn hoisted(&self, name: &str) -> Option<u16> {
+        let state = self.current();
+        state.locals.iter().rposition(|local| local.name == name && local.depth == state.scope_depth).map(|slot| slot as u16)
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+        loop {
+            let state = self.current();
+            match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => {
+                    let op = if local.captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+                    self.current_mut().locals.pop();
+                    self.emit_op(op);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    // ends a scope whose code always leaves by return or throw, so nothing has to be popped
+    fn forget_scope(&mut self) {
+        let state = self.current_mut();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while state.locals.last().map_or(false, |local| local.depth > depth) {
+            state.locals.pop();
+        }
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().unwrap()
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current_mut().function.chunk
+    }
+
+    fn constant(&mut self, constant: Constant) -> u16 {
+        let index = self.chunk().add_constant(constant);
+        if index > u16::MAX as usize {
+            self.fail("Too many constants in one function".to_string());
+        }
+        index as u16
+    }
+
+    fn name_constant(&mut self, name: Symbol) -> u16 {
+        self.constant(Constant::Name(name))
+    }
+
+    // globals are numbered across the whole program, in the order they are first mentioned
+    fn global(&mut self, name: &str) -> u16 {
+        if let Some(index) = self.global_indexes.get(name) {
+            return *index;
+        }
+        if self.globals.len() > u16::MAX as usize {
+            self.fail("Too many global variables".to_string());
+        }
+        let index = self.globals.len() as u16;
+        let name: Rc<str> = name.into();
+        self.globals.push(name.clone());
+        self.global_indexes.insert(name, index);
+        index
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.chunk().write(byte, line);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let line = self.line;
+        self.chunk().write_u16(value, line);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_op_u16(&mut self, op: OpCode, operand: u16) {
+        self.emit_op(op);
+        self.emit_u16(operand);
+    }
+
+    // returns the position of the offset to patch once the target is known
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op_u16(op, u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.fail("Too much code to jump over".to_string());
+        }
+        self.chunk().patch_u16(offset, jump as u16);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let offset = self.chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.fail("Loop body too large".to_string());
+        }
+        self.emit_u16(offset as u16);
+    }
+
+    fn count(&mut self, count: usize) -> u16 {
+        if count > u16::MAX as usize {
+            self.fail("Too many items in one literal".to_string());
+        }
+        count as u16
+    }
+
+    // the compiler goes on after an error so it stays simple, only the first one is reported
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(SyntaxError::new(message, self.line));
+        }
+    }
+}
+
+fn variable_name(expression: &Expression) -> String {
+    match expression {
+        Expression::VariableExpr { value, .. } => value.to_string(),
+        other => panic!("Expected a variable name, found {:?}", other),
+    }
+}
+
+fn binary_op(token_type: TokenType) -> OpCode {
+    match token_type {
+        TokenType::Plus => OpCode::Add,
+        TokenType::Minus => OpCode::Subtract,
+        TokenType::Star => OpCode::Multiply,
+        TokenType::Slash => OpCode::Divide,
+        TokenType::Percent => OpCode::Modulo,
+        TokenType::EqualEqual => OpCode::Equal,
+        TokenType::BangEqual => OpCode::NotEqual,
+        TokenType::Greater => OpCode::Greater,
+        TokenType::GreaterEqual => OpCode::GreaterEqual,
+        TokenType::Less => OpCode::Less,
+        TokenType::LessEqual => OpCode::LessEqual,
+        other => panic!("Unsupported binary operator {:?}", other),
+    }
+}
+
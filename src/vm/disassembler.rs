@@ -0,0 +1,112 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::vm::chunk::{Chunk, Constant, Function, OpCode};
+
+/// Lists the instructions of the function and of every function nested in it, with their constant pools.
+pub fn disassemble(function: &Function) -> String {
+    let mut out = String::new();
+    disassemble_function(function, &function.globals, &mut out);
+    out
+}
+
+fn disassemble_function(function: &Function, globals: &[Rc<str>], out: &mut String) {
+    let chunk = &function.chunk;
+    writeln!(out, "== {} ({} params, {} upvalues) ==", function.name, function.arity, function.upvalue_count).unwrap();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, globals, offset, out);
+    }
+    if !chunk.constants.is_empty() {
+        writeln!(out, "constants:").unwrap();
+        for (index, constant) in chunk.constants.iter().enumerate() {
+            writeln!(out, "{:>6} {}", index, describe(constant)).unwrap();
+        }
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(nested) = constant {
+            writeln!(out).unwrap();
+            disassemble_function(nested, globals, out);
+        }
+    }
+}
+
+/// Writes the instruction at the offset as one line, returns the offset of the next one.
+/// `globals` are the names of the global variables of the script the chunk belongs to.
+pub fn disassemble_instruction(chunk: &Chunk, globals: &[Rc<str>], offset: usize, out: &mut String) -> usize {
+    write!(out, "{:04} ", offset).unwrap();
+    // lines are shown counted from 1, like in error messages
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        write!(out, "   | ").unwrap();
+    } else {
+        write!(out, "{:>4} ", chunk.lines[offset] + 1).unwrap();
+    }
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => {
+            writeln!(out, "unknown opcode {}", chunk.code[offset]).unwrap();
+            return offset + 1;
+        }
+    };
+    let name = format!("{:?}", op);
+    match op {
+        OpCode::Constant | OpCode::GetProperty | OpCode::SetProperty => {
+            let index = chunk.read_u16(offset + 1) as usize;
+            writeln!(out, "{:<16} {:>4} {}", name, index, describe(&chunk.constants[index])).unwrap();
+            offset + 3
+        }
+        OpCode::GetGlobal | OpCode::SetGlobal | OpCode::DefineGlobal => {
+            let index = chunk.read_u16(offset + 1) as usize;
+            let global = globals.get(index).map_or("?", |name| &**name);
+            writeln!(out, "{:<16} {:>4} {}", name, index, global).unwrap();
+            offset + 3
+        }
+        OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue | OpCode::List | OpCode::Map
+        | OpCode::Interpolate | OpCode::IterHasNext | OpCode::IterNext => {
+            writeln!(out, "{:<16} {:>4}", name, chunk.read_u16(offset + 1)).unwrap();
+            offset + 3
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::And | OpCode::Or | OpCode::PushHandler | OpCode::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = if op == OpCode::Loop { offset + 3 - jump } else { offset + 3 + jump };
+            writeln!(out, "{:<16} {:>4} -> {:04}", name, jump, target).unwrap();
+            offset + 3
+        }
+        OpCode::Call => {
+            writeln!(out, "{:<16} {:>4}", name, chunk.code[offset + 1]).unwrap();
+            offset + 2
+        }
+        OpCode::Class => {
+            let index = chunk.read_u16(offset + 1) as usize;
+            let methods = chunk.read_u16(offset + 3);
+            writeln!(out, "{:<16} {:>4} {} with {} methods", name, index, describe(&chunk.constants[index]), methods).unwrap();
+            offset + 5
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1) as usize;
+            writeln!(out, "{:<16} {:>4} {}", name, index, describe(&chunk.constants[index])).unwrap();
+            let mut next = offset + 3;
+            if let Constant::Function(function) = &chunk.constants[index] {
+                for _ in 0..function.upvalue_count {
+                    let kind = if chunk.code[next] == 1 { "local" } else { "upvalue" };
+                    writeln!(out, "{:04}    |   {} {}", next, kind, chunk.read_u16(next + 1)).unwrap();
+                    next += 3;
+                }
+            }
+            next
+        }
+        _ => {
+            writeln!(out, "{}", name).unwrap();
+            offset + 1
+        }
+    }
+}
+
+fn describe(constant: &Constant) -> String {
+    match constant {
+        Constant::Number(number) => number.to_string(),
+        Constant::String(text) => format!("{:?}", text),
+        Constant::Name(name) => format!("{:?}", name),
+        Constant::Function(function) => format!("<fn {}>", function.name),
+    }
+}
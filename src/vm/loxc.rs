@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::interner::Symbol;
+use crate::vm::chunk::{Chunk, Constant, Function};
+
+// Precompiled scripts, stored next to the source as `.loxc` files.
+// The file starts with the magic bytes, the format version and a hash of the source it was compiled from,
+// followed by the script function and the names of the globals it indexes. Numbers are little endian, strings and lists are prefixed by their length.
+const MAGIC: &[u8; 4] = b"LOXC";
+/// Bumped whenever the layout or the instruction set changes, older files are then recompiled.
+pub const FORMAT_VERSION: u16 = 4;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+const NAME: u8 = 3;
+
+/// FNV-1a, stable across builds unlike the hasher of the standard library.
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Where the precompiled form of the script is kept.
+pub fn cache_path(script: &Path) -> PathBuf {
+    script.with_extension("loxc")
+}
+
+pub fn serialize(script: &Function, source_hash: u64) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&source_hash.to_le_bytes());
+    write_function(script, &mut out);
+    out
+}
+
+/// Reads a compiled script and the hash of its source back.
+pub fn deserialize(bytes: &[u8]) -> Result<(Rc<Function>, u64), String> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err("not a compiled Lox file".to_string());
+    }
+    let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(format!("compiled with format version {}, expected {}", version, FORMAT_VERSION));
+    }
+    let hash = reader.u64()?;
+    let script = reader.function()?;
+    if reader.position != bytes.len() {
+        return Err("trailing bytes after the script".to_string());
+    }
+    Ok((script, hash))
+}
+
+/// Loads the compiled script, as long as it was compiled from this source with the current format.
+pub fn load(path: &Path, source: Option<&str>) -> Result<Rc<Function>, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    let (script, hash) = deserialize(&bytes)?;
+    match source {
+        Some(source) if source_hash(source) != hash => Err("the source changed since it was compiled".to_string()),
+        _ => Ok(script),
+    }
+}
+
+fn write_function(function: &Function, out: &mut Vec<u8>) {
+    write_str(&function.name, out);
+    write_u32(function.arity, out);
+    write_u32(function.upvalue_count, out);
+    let chunk = &function.chunk;
+    write_u32(chunk.code.len(), out);
+    out.extend_from_slice(&chunk.code);
+    for line in &chunk.lines {
+        write_u32(*line, out);
+    }
+    write_u32(chunk.constants.len(), out);
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(number) => {
+                out.push(NUMBER);
+                out.extend_from_slice(&(*number as i64).to_le_bytes());
+            }
+            Constant::String(text) => {
+                out.push(STRING);
+                write_str(text, out);
+            }
+            Constant::Name(name) => {
+                out.push(NAME);
+                write_str(&name.as_str(), out);
+            }
+            Constant::Function(nested) => {
+                out.push(FUNCTION);
+                write_function(nested, out);
+            }
+        }
+    }
+    write_u32(function.globals.len(), out);
+    for name in &function.globals {
+        write_str(name, out);
+    }
+}
+
+fn write_u32(value: usize, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_str(text: &str, out: &mut Vec<u8>) {
+    write_u32(text.len(), out);
+    out.extend_from_slice(text.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < count {
+            return Err("unexpected end of file".to_string());
+        }
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()?;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| "string constant is not valid UTF-8".to_string())
+    }
+
+    fn function(&mut self) -> Result<Rc<Function>, String> {
+        let mut function = Function::new(self.string()?, self.u32()?);
+        function.upvalue_count = self.u32()?;
+        let length = self.u32()?;
+        let mut chunk = Chunk::new();
+        chunk.code = self.take(length)?.to_vec();
+        for _ in 0..length {
+            chunk.lines.push(self.u32()?);
+        }
+        for _ in 0..self.u32()? {
+            let constant = match self.take(1)?[0] {
+                NUMBER => Constant::Number(self.u64()? as i64 as isize),
+                STRING => Constant::String(self.string()?.into()),
+                NAME => Constant::Name(Symbol::intern(&self.string()?)),
+                FUNCTION => Constant::Function(self.function()?),
+                tag => return Err(format!("unknown constant tag {}", tag)),
+            };
+            chunk.constants.push(constant);
+        }
+        function.chunk = chunk;
+        for _ in 0..self.u32()? {
+            function.globals.push(self.string()?.into());
+        }
+        Ok(Rc::new(function))
+    }
+}
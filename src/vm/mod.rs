@@ -0,0 +1,7 @@
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
+pub mod loxc;
+pub mod vm;
+#[cfg(test)]
+pub mod vm_tests;
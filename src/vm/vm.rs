@@ -0,0 +1,645 @@
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::env::environment::Environment;
+use crate::expressions::expression::{ExpressionRes, ExprResType};
+use crate::interner::Symbol;
+use crate::program::errors::{self, RuntimeError};
+use crate::program::execution::{ExecutionState, Frame, Limits};
+use crate::program::heap::{self, Heap};
+use crate::program::natives::{self, Host};
+use crate::program::operations;
+use crate::program::program::builtins;
+use crate::program::runtime::{Class, Closure, Map, MapKey, Upvalue};
+use crate::statements::statement::Statement;
+use crate::token::TokenType;
+use crate::vm::chunk::{Constant, Function, OpCode};
+use crate::vm::compiler::Compiler;
+
+// A call in progress, its locals start at `slots` on the value stack.
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    slots: usize,
+}
+
+// An active try block: the call it belongs to, the stack height to go back to and where its handler starts.
+struct Handler {
+    depth: usize,
+    height: usize,
+    target: usize,
+}
+
+/// Stack based virtual machine running the bytecode of the compiler.
+///
+/// It shares values, natives and globals with the tree-walker and runs under the same execution state,
+/// except that fuel is charged for every loop iteration and call instead of every statement.
+pub struct Vm {
+    stack: Vec<ExpressionRes>,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    // upvalues still pointing into the stack
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // values of the globals by the index the compiler gave their names, None until they are defined
+    globals: Vec<Option<ExpressionRes>>,
+    global_names: Vec<Rc<str>>,
+    state: Rc<RefCell<ExecutionState>>,
+    line: usize,
+    // bytes the values on the stack add to the heap usage
+    accounted: usize,
+    heap: Rc<Heap>,
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let state = ExecutionState::new();
+        let heap = state.heap.clone();
+        let _entered = heap::enter(&heap);
+        let (global_names, globals) = builtins().into_iter().map(|(name, value)| (name.into(), Some(value))).unzip();
+        Vm {
+            stack: vec![],
+            frames: vec![],
+            handlers: vec![],
+            open_upvalues: vec![],
+            globals,
+            global_names,
+            state: Rc::new(RefCell::new(state)),
+            line: usize::MAX,
+            accounted: 0,
+            heap,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.state.borrow().depth()
+    }
+
+    pub fn global(&self, name: &str) -> Option<ExpressionRes> {
+        let index = self.global_names.iter().position(|known| &**known == name)?;
+        self.globals[index].clone()
+    }
+
+    /// Runs the program, an uncaught Lox error panics with its message and stack trace.
+    pub fn interpret(&mut self, program: Vec<Box<Statement>>) -> ExpressionRes {
+        match self.run(program) {
+            Ok(result) => result,
+            Err(error) => { panic!("{}", error.report()) }
+        }
+    }
+
+    /// Compiles and runs the program, handing an uncaught Lox error back to the host.
+    /// The result is the value the script returned, nil when it did not return.
+    pub fn run(&mut self, program: Vec<Box<Statement>>) -> Result<ExpressionRes, RuntimeError> {
+        let compiler = Compiler::with_globals(self.global_names.clone());
+        let script = compiler.compile(&program).map_err(errors::from_syntax)?;
+        self.run_function(script)
+    }
+
+    /// Runs a compiled script, its globals have to extend the ones of this machine.
+    pub fn run_function(&mut self, script: Rc<Function>) -> Result<ExpressionRes, RuntimeError> {
+        if !script.globals.starts_with(&self.global_names) {
+            return Err(errors::error("The script was compiled for other globals than this machine has".to_string()));
+        }
+        for name in &script.globals[self.global_names.len()..] {
+            self.global_names.push(name.clone());
+            self.globals.push(None);
+        }
+        let _entered = heap::enter(&self.heap);
+        let closure = Rc::new(Closure { function: script, upvalues: vec![], receiver: None });
+        let depth = self.frames.len();
+        let slots = self.stack.len();
+        self.push(ExpressionRes::from_none());
+        self.frames.push(CallFrame { closure, ip: 0, slots });
+        self.resume(depth)
+    }
+
+    // runs until the call stack is back at `depth`, handing errors to the try blocks above it
+    fn resume(&mut self, depth: usize) -> Result<ExpressionRes, RuntimeError> {
+        loop {
+            match self.execute(depth) {
+                Ok(result) => return Ok(result),
+                Err(error) => self.recover(error, depth)?,
+            }
+        }
+    }
+
+    fn recover(&mut self, mut error: RuntimeError, depth: usize) -> Result<(), RuntimeError> {
+        if error.trace.is_empty() {
+            error.trace = self.state.borrow().traceback(error.line);
+        }
+        let catchable = error.kind.is_catchable() && self.handlers.last().map_or(false, |handler| handler.depth > depth);
+        if !catchable {
+            // leave the machine as it was before this run
+            let slots = self.frames[depth].slots;
+            self.close_upvalues(slots);
+            self.truncate(slots);
+            self.frames.truncate(depth);
+            self.handlers.retain(|handler| handler.depth <= depth);
+            self.state.borrow_mut().unwind_to(depth.saturating_sub(1));
+            return Err(error);
+        }
+        let handler = self.handlers.pop().unwrap();
+        self.frames.truncate(handler.depth);
+        self.state.borrow_mut().unwind_to(handler.depth - 1);
+        self.close_upvalues(handler.height);
+        self.truncate(handler.height);
+        self.push(error.value);
+        self.frames.last_mut().unwrap().ip = handler.target;
+        Ok(())
+    }
+
+    fn execute(&mut self, depth: usize) -> Result<ExpressionRes, RuntimeError> {
+        loop {
+            match self.read_op() {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Number(number) => ExpressionRes::from_number(*number),
+                        Constant::String(text) => ExpressionRes::from_str(text.clone()),
+                        Constant::Name(name) => { panic!("name {} loaded as a value", name) }
+                        Constant::Function(function) => { panic!("function {} loaded without a closure", function.name) }
+                    };
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(ExpressionRes::from_none()),
+                OpCode::True => self.push(ExpressionRes::from_bool(true)),
+                OpCode::False => self.push(ExpressionRes::from_bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::Dup => self.push(self.peek(0).clone()),
+                OpCode::Dup2 => {
+                    self.push(self.peek(1).clone());
+                    self.push(self.peek(1).clone());
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_u16() as usize;
+                    self.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_u16() as usize;
+                    self.set_slot(slot, self.peek(0).clone());
+                }
+                OpCode::GetGlobal => {
+                    let index = self.read_u16() as usize;
+                    match &self.globals[index] {
+                        Some(value) => self.push(value.clone()),
+                        None => return Err(self.undefined(index)),
+                    }
+                }
+                OpCode::SetGlobal => {
+                    let index = self.read_u16() as usize;
+                    if self.globals[index].is_none() {
+                        return Err(self.undefined(index));
+                    }
+                    self.set_global(index, self.peek(0).clone());
+                }
+                OpCode::DefineGlobal => {
+                    let index = self.read_u16() as usize;
+                    let value = self.pop();
+                    self.set_global(index, value);
+                }
+                OpCode::GetUpvalue => {
+                    let upvalue = self.read_upvalue();
+                    let value = match upvalue.borrow().deref() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let upvalue = self.read_upvalue();
+                    let value = self.peek(0).clone();
+                    let open = match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => Some(*slot),
+                        Upvalue::Closed(closed) => {
+                            *closed = value.clone();
+                            None
+                        }
+                    };
+                    if let Some(slot) = open {
+                        self.set_slot(slot, value);
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.pop();
+                    let property = operations::get_property(&object, name)?;
+                    self.push(property);
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    let object = self.pop();
+                    operations::set_property(&object, name, value.clone())?;
+                    self.push(value);
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.push(natives::get_index(&object, &index)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    natives::set_index(&object, &index, value.clone())?;
+                    self.push(value);
+                }
+                op @ (OpCode::Equal | OpCode::NotEqual | OpCode::Greater | OpCode::GreaterEqual | OpCode::Less
+                | OpCode::LessEqual | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide | OpCode::Modulo) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let (operator, lexeme) = operator(op);
+                    let result = operations::binary(self, operator, lexeme, lhs, rhs)?;
+                    self.push(result);
+                }
+                op @ (OpCode::And | OpCode::Or) => {
+                    let offset = self.read_u16() as usize;
+                    let (operator, lexeme) = if op == OpCode::And { (TokenType::And, "and") } else { (TokenType::Or, "or") };
+                    if operations::short_circuits(operator, lexeme, self.peek(0))? {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    let result = operations::unary(self, TokenType::Bang, "!", value)?;
+                    self.push(result);
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    let result = operations::unary(self, TokenType::Minus, "-", value)?;
+                    self.push(result);
+                }
+                op @ (OpCode::Increment | OpCode::Decrement) => {
+                    let value = self.pop();
+                    let (lexeme, offset) = if op == OpCode::Increment { ("++", 1) } else { ("--", -1) };
+                    let result = operations::step(&value, lexeme, offset)?;
+                    self.push(result);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    println!("{}", operations::stringify(self, &value)?);
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !operations::condition(self.peek(0))? {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                    self.state.borrow_mut().charge()?;
+                }
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call_value(count)?;
+                }
+                OpCode::Closure => {
+                    let function = match self.read_constant() {
+                        Constant::Function(function) => function.clone(),
+                        other => { panic!("closure over {:?}, which is not a function", other) }
+                    };
+                    let mut upvalues = vec![];
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_u16() as usize;
+                        if is_local {
+                            upvalues.push(self.capture_upvalue(self.frame().slots + index));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[index].clone());
+                        }
+                    }
+                    self.push(ExpressionRes::from_closure(Closure { function, upvalues, receiver: None }));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.truncate(frame.slots);
+                    let frames = self.frames.len();
+                    self.handlers.retain(|handler| handler.depth <= frames);
+                    self.state.borrow_mut().unwind_to(frames.saturating_sub(1));
+                    if frames == depth {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_name();
+                    let count = self.read_u16() as usize;
+                    let closures = self.pop_many(count).into_iter()
+                        .map(|method| method.closure.expect("class methods are closures"))
+                        .collect();
+                    self.push(ExpressionRes::from_class(Class::new_compiled(name.to_string(), closures)));
+                }
+                OpCode::List => {
+                    let count = self.read_u16() as usize;
+                    let items = self.pop_many(count);
+                    self.push(ExpressionRes::from_list(items));
+                }
+                OpCode::Map => {
+                    let count = self.read_u16() as usize;
+                    let values = self.pop_many(2 * count);
+                    let mut map = Map::new();
+                    for entry in values.chunks(2) {
+                        map.insert(MapKey::from_res(&entry[0])?, entry[1].clone());
+                    }
+                    self.push(ExpressionRes::from_map(map));
+                }
+                OpCode::Interpolate => {
+                    let count = self.read_u16() as usize;
+                    let mut text = String::new();
+                    for part in self.pop_many(count) {
+                        text.push_str(&operations::stringify(self, &part)?);
+                    }
+                    self.push(ExpressionRes::from_str(text));
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    errors::mark_error_line(&value);
+                    return Err(errors::thrown(value));
+                }
+                OpCode::PushHandler => {
+                    let offset = self.read_u16() as usize;
+                    let target = self.frame().ip + offset;
+                    self.handlers.push(Handler { depth: self.frames.len(), height: self.stack.len(), target });
+                }
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+                OpCode::IterPrepare => {
+                    let iterable = self.pop();
+                    let iterator = operations::iterator(self, iterable)?;
+                    self.push(iterator);
+                }
+                OpCode::IterHasNext => {
+                    let slot = self.frame().slots + self.read_u16() as usize;
+                    let iterator = self.stack[slot].clone();
+                    // lists are walked by the position in the slot after them
+                    let has_next = match &iterator.list {
+                        Some(items) => (self.stack[slot + 1].number as usize) < items.borrow().len(),
+                        None => operations::has_next(self, &iterator)?,
+                    };
+                    self.push(ExpressionRes::from_bool(has_next));
+                }
+                OpCode::IterNext => {
+                    let slot = self.frame().slots + self.read_u16() as usize;
+                    let iterator = self.stack[slot].clone();
+                    let item = match &iterator.list {
+                        Some(items) => {
+                            let position = self.stack[slot + 1].number;
+                            self.set_slot(slot + 1, ExpressionRes::from_number(position + 1));
+                            let item = items.borrow()[position as usize].clone();
+                            item
+                        }
+                        None => operations::next(self, &iterator)?,
+                    };
+                    self.push(item);
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, count: usize) -> Result<(), RuntimeError> {
+        let callee = self.peek(count).clone();
+        match callee.type_ {
+            ExprResType::Function if callee.closure.is_some() => self.call_closure(callee, count),
+            ExprResType::Native => {
+                let arguments = self.pop_many(count);
+                self.pop();
+                let result = callee.native.as_ref().unwrap().call(self, arguments)?;
+                self.push(result);
+                Ok(())
+            }
+            ExprResType::Class => {
+                let arguments = self.pop_many(count);
+                self.pop();
+                let instance = Class::construct(callee.class.clone().unwrap(), arguments)?;
+                self.push(ExpressionRes::from_instance(instance));
+                Ok(())
+            }
+            _ => Err(errors::error("please call () is only usable on functions or classes".to_string())),
+        }
+    }
+
+    fn call_closure(&mut self, callee: ExpressionRes, count: usize) -> Result<(), RuntimeError> {
+        let closure = callee.closure.clone().unwrap();
+        let function = &closure.function;
+        errors::expect_arity(&function.name, function.arity, count)?;
+        self.state.borrow_mut().charge()?;
+        self.state.borrow_mut().push_frame(Frame {
+            name: function.name.clone(),
+            line: errors::current_line(),
+            callee,
+        })?;
+        let slots = self.stack.len() - count - 1;
+        if let Some(receiver) = &closure.receiver {
+            self.set_slot(slots, receiver.clone());
+        }
+        self.frames.push(CallFrame { closure, ip: 0, slots });
+        Ok(())
+    }
+
+    /// Calls a function from Rust, running it to completion before returning its result.
+    fn call_function(&mut self, callee: ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+        let depth = self.frames.len();
+        let count = arguments.len();
+        self.push(callee);
+        for argument in arguments {
+            self.push(argument);
+        }
+        self.call_value(count)?;
+        if self.frames.len() == depth {
+            return Ok(self.pop());
+        }
+        self.resume(depth)
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self.open_upvalues.iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // moves the variables from `slot` up off the stack into the closures that captured them
+    fn close_upvalues(&mut self, slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let open = match *upvalue.borrow() {
+                Upvalue::Open(open) => open,
+                Upvalue::Closed(_) => return false,
+            };
+            if open < slot {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[open].clone());
+            false
+        });
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_op(&mut self) -> OpCode {
+        let frame = self.frames.last_mut().unwrap();
+        let chunk = &frame.closure.function.chunk;
+        let line = chunk.lines[frame.ip];
+        let byte = chunk.code[frame.ip];
+        frame.ip += 1;
+        if line != self.line {
+            self.line = line;
+            errors::set_line(line);
+        }
+        match OpCode::from_byte(byte) {
+            Some(op) => op,
+            None => { panic!("Unknown opcode {}", byte) }
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frames.last_mut().unwrap();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> &Constant {
+        let index = self.read_u16() as usize;
+        &self.frame().closure.function.chunk.constants[index]
+    }
+
+    fn undefined(&self, global: usize) -> RuntimeError {
+        errors::error(format!("Undefined variable '{}'", self.global_names[global]))
+    }
+
+    fn read_name(&mut self) -> Symbol {
+        match self.read_constant() {
+            Constant::Name(name) => *name,
+            other => { panic!("expected a name constant, found {:?}", other) }
+        }
+    }
+
+    fn read_upvalue(&mut self) -> Rc<RefCell<Upvalue>> {
+        let index = self.read_u16() as usize;
+        self.frame().closure.upvalues[index].clone()
+    }
+
+    fn peek(&self, distance: usize) -> &ExpressionRes {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn push(&mut self, value: ExpressionRes) {
+        let size = heap::value_size(&value);
+        self.heap.allocate(size);
+        self.accounted += size;
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> ExpressionRes {
+        let value = self.stack.pop().unwrap();
+        self.release(&value);
+        value
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<ExpressionRes> {
+        let values = self.stack.split_off(self.stack.len() - count);
+        values.iter().for_each(|value| self.release(value));
+        values
+    }
+
+    fn truncate(&mut self, height: usize) {
+        while self.stack.len() > height {
+            self.pop();
+        }
+    }
+
+    fn set_slot(&mut self, slot: usize, value: ExpressionRes) {
+        let size = heap::value_size(&value);
+        self.heap.allocate(size);
+        self.accounted += size;
+        let previous = std::mem::replace(&mut self.stack[slot], value);
+        self.release(&previous);
+    }
+
+    // globals are accounted like the stack
+    fn set_global(&mut self, index: usize, value: ExpressionRes) {
+        let size = heap::value_size(&value);
+        self.heap.allocate(size);
+        self.accounted += size;
+        if let Some(previous) = self.globals[index].replace(value) {
+            self.release(&previous);
+        }
+    }
+
+    fn release(&mut self, value: &ExpressionRes) {
+        let size = heap::value_size(value).min(self.accounted);
+        self.heap.release(size);
+        self.accounted -= size;
+    }
+}
+
+impl Limits for Vm {
+    fn state(&self) -> &Rc<RefCell<ExecutionState>> {
+        &self.state
+    }
+}
+
+impl Host for Vm {
+    fn call(&mut self, callee: ExpressionRes, arguments: Vec<ExpressionRes>) -> Result<ExpressionRes, RuntimeError> {
+        self.call_function(callee, arguments)
+    }
+
+    fn memory_limit(&self) -> Option<usize> {
+        self.state.borrow().memory_limit
+    }
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        self.heap.release(self.accounted);
+    }
+}
+
+fn operator(op: OpCode) -> (TokenType, &'static str) {
+    match op {
+        OpCode::Equal => (TokenType::EqualEqual, "=="),
+        OpCode::NotEqual => (TokenType::BangEqual, "!="),
+        OpCode::Greater => (TokenType::Greater, ">"),
+        OpCode::GreaterEqual => (TokenType::GreaterEqual, ">="),
+        OpCode::Less => (TokenType::Less, "<"),
+        OpCode::LessEqual => (TokenType::LessEqual, "<="),
+        OpCode::Add => (TokenType::Plus, "+"),
+        OpCode::Subtract => (TokenType::Minus, "-"),
+        OpCode::Multiply => (TokenType::Star, "*"),
+        OpCode::Divide => (TokenType::Slash, "/"),
+        _ => (TokenType::Percent, "%"),
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Instant;
+
+use crate::program::errors::{ErrorKind, RuntimeError};
+use crate::program::execution::Limits;
+use crate::program::heap;
+use crate::program::runtime::MapKey;
+use crate::statements::statement::Statement;
+use crate::vm::chunk::{OpCode, OPCODES};
+use crate::vm::vm::Vm;
+
+fn parse(program: &str) -> Vec<Box<Statement>> {
+    let tokens = crate::token::Scanner::new().tokenize_string(program.to_string());
+    crate::parser::Parser::new(tokens).program()
+}
+
+fn global(vm: &Vm, name: &str) -> String {
+    vm.global(name).unwrap_or_else(|| panic!("no global {}", name)).print()
+}
+
+fn run_error(vm: &mut Vm, program: &str) -> RuntimeError {
+    match vm.run(parse(program)) {
+        Ok(_) => panic!("expected an uncaught error"),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn test_opcodes_match_their_bytes() {
+    for (byte, op) in OPCODES.iter().enumerate() {
+        assert_eq!(*op as u8 as usize, byte);
+        assert_eq!(OpCode::from_byte(byte as u8), Some(*op));
+    }
+    assert_eq!(OpCode::from_byte(OPCODES.len() as u8), None);
+}
+
+#[test]
+fn test_uncaught_error_unwinds_the_machine() {
+    let mut vm = Vm::new();
+    let error = run_error(&mut vm, "fun inner() { return nil + 1; }
+    fun outer() { return inner(); }
+    outer();");
+    assert_eq!(error.trace, vec!["[line 1] in inner()", "[line 2] in outer()", "[line 3] in script"]);
+    assert_eq!(vm.depth(), 0);
+    vm.interpret(parse("var after = outer;"));
+    assert_eq!(global(&vm, "after"), "<fn outer>");
+}
+
+#[test]
+fn test_stack_overflow_is_catchable() {
+    let mut vm = Vm::new();
+    vm.set_max_depth(8);
+    vm.interpret(parse(r#"fun forever(n) {
+        return forever(n + 1);
+    }
+    var message;
+    try {
+        forever(0);
+    } catch (e) {
+        message = e.message;
+    }
+    fun twice() { return 2; }
+    var after = twice();"#));
+    assert_eq!(global(&vm, "message"), "Stack overflow, calling forever would nest more than 8 calls");
+    assert_eq!(global(&vm, "after"), "2");
+    assert_eq!(vm.depth(), 0);
+}
+
+#[test]
+fn test_traceback_elides_deep_stacks() {
+    let error = run_error(&mut Vm::new(), "fun down(n) {
+        if (n == 0) { return nil + 1; }
+        return down(n - 1);
+    }
+    down(25);");
+    assert_eq!(error.trace.len(), 21);
+    assert_eq!(error.trace[0], "[line 2] in down()");
+    assert_eq!(error.trace[1], "[line 3] in down()");
+    assert_eq!(error.trace[10], "... 7 more calls");
+    assert_eq!(error.trace[20], "[line 5] in script");
+}
+
+#[test]
+fn test_fuel_budget_stops_infinite_loop() {
+    let mut vm = Vm::new();
+    vm.set_fuel(1000);
+    let error = run_error(&mut vm, "var caught = false;
+    try {
+        while (true) {}
+    } catch (e) {
+        caught = true;
+    }");
+    assert_eq!(error.kind, ErrorKind::OutOfFuel);
+    assert_eq!(global(&vm, "caught"), "false");
+}
+
+#[test]
+fn test_deadline_stops_execution() {
+    let mut vm = Vm::new();
+    vm.set_deadline(Instant::now() + std::time::Duration::from_millis(20));
+    let error = run_error(&mut vm, "while (true) {}");
+    assert_eq!(error.kind, ErrorKind::Timeout);
+}
+
+#[test]
+fn test_memory_limit_raises_out_of_memory() {
+    let mut vm = Vm::new();
+    vm.set_memory_limit(vm.heap_used() + 1024 * 1024);
+    vm.interpret(parse(r#"var message;
+    try {
+        var s = "ab";
+        while (true) { s = s + s; }
+    } catch (e) {
+        message = e.message;
+    }
+    var big;
+    try {
+        big = range(100000000);
+    } catch (e) {
+        big = "refused";
+    }"#));
+    assert!(global(&vm, "message").starts_with("Out of memory"));
+    assert_eq!(global(&vm, "big"), "refused");
+}
+
+#[test]
+fn test_gc_collects_cycles() {
+    let mut vm = Vm::new();
+    vm.interpret(parse("class Node {
+        link(other) { this.other = other; }
+    }
+    fun make() {
+        var a = Node();
+        var b = Node();
+        a.link(b);
+        b.link(a);
+        var xs = [];
+        xs.push(xs);
+        fun recurse() { return recurse; }
+        var bound = a.link;
+    }
+    for (var i = 0; i < 5; i++) make();
+    var first = gc();
+    var second = gc();
+    var keep = Node();
+    keep.self = keep;
+    fun add(a, b) { return a + b; }
+    var third = gc();
+    var still_works = [keep.self == keep, add(1, 2)];"));
+    let collected = |name: &str| {
+        let stats = vm.global(name).unwrap();
+        let key = MapKey::String("collected".into());
+        let number = stats.map.as_ref().unwrap().borrow().get(&key).unwrap().number;
+        number
+    };
+    assert!(collected("first") > 0);
+    assert_eq!(collected("second"), 0);
+    assert_eq!(collected("third"), 0);
+    assert_eq!(global(&vm, "still_works"), "[true, 3]");
+}
+
+#[test]
+fn test_disassemble() {
+    let script = crate::vm::compiler::Compiler::new().compile(&parse("var x = 1;
+    fun get() {
+        return x;
+    }
+    print get();")).unwrap();
+    assert_eq!(crate::vm::disassembler::disassemble(&script), "== script (0 params, 0 upvalues) ==
+0000    1 Constant            0 1
+0003    | DefineGlobal        4 x
+0006    2 Closure             1 <fn get>
+0009    | DefineGlobal        5 get
+0012    5 GetGlobal           5 get
+0015    | Call                0
+0017    | Print
+0018    | Nil
+0019    | Return
+constants:
+     0 1
+     1 <fn get>
+
+== get (0 params, 0 upvalues) ==
+0000    3 GetGlobal           4 x
+0003    | Return
+0004    | Nil
+0005    | Return
+");
+}
+
+#[test]
+fn test_loxc_round_trip() {
+    use crate::vm::loxc;
+    let source = "var greeting = \"hi\";
+    fun twice(n) { return n * 2; }
+    class Pair { first() { return this.value; } }
+    var pair = Pair();
+    pair.value = 7;
+    var numbers = [twice(-21), greeting, pair.first()];";
+    let script = crate::vm::compiler::Compiler::new().compile(&parse(source)).unwrap();
+    let bytes = loxc::serialize(&script, loxc::source_hash(source));
+    let (loaded, hash) = loxc::deserialize(&bytes).unwrap();
+    assert_eq!(hash, loxc::source_hash(source));
+    assert_eq!(crate::vm::disassembler::disassemble(&loaded), crate::vm::disassembler::disassemble(&script));
+    let mut vm = Vm::new();
+    assert!(vm.run_function(loaded).is_ok());
+    assert_eq!(global(&vm, "numbers"), "[-42, hi, 7]");
+
+    let path = std::env::temp_dir().join(format!("vm_tests_{}.loxc", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(loxc::load(&path, Some(source)).is_ok());
+    assert!(loxc::load(&path, None).is_ok());
+    assert_eq!(loxc::load(&path, Some("print 1;")).err().unwrap(), "the source changed since it was compiled");
+    std::fs::remove_file(&path).unwrap();
+
+    let mut outdated = bytes.clone();
+    outdated[4] = 0;
+    let error = loxc::deserialize(&outdated).err().unwrap();
+    assert_eq!(error, format!("compiled with format version 0, expected {}", loxc::FORMAT_VERSION));
+    assert_eq!(loxc::deserialize(&bytes[..bytes.len() - 1]).err().unwrap(), "unexpected end of file");
+
+    let foreign = crate::vm::compiler::Compiler::with_globals(vec![]).compile(&parse(source)).unwrap();
+    let error = Vm::new().run_function(foreign).err().unwrap();
+    assert_eq!(error.message(), "The script was compiled for other globals than this machine has");
+}